@@ -0,0 +1,135 @@
+//! Emission time and output size of representative charts, Typst vs SVG.
+//!
+//! Run with `cargo bench`; output sizes are printed once per chart.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use plotters_typst::TypstBackend;
+
+/// A chart drawn identically on every backend.
+trait Scene {
+    const NAME: &'static str;
+
+    fn draw<DB: DrawingBackend>(root: &DrawingArea<DB, Shift>);
+}
+
+struct Line100k;
+struct DenseScatter;
+struct Heatmap;
+
+impl Scene for Line100k {
+    const NAME: &'static str = "line_100k";
+
+    fn draw<DB: DrawingBackend>(root: &DrawingArea<DB, Shift>) {
+        root.fill(&WHITE).unwrap();
+        let mut chart = ChartBuilder::on(root)
+            .margin(10)
+            .x_label_area_size(30)
+            .y_label_area_size(30)
+            .build_cartesian_2d(0f64..100f64, -1.5f64..1.5f64)
+            .unwrap();
+        chart.configure_mesh().draw().unwrap();
+        chart
+            .draw_series(LineSeries::new(
+                (0..100_000).map(|i| {
+                    let x = i as f64 / 1000.0;
+                    (x, x.sin() + (x * 37.0).sin() * 0.3)
+                }),
+                &BLUE,
+            ))
+            .unwrap();
+    }
+}
+
+impl Scene for DenseScatter {
+    const NAME: &'static str = "dense_scatter";
+
+    fn draw<DB: DrawingBackend>(root: &DrawingArea<DB, Shift>) {
+        root.fill(&WHITE).unwrap();
+        let mut chart = ChartBuilder::on(root)
+            .margin(10)
+            .x_label_area_size(30)
+            .y_label_area_size(30)
+            .build_cartesian_2d(0f64..1f64, 0f64..1f64)
+            .unwrap();
+        chart.configure_mesh().draw().unwrap();
+        chart
+            .draw_series((0..20_000u32).map(|i| {
+                // Points on a low-discrepancy sequence, dense but deterministic.
+                let x = (i as f64 * 0.618_033_988_75).fract();
+                let y = (i as f64 * 0.754_877_666_25).fract();
+                Circle::new((x, y), 2, RED.mix(0.5).filled())
+            }))
+            .unwrap();
+    }
+}
+
+impl Scene for Heatmap {
+    const NAME: &'static str = "heatmap";
+
+    fn draw<DB: DrawingBackend>(root: &DrawingArea<DB, Shift>) {
+        root.fill(&WHITE).unwrap();
+        let mut chart = ChartBuilder::on(root)
+            .margin(10)
+            .x_label_area_size(30)
+            .y_label_area_size(30)
+            .build_cartesian_2d(0..100, 0..100)
+            .unwrap();
+        chart.configure_mesh().disable_mesh().draw().unwrap();
+        chart
+            .draw_series(
+                (0..100)
+                    .flat_map(|x| (0..100).map(move |y| (x, y)))
+                    .map(|(x, y)| {
+                        let v = ((x as f64 / 10.0).sin() * (y as f64 / 10.0).cos() + 1.0) / 2.0;
+                        Rectangle::new([(x, y), (x + 1, y + 1)], ViridisRGB::get_color(v).filled())
+                    }),
+            )
+            .unwrap();
+    }
+}
+
+fn render_typst<S: Scene>() -> String {
+    let mut out = String::new();
+    {
+        let root = TypstBackend::with_string(&mut out, (800, 600)).into_drawing_area();
+        S::draw(&root);
+        root.present().unwrap();
+    }
+    out
+}
+
+fn render_svg<S: Scene>() -> String {
+    let mut out = String::new();
+    {
+        let root = SVGBackend::with_string(&mut out, (800, 600)).into_drawing_area();
+        S::draw(&root);
+        root.present().unwrap();
+    }
+    out
+}
+
+fn bench_scene<S: Scene>(c: &mut Criterion) {
+    let name = S::NAME;
+    let typst_size = render_typst::<S>().len();
+    let svg_size = render_svg::<S>().len();
+    println!("{name}: typst {typst_size} bytes, svg {svg_size} bytes");
+
+    let mut group = c.benchmark_group(name);
+    group.sample_size(20);
+    group.throughput(Throughput::Bytes(typst_size as u64));
+    group.bench_function("typst", |b| b.iter(|| black_box(render_typst::<S>())));
+    group.throughput(Throughput::Bytes(svg_size as u64));
+    group.bench_function("svg", |b| b.iter(|| black_box(render_svg::<S>())));
+    group.finish();
+}
+
+fn backends(c: &mut Criterion) {
+    bench_scene::<Line100k>(c);
+    bench_scene::<DenseScatter>(c);
+    bench_scene::<Heatmap>(c);
+}
+
+criterion_group!(benches, backends);
+criterion_main!(benches);
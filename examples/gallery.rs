@@ -0,0 +1,260 @@
+//! Renders the standard plotters demo charts to Typst files in `gallery/`.
+//!
+//! Each chart exercises a different backend path: rectangles (histogram),
+//! filled polygons and paths (area), rectangles plus lines (candlestick),
+//! projected polygons (3D surface) and single pixels (mandelbrot).
+
+use std::fs;
+
+use plotters_typst::prelude::*;
+
+const OUT_DIR: &str = "gallery";
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    fs::create_dir_all(OUT_DIR)?;
+
+    histogram(&format!("{OUT_DIR}/histogram.typ"))?;
+    area_chart(&format!("{OUT_DIR}/area-chart.typ"))?;
+    candlestick(&format!("{OUT_DIR}/candlestick.typ"))?;
+    surface_3d(&format!("{OUT_DIR}/3d-plot.typ"))?;
+    mandelbrot(&format!("{OUT_DIR}/mandelbrot.typ"))?;
+
+    println!("Gallery has been saved to {OUT_DIR}/");
+    Ok(())
+}
+
+/// Deterministic pseudo-random sequence in `0.0..1.0`, so the output is stable
+/// between runs without pulling in a random number generator.
+fn noise(count: usize, seed: u64) -> impl Iterator<Item = f64> {
+    let mut state = seed;
+    (0..count).map(move |_| {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (state >> 11) as f64 / (1u64 << 53) as f64
+    })
+}
+
+fn histogram(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let root = TypstBackend::new(path, (640, 480)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let mut chart = ChartBuilder::on(&root)
+        .x_label_area_size(35)
+        .y_label_area_size(40)
+        .margin(5)
+        .caption("Histogram Test", ("sans-serif", 30))
+        .build_cartesian_2d((0u32..10u32).into_segmented(), 0u32..30u32)?;
+
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .bold_line_style(WHITE.mix(0.3))
+        .y_desc("Count")
+        .x_desc("Bucket")
+        .axis_desc_style(("sans-serif", 15))
+        .draw()?;
+
+    let data = noise(150, 7).map(|v| (v * v * 10.0) as u32);
+
+    chart.draw_series(
+        Histogram::vertical(&chart)
+            .style(RED.mix(0.5).filled())
+            .data(data.map(|x| (x, 1))),
+    )?;
+
+    root.present()?;
+    Ok(())
+}
+
+fn area_chart(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let data: Vec<f64> = {
+        let mut last = 0.0;
+        noise(100, 42)
+            .map(|v| {
+                last = (last + v - 0.45).max(0.0);
+                last * 10.0
+            })
+            .collect()
+    };
+    let max = data.iter().cloned().fold(0.0, f64::max);
+
+    let root = TypstBackend::new(path, (640, 480)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let mut chart = ChartBuilder::on(&root)
+        .set_label_area_size(LabelAreaPosition::Left, 60)
+        .set_label_area_size(LabelAreaPosition::Bottom, 60)
+        .caption("Area Chart Demo", ("sans-serif", 30))
+        .build_cartesian_2d(0..(data.len() - 1), 0.0..max * 1.1)?;
+
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .disable_y_mesh()
+        .draw()?;
+
+    chart.draw_series(
+        AreaSeries::new(
+            (0..).zip(data.iter()).map(|(x, y)| (x, *y)),
+            0.0,
+            RED.mix(0.2),
+        )
+        .border_style(RED),
+    )?;
+
+    root.present()?;
+    Ok(())
+}
+
+fn candlestick(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let data: Vec<(u32, f32, f32, f32, f32)> = {
+        let mut close = 120.0f32;
+        let mut values = noise(120, 2019);
+        (0..30)
+            .map(|day| {
+                let mut next = || values.next().unwrap_or(0.5) as f32;
+                let open = close + (next() - 0.5) * 2.0;
+                close = open + (next() - 0.5) * 4.0;
+                let high = open.max(close) + next() * 1.5;
+                let low = open.min(close) - next() * 1.5;
+                (day, open, high, low, close)
+            })
+            .collect()
+    };
+    let low = data.iter().map(|d| d.3).fold(f32::MAX, f32::min);
+    let high = data.iter().map(|d| d.2).fold(f32::MIN, f32::max);
+
+    let root = TypstBackend::new(path, (1024, 768)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let mut chart = ChartBuilder::on(&root)
+        .x_label_area_size(40)
+        .y_label_area_size(40)
+        .caption("Stock Price", ("sans-serif", 50))
+        .build_cartesian_2d(0u32..30u32, (low - 1.0)..(high + 1.0))?;
+
+    chart
+        .configure_mesh()
+        .light_line_style(WHITE)
+        .x_desc("Trading day")
+        .draw()?;
+
+    chart.draw_series(data.iter().map(|&(day, open, high, low, close)| {
+        CandleStick::new(day, open, high, low, close, GREEN.filled(), RED, 15)
+    }))?;
+
+    root.present()?;
+    Ok(())
+}
+
+fn surface_3d(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let root = TypstBackend::new(path, (1024, 760)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let x_axis = (-3.0..3.0).step(0.1);
+    let z_axis = (-3.0..3.0).step(0.1);
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption("3D Plot Test", ("sans-serif", 20))
+        .build_cartesian_3d(x_axis, -3.0..3.0, z_axis)?;
+
+    chart.with_projection(|mut pb| {
+        pb.yaw = 0.5;
+        pb.scale = 0.9;
+        pb.into_matrix()
+    });
+
+    chart
+        .configure_axes()
+        .light_grid_style(BLACK.mix(0.15))
+        .max_light_lines(3)
+        .draw()?;
+
+    chart
+        .draw_series(
+            SurfaceSeries::xoz(
+                (-30..30).map(|f| f as f64 / 10.0),
+                (-30..30).map(|f| f as f64 / 10.0),
+                |x, z| (x * x + z * z).cos(),
+            )
+            .style(BLUE.mix(0.2).filled()),
+        )?
+        .label("Surface")
+        .legend(|(x, y)| Rectangle::new([(x + 5, y - 5), (x + 15, y + 5)], BLUE.mix(0.5).filled()));
+
+    chart
+        .draw_series(LineSeries::new(
+            (-100..100)
+                .map(|y| y as f64 / 40.0)
+                .map(|y| ((y * 10.0).sin(), y, (y * 10.0).cos())),
+            &BLACK,
+        ))?
+        .label("Line")
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], BLACK));
+
+    chart.configure_series_labels().border_style(BLACK).draw()?;
+
+    root.present()?;
+    Ok(())
+}
+
+fn mandelbrot(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    // Every pixel becomes its own `#place`, so keep the canvas small.
+    let root = TypstBackend::new(path, (240, 200)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let mut chart = ChartBuilder::on(&root)
+        .margin(10)
+        .x_label_area_size(20)
+        .y_label_area_size(30)
+        .build_cartesian_2d(-2.1f64..0.6f64, -1.2f64..1.2f64)?;
+
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .disable_y_mesh()
+        .label_style(("sans-serif", 8))
+        .draw()?;
+
+    let plotting_area = chart.plotting_area();
+    let range = plotting_area.get_pixel_range();
+    let (pw, ph) = (range.0.end - range.0.start, range.1.end - range.1.start);
+    let (xr, yr) = (chart.x_range(), chart.y_range());
+
+    for (x, y, c) in mandelbrot_set(xr, yr, (pw as usize, ph as usize), 100) {
+        if c != 100 {
+            plotting_area.draw_pixel((x, y), &MandelbrotHSL::get_color(c as f64 / 100.0))?;
+        } else {
+            plotting_area.draw_pixel((x, y), &BLACK)?;
+        }
+    }
+
+    root.present()?;
+    Ok(())
+}
+
+fn mandelbrot_set(
+    real: std::ops::Range<f64>,
+    complex: std::ops::Range<f64>,
+    samples: (usize, usize),
+    max_iter: usize,
+) -> impl Iterator<Item = (f64, f64, usize)> {
+    let step = (
+        (real.end - real.start) / samples.0 as f64,
+        (complex.end - complex.start) / samples.1 as f64,
+    );
+    (0..(samples.0 * samples.1)).map(move |k| {
+        let c = (
+            real.start + step.0 * (k % samples.0) as f64,
+            complex.start + step.1 * (k / samples.0) as f64,
+        );
+        let mut z = (0.0, 0.0);
+        let mut cnt = 0;
+        while cnt < max_iter && z.0 * z.0 + z.1 * z.1 <= 1e10 {
+            z = (z.0 * z.0 - z.1 * z.1 + c.0, 2.0 * z.0 * z.1 + c.1);
+            cnt += 1;
+        }
+        (c.0, c.1, cnt)
+    })
+}
@@ -0,0 +1,309 @@
+//! Command line tool that turns a CSV file into a Typst chart.
+//!
+//! ```text
+//! plotters-typst data.csv -o chart.typ --x time --y temp,humidity --kind line --size 640x400
+//! plotters-typst data.csv -o - | typst compile - chart.pdf
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use plotters::prelude::*;
+use plotters_typst::TypstBackend;
+
+const USAGE: &str = "\
+Usage: plotters-typst <INPUT.csv> [options]
+
+Options:
+  -o, --output <FILE>    Output .typ file, `-` for the standard output
+                         (default: input with .typ extension)
+      --x <COLUMN>       Column used for the x axis (default: first column)
+      --y <COLUMNS>      Comma separated columns to plot (default: all others)
+      --kind <KIND>      line, scatter, area or bar (default: line)
+      --size <WxH>       Canvas size in points (default: 640x480)
+      --title <TEXT>     Chart caption
+      --no-header        The first CSV row is data, columns are 0-based indices
+  -h, --help             Print this help";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Kind {
+    Line,
+    Scatter,
+    Area,
+    Bar,
+}
+
+#[derive(Debug)]
+struct Spec {
+    input: PathBuf,
+    output: PathBuf,
+    x: Option<String>,
+    y: Vec<String>,
+    kind: Kind,
+    size: (u32, u32),
+    title: Option<String>,
+    header: bool,
+}
+
+#[derive(Debug)]
+struct CliError(String);
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for CliError {}
+
+fn fail<T>(msg: impl Into<String>) -> Result<T, Box<dyn Error>> {
+    Err(Box::new(CliError(msg.into())))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Spec>, Box<dyn Error>> {
+    let mut input = None;
+    let mut output = None;
+    let mut x = None;
+    let mut y = Vec::new();
+    let mut kind = Kind::Line;
+    let mut size = (640, 480);
+    let mut title = None;
+    let mut header = true;
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| match args.next() {
+            Some(v) => Ok(v),
+            None => fail(format!("missing value for {name}")),
+        };
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => output = Some(PathBuf::from(value(&arg)?)),
+            "--x" => x = Some(value(&arg)?),
+            "--y" => {
+                y = value(&arg)?
+                    .split(',')
+                    .map(|s| s.trim().to_string())
+                    .collect()
+            }
+            "--kind" => {
+                kind = match value(&arg)?.as_str() {
+                    "line" => Kind::Line,
+                    "scatter" => Kind::Scatter,
+                    "area" => Kind::Area,
+                    "bar" => Kind::Bar,
+                    other => return fail(format!("unknown chart kind `{other}`")),
+                }
+            }
+            "--size" => {
+                let v = value(&arg)?;
+                size = v
+                    .split_once('x')
+                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                    .map_or_else(|| fail(format!("invalid size `{v}`, expected WxH")), Ok)?;
+            }
+            "--title" => title = Some(value(&arg)?),
+            "--no-header" => header = false,
+            flag if flag.starts_with('-') => return fail(format!("unknown option `{flag}`")),
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => return fail(format!("unexpected argument `{arg}`")),
+        }
+    }
+
+    let Some(input) = input else {
+        return fail("no input file given");
+    };
+    let output = output.unwrap_or_else(|| input.with_extension("typ"));
+    Ok(Some(Spec {
+        input,
+        output,
+        x,
+        y,
+        kind,
+        size,
+        title,
+        header,
+    }))
+}
+
+/// A column of the CSV file, parsed as numbers.
+struct Column {
+    name: String,
+    values: Vec<f64>,
+}
+
+fn read_columns(spec: &Spec) -> Result<(Column, Vec<Column>), Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(spec.header)
+        .trim(csv::Trim::All)
+        .from_path(&spec.input)?;
+
+    let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+    let width = records.first().map_or(0, |r| r.len());
+    let names: Vec<String> = if spec.header {
+        reader.headers()?.iter().map(str::to_string).collect()
+    } else {
+        (0..width).map(|i| i.to_string()).collect()
+    };
+
+    let find = |name: &str| match names.iter().position(|n| n == name) {
+        Some(i) => Ok(i),
+        None => fail(format!("no column named `{name}`")),
+    };
+    let x = match &spec.x {
+        Some(name) => find(name)?,
+        None => 0,
+    };
+    let ys = if spec.y.is_empty() {
+        (0..names.len()).filter(|&i| i != x).collect()
+    } else {
+        spec.y
+            .iter()
+            .map(|n| find(n))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    if ys.is_empty() {
+        return fail("nothing to plot, the CSV needs at least two columns");
+    }
+
+    let column = |index: usize| -> Result<Column, Box<dyn Error>> {
+        let values = records
+            .iter()
+            .enumerate()
+            .map(|(row, record)| {
+                let cell = record.get(index).unwrap_or("");
+                cell.parse::<f64>().or_else(|_| {
+                    fail(format!(
+                        "column `{}`, row {}: `{cell}` is not a number",
+                        names[index],
+                        row + 1
+                    ))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Column {
+            name: names[index].clone(),
+            values,
+        })
+    };
+
+    Ok((
+        column(x)?,
+        ys.into_iter().map(column).collect::<Result<_, _>>()?,
+    ))
+}
+
+fn range(values: impl Iterator<Item = f64>, include_zero: bool) -> std::ops::Range<f64> {
+    let (mut lo, mut hi) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| {
+        (lo.min(v), hi.max(v))
+    });
+    if include_zero {
+        lo = lo.min(0.0);
+        hi = hi.max(0.0);
+    }
+    if !lo.is_finite() || !hi.is_finite() {
+        return 0.0..1.0;
+    }
+    if lo == hi {
+        return (lo - 1.0)..(hi + 1.0);
+    }
+    let pad = (hi - lo) * 0.05;
+    (lo - if include_zero && lo == 0.0 { 0.0 } else { pad })..(hi + pad)
+}
+
+fn render(spec: &Spec, x: &Column, ys: &[Column]) -> Result<(), Box<dyn Error>> {
+    let backend = if spec.output.as_os_str() == "-" {
+        TypstBackend::stdout(spec.size)
+    } else {
+        TypstBackend::new(&spec.output, spec.size)
+    };
+    let root = backend.into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let filled = matches!(spec.kind, Kind::Area | Kind::Bar);
+    let mut x_range = range(x.values.iter().copied(), false);
+
+    // Bars of all series share one slot around each x value.
+    let slot = x
+        .values
+        .windows(2)
+        .map(|w| (w[1] - w[0]).abs())
+        .filter(|d| *d > 0.0)
+        .fold(x_range.end - x_range.start, f64::min)
+        * 0.8;
+    let bar = slot / ys.len() as f64;
+    if spec.kind == Kind::Bar {
+        x_range = (x_range.start - slot / 2.0)..(x_range.end + slot / 2.0);
+    }
+
+    let y_range = range(ys.iter().flat_map(|c| c.values.iter().copied()), filled);
+
+    let mut builder = ChartBuilder::on(&root);
+    builder
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(50);
+    if let Some(title) = &spec.title {
+        builder.caption(title, ("sans-serif", 24));
+    }
+    let mut chart = builder.build_cartesian_2d(x_range, y_range)?;
+
+    chart.configure_mesh().x_desc(&x.name).draw()?;
+
+    for (i, column) in ys.iter().enumerate() {
+        let color = Palette99::pick(i).to_rgba();
+        let points = x.values.iter().copied().zip(column.values.iter().copied());
+        let series = match spec.kind {
+            Kind::Line => chart.draw_series(LineSeries::new(points, color.stroke_width(2)))?,
+            Kind::Scatter => {
+                chart.draw_series(points.map(|p| Circle::new(p, 3, color.filled())))?
+            }
+            Kind::Area => chart
+                .draw_series(AreaSeries::new(points, 0.0, color.mix(0.3)).border_style(color))?,
+            Kind::Bar => chart.draw_series(points.map(|(x, y)| {
+                let left = x - slot / 2.0 + bar * i as f64;
+                Rectangle::new([(left, 0.0), (left + bar, y)], color.filled())
+            }))?,
+        };
+        series
+            .label(&column.name)
+            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled()));
+    }
+
+    if ys.len() > 1 {
+        chart
+            .configure_series_labels()
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()?;
+    }
+
+    root.present()?;
+    Ok(())
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let Some(spec) = parse_args(std::env::args().skip(1))? else {
+        println!("{USAGE}");
+        return Ok(());
+    };
+    let (x, ys) = read_columns(&spec)?;
+    render(&spec, &x, &ys)?;
+    // On the standard output, the message would end up in the chart
+    if spec.output.as_os_str() != "-" {
+        println!("Chart has been saved to {}", spec.output.display());
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("plotters-typst: {e}");
+            eprintln!("Try `plotters-typst --help` for more information.");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,327 @@
+/*!
+Compiling generated charts with Typst
+
+Behind the `compile` feature, charts are compiled in-process with the `typst`
+crates, so a pipeline can ship the editable `.typ` source together with a
+ready-to-embed SVG or PNG without a Typst installation:
+
+```no_run
+use plotters::prelude::*;
+use plotters_typst::compile::ExportFormat;
+use plotters_typst::TypstBackend;
+
+let mut backend = TypstBackend::new_buffered((320, 200));
+backend.draw_rect((20, 20), (120, 80), &BLUE.filled(), true).unwrap();
+let files = backend
+    .export_all("target/chart", &[ExportFormat::Svg, ExportFormat::Png])
+    .unwrap();
+assert_eq!(files.len(), 3);
+```
+
+Sources are compiled against the fonts bundled in `typst-assets`, so the result
+does not depend on the fonts installed on the machine.
+*/
+
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use comemo::Prehashed;
+use typst::diag::{FileError, FileResult};
+use typst::foundations::{Bytes, Datetime};
+use typst::model::Document;
+use typst::syntax::{FileId, Source, VirtualPath};
+use typst::text::{Font, FontBook};
+use typst::visualize::Color;
+use typst::{Library, World};
+
+use crate::TypstBackend;
+
+/// Shrink the page to its content so a rendered chart has no surrounding margins
+const PAGE_SETUP: &str = "#set page(width: auto, height: auto, margin: 0pt)\n";
+
+/// Pixels per point of exported PNG images by default
+const PIXEL_PER_PT: f32 = 1.0;
+
+/// A file compiled next to the Typst source by [`TypstBackend::export_all`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A scalable vector image, `<base>.svg`
+    Svg,
+    /// A raster image on a white background, `<base>.png`
+    Png,
+}
+
+impl ExportFormat {
+    /// The file extension of the format
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Svg => "svg",
+            ExportFormat::Png => "png",
+        }
+    }
+}
+
+struct Fonts {
+    book: Prehashed<FontBook>,
+    fonts: Vec<Font>,
+}
+
+fn fonts() -> &'static Fonts {
+    static FONTS: OnceLock<Fonts> = OnceLock::new();
+    FONTS.get_or_init(|| {
+        let fonts: Vec<Font> = typst_assets::fonts()
+            .flat_map(|data| Font::iter(Bytes::from_static(data)))
+            .collect();
+        Fonts {
+            book: Prehashed::new(FontBook::from_fonts(&fonts)),
+            fonts,
+        }
+    })
+}
+
+/// A single-file world; other files are resolved relative to the working directory
+struct SourceWorld {
+    library: Prehashed<Library>,
+    main: Source,
+}
+
+impl SourceWorld {
+    fn new(text: &str) -> Self {
+        let id = FileId::new(None, VirtualPath::new("main.typ"));
+        Self {
+            library: Prehashed::new(Library::default()),
+            main: Source::new(id, format!("{}{}", PAGE_SETUP, text)),
+        }
+    }
+}
+
+impl World for SourceWorld {
+    fn library(&self) -> &Prehashed<Library> {
+        &self.library
+    }
+
+    fn book(&self) -> &Prehashed<FontBook> {
+        &fonts().book
+    }
+
+    fn main(&self) -> Source {
+        self.main.clone()
+    }
+
+    fn source(&self, id: FileId) -> FileResult<Source> {
+        if id == self.main.id() {
+            return Ok(self.main.clone());
+        }
+        let path = id.vpath().as_rootless_path();
+        let text = std::fs::read_to_string(path).map_err(|e| FileError::from_io(e, path))?;
+        Ok(Source::new(id, text))
+    }
+
+    fn file(&self, id: FileId) -> FileResult<Bytes> {
+        let path = id.vpath().as_rootless_path();
+        std::fs::read(path)
+            .map(Bytes::from)
+            .map_err(|e| FileError::from_io(e, path))
+    }
+
+    fn font(&self, index: usize) -> Option<Font> {
+        fonts().fonts.get(index).cloned()
+    }
+
+    fn today(&self, _offset: Option<i64>) -> Option<Datetime> {
+        None
+    }
+}
+
+/// Compile `source`, returning the error messages with the offending snippet on failure
+pub(crate) fn compile(source: &str) -> Result<Document, String> {
+    let world = SourceWorld::new(source);
+    let mut tracer = typst::eval::Tracer::new();
+    typst::compile(&world, &mut tracer).map_err(|errors| {
+        let mut message = String::new();
+        for error in errors.iter() {
+            let snippet = world
+                .main
+                .range(error.span)
+                .map(|r| world.main.text()[r].chars().take(80).collect::<String>())
+                .unwrap_or_default();
+            message.push_str(&format!("error: {} at `{}`\n", error.message, snippet));
+        }
+        message
+    })
+}
+
+/// Render the first page of `document` to an image
+#[cfg(feature = "test-util")]
+pub(crate) fn render(document: &Document) -> tiny_skia::Pixmap {
+    typst_render::render(&document.pages[0].frame, PIXEL_PER_PT, Color::WHITE)
+}
+
+impl TypstBackend<'_> {
+    /// Render PNG exports at `pixel_per_pt` pixels per point, e.g. 0.25 for a
+    /// thumbnail or 300.0 / 72.0 for a 300 DPI print raster; 1.0 by default
+    ///
+    /// # Panics
+    /// If `pixel_per_pt` is not a finite number greater than zero.
+    pub fn export_scale(mut self, pixel_per_pt: f32) -> Self {
+        assert!(
+            pixel_per_pt.is_finite() && pixel_per_pt > 0.0,
+            "export scale must be finite and positive, got {}",
+            pixel_per_pt
+        );
+        self.options.pixel_per_pt = Some(pixel_per_pt);
+        self
+    }
+
+    /// Export page `page`, counted from 0, of a document spanning several pages,
+    /// such as a standalone chart with a page header; the first one by default
+    pub fn export_page(mut self, page: usize) -> Self {
+        self.options.export_page = page;
+        self
+    }
+
+    /// Complete the chart, write it to `<base_path>.typ` and compile it into a
+    /// sibling file per format, e.g. `chart.typ`, `chart.svg` and `chart.png`
+    ///
+    /// Returns the paths written, the source first. Only one page of a document
+    /// spanning several pages is exported, see [`Self::export_page`]; the target of
+    /// the backend is left untouched, like with [`Self::finish`].
+    pub fn export_all<P: AsRef<Path>>(
+        self,
+        base_path: P,
+        formats: &[ExportFormat],
+    ) -> Result<Vec<PathBuf>, Error> {
+        let base_path = base_path.as_ref();
+        let pixel_per_pt = self.options.pixel_per_pt.unwrap_or(PIXEL_PER_PT);
+        let page = self.options.export_page;
+        let source = self.finish()?;
+        let source_path = base_path.with_extension("typ");
+        std::fs::write(&source_path, &source)?;
+        let mut paths = vec![source_path];
+        if formats.is_empty() {
+            return Ok(paths);
+        }
+
+        let document = compile(&source).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let Some(page) = document.pages.get(page) else {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "cannot export page {}, the document has {}",
+                    page,
+                    document.pages.len()
+                ),
+            ));
+        };
+        for &format in formats {
+            let path = base_path.with_extension(format.extension());
+            match format {
+                ExportFormat::Svg => std::fs::write(&path, typst_svg::svg(&page.frame))?,
+                ExportFormat::Png => {
+                    let png = typst_render::render(&page.frame, pixel_per_pt, Color::WHITE)
+                        .encode_png()
+                        .map_err(Error::other)?;
+                    std::fs::write(&path, png)?
+                }
+            }
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ExportFormat;
+    use crate::TypstBackend;
+    use plotters::prelude::*;
+    use std::fs;
+
+    const EXPORT_DIR: &str = "target/test/typst/export";
+
+    #[test]
+    fn test_export_all() {
+        fs::create_dir_all(EXPORT_DIR).unwrap();
+        let mut backend = TypstBackend::new_buffered((100, 60));
+        backend
+            .draw_rect((10, 10), (90, 50), &BLUE.filled(), true)
+            .unwrap();
+
+        let paths = backend
+            .export_all(
+                format!("{EXPORT_DIR}/chart"),
+                &[ExportFormat::Svg, ExportFormat::Png],
+            )
+            .unwrap();
+        let names: Vec<_> = paths
+            .iter()
+            .map(|p| p.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, ["chart.typ", "chart.svg", "chart.png"]);
+
+        assert!(fs::read_to_string(&paths[0])
+            .unwrap()
+            .starts_with("#box(width: 100pt, height: 60pt"));
+        assert!(fs::read_to_string(&paths[1]).unwrap().starts_with("<svg"));
+        let png = tiny_skia::Pixmap::load_png(&paths[2]).unwrap();
+        assert_eq!((png.width(), png.height()), (100, 60));
+    }
+
+    #[test]
+    fn test_export_scale_and_page() {
+        fs::create_dir_all(EXPORT_DIR).unwrap();
+        let export = |backend: TypstBackend| {
+            let mut backend = backend.standalone().page_header("Sales");
+            backend
+                .draw_rect((10, 10), (90, 50), &BLUE.filled(), true)
+                .unwrap();
+            backend.export_all(format!("{EXPORT_DIR}/scaled"), &[ExportFormat::Png])
+        };
+
+        let paths = export(TypstBackend::new_buffered((100, 60)).export_scale(2.5)).unwrap();
+        let png = tiny_skia::Pixmap::load_png(&paths[1]).unwrap();
+        assert!(png.width() >= 250 && png.height() >= 150);
+
+        let error = export(TypstBackend::new_buffered((100, 60)).export_page(1)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "cannot export page 1, the document has 1"
+        );
+    }
+
+    #[test]
+    fn test_export_page() {
+        fs::create_dir_all(EXPORT_DIR).unwrap();
+        let export = |page: usize| {
+            let mut backend = TypstBackend::new_buffered((100, 60))
+                .template("Cover\n#pagebreak()\n{{chart}}")
+                .export_page(page);
+            backend
+                .draw_rect((10, 10), (90, 50), &BLUE.filled(), true)
+                .unwrap();
+            let paths = backend
+                .export_all(format!("{EXPORT_DIR}/page-{page}"), &[ExportFormat::Png])
+                .unwrap();
+            tiny_skia::Pixmap::load_png(&paths[1]).unwrap()
+        };
+
+        // The chart is on the second page, after the cover
+        let is_blue = |pixmap: &tiny_skia::Pixmap| {
+            pixmap
+                .pixels()
+                .iter()
+                .any(|p| p.blue() > 200 && p.red() < 50 && p.green() < 50)
+        };
+        assert!(!is_blue(&export(0)));
+        let chart = export(1);
+        assert!(is_blue(&chart));
+        assert_eq!((chart.width(), chart.height()), (100, 60));
+    }
+
+    #[test]
+    #[should_panic(expected = "export scale must be finite and positive")]
+    fn test_export_scale_rejects_zero() {
+        let _ = TypstBackend::new_buffered((100, 60)).export_scale(0.0);
+    }
+}
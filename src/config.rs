@@ -0,0 +1,547 @@
+/*!
+Chart settings kept in a configuration file
+
+Behind the `config` feature, the options set by the builder methods of
+[`TypstBackend`] can be read from a TOML file or any serde format, so a report
+generator keeps its output settings next to its other configuration:
+
+```no_run
+use plotters_typst::config::TypstConfig;
+use plotters_typst::TypstBackend;
+
+let config = TypstConfig::from_toml(
+    r#"
+    em_units = 11.0
+    tabular_figures = true
+    font_features = ["zero"]
+    colorblind_palette = "okabe-ito"
+    frame = { color = [0, 0, 0], width = 1 }
+    "#,
+)
+.unwrap();
+let backend = TypstBackend::new("chart.typ", (640, 480)).config(&config);
+```
+
+The keys are named after the builder methods. Options taking Rust values, such as
+[`TypstBackend::color_serializer`] or [`TypstBackend::command_log`], are set in
+code.
+*/
+
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use plotters_backend::BackendColor;
+use serde::Deserialize;
+
+use crate::{ColorblindPalette, TypstBackend, STROKE_CAPS, STROKE_DASHES, STROKE_JOINS};
+
+/// The color, as `[red, green, blue]`, and width in pixels of a stroke
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StrokeConfig {
+    /// The color of the stroke
+    pub color: (u8, u8, u8),
+    /// The width of the stroke, 1 by default
+    #[serde(default = "default_width")]
+    pub width: u32,
+}
+
+fn default_width() -> u32 {
+    1
+}
+
+impl StrokeConfig {
+    fn backend(self) -> (BackendColor, u32) {
+        let color = BackendColor {
+            alpha: 1.0,
+            rgb: self.color,
+        };
+        (color, self.width)
+    }
+}
+
+/// A watermark, see [`TypstBackend::watermark`]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatermarkConfig {
+    /// The text of the watermark
+    pub text: String,
+    /// The clockwise rotation of the text in degrees, 0 by default
+    #[serde(default)]
+    pub angle: f64,
+    /// The opacity of the text from 0 to 1
+    pub opacity: f64,
+}
+
+/// The options of a [`TypstBackend`], each named after the builder method setting
+/// it; unset options keep their defaults
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TypstConfig {
+    /// See [`TypstBackend::with_outline`]
+    pub outline: Option<StrokeConfig>,
+    /// See [`TypstBackend::inline`]
+    pub inline: bool,
+    /// See [`TypstBackend::overflow_visible`]
+    pub overflow_visible: bool,
+    /// See [`TypstBackend::float_safe`]
+    pub float_safe: bool,
+    /// See [`TypstBackend::padding`]
+    pub padding: Option<u32>,
+    /// See [`TypstBackend::auto_crop`]
+    pub auto_crop: bool,
+    /// See [`TypstBackend::manifest`]
+    pub manifest: bool,
+    /// See [`TypstBackend::source_map_comments`]
+    pub source_map_comments: bool,
+    /// See [`TypstBackend::detach_legend`]
+    pub detach_legend: bool,
+    /// See [`TypstBackend::split_series`]
+    pub split_series: bool,
+    /// See [`TypstBackend::split_layers`]
+    pub split_layers: bool,
+    /// See [`TypstBackend::named_anchors`]
+    pub named_anchors: bool,
+    /// See [`TypstBackend::figure_caption`]
+    pub figure_caption: Option<String>,
+    /// See [`TypstBackend::figure_label`]
+    pub figure_label: Option<String>,
+    /// See [`TypstBackend::reproducible`]
+    pub reproducible: bool,
+    /// See [`TypstBackend::compact`]
+    pub compact: bool,
+    /// See [`TypstBackend::batch_histograms`]
+    pub batch_histograms: bool,
+    /// See [`TypstBackend::decimate_lines`]
+    pub decimate_lines: bool,
+    /// See [`TypstBackend::spill_to_disk`], the cap in bytes
+    pub spill_to_disk: Option<usize>,
+    /// See [`TypstBackend::size_budget`], in bytes
+    pub size_budget: Option<usize>,
+    /// See [`TypstBackend::primitive_budget`]
+    pub primitive_budget: Option<usize>,
+    /// See [`TypstBackend::baseline`]
+    pub baseline: Option<i32>,
+    /// See [`TypstBackend::responsive`]
+    pub responsive: bool,
+    /// See [`TypstBackend::scale`]
+    pub scale: Option<f64>,
+    /// See [`TypstBackend::em_units`]
+    pub em_units: Option<f64>,
+    /// See [`TypstBackend::transparent_background`]
+    pub transparent_background: bool,
+    /// See [`TypstBackend::frame`]
+    pub frame: Option<StrokeConfig>,
+    /// The corner radius of the [`Self::frame`] in pixels
+    pub frame_radius: u32,
+    /// See [`TypstBackend::debug_overlay`]
+    pub debug_overlay: bool,
+    /// See [`TypstBackend::watermark`]
+    pub watermark: Option<WatermarkConfig>,
+    /// See [`TypstBackend::preamble`]
+    pub preamble: Option<String>,
+    /// See [`TypstBackend::template`]
+    pub template: Option<String>,
+    /// See [`TypstBackend::template_file`]
+    pub template_file: Option<PathBuf>,
+    /// See [`TypstBackend::standalone`]
+    pub standalone: bool,
+    /// See [`TypstBackend::page_header`]
+    pub page_header: Option<String>,
+    /// See [`TypstBackend::page_footer`]
+    pub page_footer: Option<String>,
+    /// See [`TypstBackend::reveal_function`]
+    pub reveal_function: Option<String>,
+    /// See [`TypstBackend::namespace`]
+    pub namespace: Option<String>,
+    /// See [`TypstBackend::hashed_namespace`]
+    pub hashed_namespace: bool,
+    /// See [`TypstBackend::stroke_cap`]
+    pub stroke_cap: Option<String>,
+    /// See [`TypstBackend::stroke_join`]
+    pub stroke_join: Option<String>,
+    /// See [`TypstBackend::stroke_miter_limit`]
+    pub stroke_miter_limit: Option<f64>,
+    /// See [`TypstBackend::stroke_dash`]
+    pub stroke_dash: Option<String>,
+    /// See [`TypstBackend::stroke_scale`]
+    pub stroke_scale: Option<f64>,
+    /// See [`TypstBackend::tabular_figures`]
+    pub tabular_figures: bool,
+    /// See [`TypstBackend::disable_ligatures`]
+    pub disable_ligatures: bool,
+    /// See [`TypstBackend::strict_fonts`]
+    pub strict_fonts: bool,
+    /// See [`TypstBackend::smart_punctuation`]
+    pub smart_punctuation: bool,
+    /// See [`TypstBackend::inherit_text_color`]
+    pub inherit_text_color: bool,
+    /// See [`TypstBackend::grayscale`]
+    pub grayscale: bool,
+    /// See [`TypstBackend::colorblind_palette`], `"okabe-ito"` or `"viridis"`
+    pub colorblind_palette: Option<ColorblindPalette>,
+    /// See [`TypstBackend::inherit_paragraph_settings`]
+    pub inherit_paragraph_settings: bool,
+    /// See [`TypstBackend::font_features`]
+    pub font_features: Vec<String>,
+    /// See [`TypstBackend::append`]
+    pub append: bool,
+    /// See [`TypstBackend::check_fonts`]
+    #[cfg(feature = "fontdb")]
+    pub check_fonts: bool,
+    /// See [`TypstBackend::export_scale`]
+    #[cfg(feature = "compile")]
+    pub export_scale: Option<f32>,
+    /// See [`TypstBackend::export_page`]
+    #[cfg(feature = "compile")]
+    pub export_page: Option<usize>,
+    /// See [`TypstBackend::raster_fallback`], the threshold
+    #[cfg(feature = "image")]
+    pub raster_fallback: Option<usize>,
+    /// See [`TypstBackend::raster_dpi`]
+    #[cfg(feature = "image")]
+    pub raster_dpi: Option<f64>,
+    /// See [`TypstBackend::raster_only`]
+    #[cfg(feature = "image")]
+    pub raster_only: Option<Vec<String>>,
+}
+
+impl TypstConfig {
+    /// Read the options from the TOML document `text`
+    pub fn from_toml(text: &str) -> Result<Self, Error> {
+        let config: Self =
+            toml::from_str(text).map_err(|e| Error::new(ErrorKind::InvalidData, e.message()))?;
+        config.check()?;
+        Ok(config)
+    }
+
+    /// Read the options from the TOML file at `path`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    /// Reject the values the builder methods would panic on
+    fn check(&self) -> Result<(), Error> {
+        let positive = [
+            ("scale", self.scale),
+            ("em_units", self.em_units),
+            ("stroke_scale", self.stroke_scale),
+            #[cfg(feature = "compile")]
+            ("export_scale", self.export_scale.map(f64::from)),
+            #[cfg(feature = "image")]
+            ("raster_dpi", self.raster_dpi),
+        ];
+        for (key, value) in positive {
+            if value.is_some_and(|value| !(value.is_finite() && value > 0.0)) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("`{}` must be finite and positive", key),
+                ));
+            }
+        }
+        let named = [
+            ("stroke_cap", &self.stroke_cap, &STROKE_CAPS[..]),
+            ("stroke_join", &self.stroke_join, &STROKE_JOINS[..]),
+            ("stroke_dash", &self.stroke_dash, &STROKE_DASHES[..]),
+        ];
+        for (key, value, allowed) in named {
+            if value
+                .as_deref()
+                .is_some_and(|value| !allowed.contains(&value))
+            {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("`{}` must be one of {}", key, allowed.join(", ")),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TypstBackend<'_> {
+    /// Set the options given in `config`, keeping the others as they are
+    pub fn config(mut self, config: &TypstConfig) -> Self {
+        // The options changing how the markup of the others is written come first
+        if let Some(factor) = config.scale {
+            self = self.scale(factor);
+        }
+        if let Some(font_size) = config.em_units {
+            self = self.em_units(font_size);
+        }
+        if config.smart_punctuation {
+            self = self.smart_punctuation();
+        }
+        if let Some(prefix) = &config.namespace {
+            self = self.namespace(prefix);
+        }
+        if config.hashed_namespace {
+            self = self.hashed_namespace();
+        }
+        if config.reproducible {
+            self = self.reproducible();
+        }
+        if let Some(outline) = config.outline {
+            self.options.outline = Some(outline.backend());
+        }
+        if config.inline {
+            self = self.inline();
+        }
+        if config.overflow_visible {
+            self = self.overflow_visible();
+        }
+        if config.float_safe {
+            self = self.float_safe();
+        }
+        if let Some(padding) = config.padding {
+            self = self.padding(padding);
+        }
+        if config.auto_crop {
+            self = self.auto_crop();
+        }
+        if config.manifest {
+            self = self.manifest();
+        }
+        if config.source_map_comments {
+            self = self.source_map_comments();
+        }
+        if config.detach_legend {
+            self = self.detach_legend();
+        }
+        if config.split_series {
+            self = self.split_series();
+        }
+        if config.split_layers {
+            self = self.split_layers();
+        }
+        if config.named_anchors {
+            self = self.named_anchors();
+        }
+        if let Some(caption) = &config.figure_caption {
+            self = self.figure_caption(caption);
+        }
+        if let Some(label) = &config.figure_label {
+            self = self.figure_label(label);
+        }
+        if config.compact {
+            self = self.compact();
+        }
+        if config.batch_histograms {
+            self = self.batch_histograms();
+        }
+        if config.decimate_lines {
+            self = self.decimate_lines();
+        }
+        if let Some(cap) = config.spill_to_disk {
+            self = self.spill_to_disk(cap);
+        }
+        if let Some(bytes) = config.size_budget {
+            self = self.size_budget(bytes);
+        }
+        if let Some(count) = config.primitive_budget {
+            self = self.primitive_budget(count);
+        }
+        if let Some(y) = config.baseline {
+            self = self.baseline(y);
+        }
+        if config.responsive {
+            self = self.responsive();
+        }
+        if config.transparent_background {
+            self = self.transparent_background();
+        }
+        if let Some(frame) = config.frame {
+            let (color, width) = frame.backend();
+            self.options.frame = Some((color, width, config.frame_radius));
+        }
+        if config.debug_overlay {
+            self = self.debug_overlay();
+        }
+        if let Some(watermark) = &config.watermark {
+            self = self.watermark(&watermark.text, watermark.angle, watermark.opacity);
+        }
+        if let Some(markup) = &config.preamble {
+            self = self.preamble(markup);
+        }
+        if let Some(template) = &config.template {
+            self = self.template(template);
+        }
+        if let Some(path) = &config.template_file {
+            self = self.template_file(path);
+        }
+        if config.standalone {
+            self = self.standalone();
+        }
+        if let Some(markup) = &config.page_header {
+            self = self.page_header(markup);
+        }
+        if let Some(markup) = &config.page_footer {
+            self = self.page_footer(markup);
+        }
+        if let Some(function) = &config.reveal_function {
+            self = self.reveal_function(function);
+        }
+        if let Some(cap) = &config.stroke_cap {
+            self = self.stroke_cap(cap);
+        }
+        if let Some(join) = &config.stroke_join {
+            self = self.stroke_join(join);
+        }
+        if let Some(limit) = config.stroke_miter_limit {
+            self = self.stroke_miter_limit(limit);
+        }
+        if let Some(dash) = &config.stroke_dash {
+            self = self.stroke_dash(dash);
+        }
+        if let Some(factor) = config.stroke_scale {
+            self = self.stroke_scale(factor);
+        }
+        if config.tabular_figures {
+            self = self.tabular_figures();
+        }
+        if config.disable_ligatures {
+            self = self.disable_ligatures();
+        }
+        if config.strict_fonts {
+            self = self.strict_fonts();
+        }
+        if config.inherit_text_color {
+            self = self.inherit_text_color();
+        }
+        if config.grayscale {
+            self = self.grayscale();
+        }
+        if let Some(palette) = config.colorblind_palette {
+            self = self.colorblind_palette(palette);
+        }
+        if config.inherit_paragraph_settings {
+            self = self.inherit_paragraph_settings();
+        }
+        if !config.font_features.is_empty() {
+            let features: Vec<&str> = config.font_features.iter().map(String::as_str).collect();
+            self = self.font_features(&features);
+        }
+        if config.append {
+            self = self.append();
+        }
+        #[cfg(feature = "fontdb")]
+        if config.check_fonts {
+            self = self.check_fonts();
+        }
+        #[cfg(feature = "compile")]
+        if let Some(pixel_per_pt) = config.export_scale {
+            self = self.export_scale(pixel_per_pt);
+        }
+        #[cfg(feature = "compile")]
+        if let Some(page) = config.export_page {
+            self = self.export_page(page);
+        }
+        #[cfg(feature = "image")]
+        if let Some(threshold) = config.raster_fallback {
+            self = self.raster_fallback(threshold);
+        }
+        #[cfg(feature = "image")]
+        if let Some(dpi) = config.raster_dpi {
+            self = self.raster_dpi(dpi);
+        }
+        #[cfg(feature = "image")]
+        if let Some(names) = &config.raster_only {
+            let names: Vec<&str> = names.iter().map(String::as_str).collect();
+            self = self.raster_only(&names);
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TypstConfig;
+    use crate::{ColorblindPalette, TypstBackend};
+    use plotters::style::{ShapeStyle, BLUE};
+
+    #[test]
+    fn test_config() {
+        let config = TypstConfig::from_toml(
+            r#"
+            em_units = 11.0
+            tabular_figures = true
+            font_features = ["zero"]
+            colorblind_palette = "okabe-ito"
+            namespace = "sales"
+            frame = { color = [0, 0, 255], width = 2 }
+            frame_radius = 4
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.colorblind_palette, Some(ColorblindPalette::OkabeIto));
+
+        let from_config = TypstBackend::new_buffered((100, 50))
+            .config(&config)
+            .finish()
+            .unwrap();
+        let from_code = TypstBackend::new_buffered((100, 50))
+            .em_units(11.0)
+            .tabular_figures()
+            .font_features(&["zero"])
+            .colorblind_palette(ColorblindPalette::OkabeIto)
+            .namespace("sales")
+            .frame(&ShapeStyle::from(BLUE).stroke_width(2), 4)
+            .finish()
+            .unwrap();
+        assert_eq!(from_config, from_code);
+
+        // The watermark is escaped with the punctuation setting listed after it
+        let config = TypstConfig::from_toml(
+            r#"
+            watermark = { text = "Don't copy", angle = -30.0, opacity = 0.2 }
+            smart_punctuation = true
+            "#,
+        )
+        .unwrap();
+        let from_config = TypstBackend::new_buffered((100, 50))
+            .config(&config)
+            .finish()
+            .unwrap();
+        let from_code = TypstBackend::new_buffered((100, 50))
+            .smart_punctuation()
+            .watermark("Don't copy", -30.0, 0.2)
+            .finish()
+            .unwrap();
+        assert_eq!(from_config, from_code);
+        assert!(from_code.contains("[Don't copy]"));
+
+        let error = TypstConfig::from_toml("em_unit = 11.0").unwrap_err();
+        assert!(error.to_string().starts_with("unknown field `em_unit`"));
+
+        let error = TypstConfig::from_toml("scale = 0.0").unwrap_err();
+        assert_eq!(error.to_string(), "`scale` must be finite and positive");
+        let error = TypstConfig::from_toml("em_units = -11.0").unwrap_err();
+        assert_eq!(error.to_string(), "`em_units` must be finite and positive");
+        let error = TypstConfig::from_toml("stroke_scale = 0.0").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "`stroke_scale` must be finite and positive"
+        );
+        let error = TypstConfig::from_toml("stroke_join = \"sharp\"").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "`stroke_join` must be one of miter, round, bevel"
+        );
+        #[cfg(feature = "compile")]
+        {
+            let error = TypstConfig::from_toml("export_scale = -1.0").unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "`export_scale` must be finite and positive"
+            );
+        }
+        #[cfg(feature = "image")]
+        {
+            let error = TypstConfig::from_toml("raster_dpi = nan").unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "`raster_dpi` must be finite and positive"
+            );
+        }
+    }
+}
@@ -0,0 +1,214 @@
+/*!
+Documents of several generated charts
+
+A [`TypstDocument`] collects finished charts, such as the output of
+[`TypstBackend::finish`](crate::TypstBackend::finish), into one Typst document:
+one captioned figure after the other by default, or a contact sheet of thumbnails
+to review dozens of charts at a glance in one compiled PDF:
+
+```
+use plotters_typst::document::TypstDocument;
+use plotters_typst::TypstBackend;
+
+let mut document = TypstDocument::new().contact_sheet(4);
+for name in ["north", "south"] {
+    let chart = TypstBackend::new_buffered((320, 200)).finish().unwrap();
+    document = document.chart(name, chart);
+}
+assert_eq!(document.len(), 2);
+```
+
+Each chart is embedded in a content block, so the bindings of one chart do not
+leak into the next. Charts must not be standalone documents, as the page cannot be
+set up from inside a figure.
+
+Charts can also be drawn by the document with [`TypstDocument::render_charts`],
+in parallel with the `rayon` feature, for reports of hundreds of charts.
+*/
+
+use std::fmt::Write as _;
+use std::io;
+use std::path::Path;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::escape_markup;
+
+/// Scales a chart down to the width of its grid cell, keeping its aspect ratio
+const THUMBNAIL: &str = "#let thumbnail(body) = layout(size => {
+  let natural = measure(body)
+  let s = calc.min(1.0, size.width / natural.width)
+  box(width: natural.width * s, height: natural.height * s, scale(x: s * 100%, y: s * 100%, origin: top + left, body))
+})
+";
+
+/// A Typst document of several charts
+#[derive(Clone, Debug, Default)]
+pub struct TypstDocument {
+    charts: Vec<(String, String)>,
+    columns: Option<usize>,
+}
+
+impl TypstDocument {
+    /// Create a document without charts
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append the chart `source` captioned `caption`; an empty caption leaves the
+    /// chart uncaptioned
+    pub fn chart<S: Into<String>>(mut self, caption: &str, source: S) -> Self {
+        self.charts.push((caption.to_string(), source.into()));
+        self
+    }
+
+    /// Draw a chart of each of `items` with `render`, which returns its caption and
+    /// source, and append them in the order of `items`
+    ///
+    /// With the `rayon` feature the charts are drawn on the rayon thread pool; the
+    /// document is the same either way. Each call of `render` creates a backend of
+    /// its own, as backends cannot be moved between threads. On failure, the error
+    /// of the first failing item is returned.
+    pub fn render_charts<T, F>(mut self, items: &[T], render: F) -> io::Result<Self>
+    where
+        T: Sync,
+        F: Fn(&T) -> io::Result<(String, String)> + Sync,
+    {
+        // Collected in order before looking for errors, so the first one is reported
+        #[cfg(feature = "rayon")]
+        let charts: Vec<_> = items.par_iter().map(&render).collect();
+        #[cfg(not(feature = "rayon"))]
+        let charts: Vec<_> = items.iter().map(&render).collect();
+        for chart in charts {
+            self.charts.push(chart?);
+        }
+        Ok(self)
+    }
+
+    /// Lay the charts out as a contact sheet: a grid of `columns` columns, each
+    /// chart scaled down to fit its cell with its caption under it
+    pub fn contact_sheet(mut self, columns: usize) -> Self {
+        self.columns = Some(columns.max(1));
+        self
+    }
+
+    /// The number of charts in the document
+    pub fn len(&self) -> usize {
+        self.charts.len()
+    }
+
+    /// Whether the document has no chart
+    pub fn is_empty(&self) -> bool {
+        self.charts.is_empty()
+    }
+
+    /// The Typst source of the document
+    pub fn to_typst(&self) -> String {
+        let mut document = String::new();
+        let figure = |document: &mut String, caption: &str, body: &str| {
+            document.push_str("figure(");
+            document.push_str(body);
+            if !caption.is_empty() {
+                write!(document, ", caption: [{}]", escape_markup(caption, true)).unwrap();
+            }
+            document.push(')');
+        };
+        match self.columns {
+            Some(columns) => {
+                document.push_str(THUMBNAIL);
+                writeln!(
+                    document,
+                    "#grid(columns: (1fr,) * {}, gutter: 12pt,",
+                    columns
+                )
+                .unwrap();
+                for (caption, source) in &self.charts {
+                    document.push_str("  ");
+                    figure(&mut document, caption, &format!("thumbnail[\n{}]", source));
+                    document.push_str(",\n");
+                }
+                document.push_str(")\n");
+            }
+            None => {
+                for (caption, source) in &self.charts {
+                    document.push('#');
+                    figure(&mut document, caption, &format!("[\n{}]", source));
+                    document.push('\n');
+                }
+            }
+        }
+        document
+    }
+
+    /// Write the document to the file at `path`
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        std::fs::write(path, self.to_typst())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TypstDocument;
+    use crate::test::checked_save_file;
+    use crate::TypstBackend;
+    use plotters::prelude::*;
+
+    fn chart(color: RGBColor) -> String {
+        let mut backend = TypstBackend::new_buffered((200, 100)).namespace("chart");
+        backend
+            .draw_rect((10, 10), (190, 90), &color.filled(), true)
+            .unwrap();
+        // Every chart binds the same name
+        backend.write_binding("color", "none");
+        backend.finish().unwrap()
+    }
+
+    #[test]
+    fn test_contact_sheet() {
+        let sheet = [("red", RED), ("green", GREEN), ("blue", BLUE)]
+            .into_iter()
+            .fold(
+                TypstDocument::new().contact_sheet(2),
+                |sheet, (name, color)| sheet.chart(name, chart(color)),
+            );
+        assert_eq!(sheet.len(), 3);
+
+        let source = sheet.to_typst();
+        checked_save_file("test_contact_sheet", &source);
+        assert!(source.contains(
+            "#grid(columns: (1fr,) * 2, gutter: 12pt,\n  figure(thumbnail[\n#let chart_color"
+        ));
+        assert!(source.ends_with("], caption: [blue]),\n)\n"));
+
+        let document = TypstDocument::new().chart("", chart(RED)).to_typst();
+        assert!(document.starts_with("#figure([\n"));
+        assert!(document.ends_with("])\n"));
+    }
+
+    #[test]
+    fn test_render_charts() {
+        let colors: Vec<_> = (0..64u8).map(|i| RGBColor(i * 4, 0, 255 - i * 4)).collect();
+        let render = |color: &RGBColor| Ok((format!("{:?}", color.rgb()), chart(*color)));
+        let rendered = TypstDocument::new()
+            .render_charts(&colors, render)
+            .unwrap()
+            .to_typst();
+        let sequential = colors
+            .iter()
+            .fold(TypstDocument::new(), |document, color| {
+                let (caption, source) = render(color).unwrap();
+                document.chart(&caption, source)
+            })
+            .to_typst();
+        assert_eq!(rendered, sequential);
+
+        let error = TypstDocument::new()
+            .render_charts(&colors, |color| match color.0 {
+                8 | 16 => Err(std::io::Error::other(format!("failed {}", color.0))),
+                _ => render(color),
+            })
+            .unwrap_err();
+        assert_eq!(error.to_string(), "failed 8");
+    }
+}
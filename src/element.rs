@@ -0,0 +1,2410 @@
+/*!
+Drawable elements that render to native Typst shapes
+
+These elements only implement [`Drawable`] for [`TypstBackend`]: instead of being
+tessellated into generic backend primitives, each one is emitted as a single Typst
+shape. They need the `plotters` feature, which is off by default.
+*/
+
+use plotters::chart::{ChartContext, SeriesAnno};
+use plotters::coord::{CoordTranslate, Shift};
+use plotters::drawing::{DrawingArea, DrawingAreaErrorKind};
+use plotters::element::{CoordMapper, Drawable, PointCollection};
+use plotters::style::text_anchor::{HPos, Pos, VPos};
+use plotters::style::{Color, ShapeStyle, SizeDesc, TextStyle};
+use plotters_backend::{
+    BackendColor, BackendCoord, BackendStyle, BackendTextStyle, DrawingBackend, DrawingErrorKind,
+};
+
+use crate::manifest::JsonObject;
+use crate::{typst_font_family, typst_identifier, Decoration, TypstBackend, TEXT_DEFAULTS};
+
+/// An ellipse emitted as a Typst `ellipse`
+pub struct TypstEllipse<Coord, Size: SizeDesc> {
+    center: Coord,
+    size: (Size, Size),
+    rotation: f64,
+    style: ShapeStyle,
+}
+
+impl<Coord, Size: SizeDesc> TypstEllipse<Coord, Size> {
+    /// Create a new ellipse element
+    /// - `center` The center of the ellipse
+    /// - `size` The width and height of the ellipse before rotation
+    /// - `style` The style of the ellipse
+    pub fn new<S: Into<ShapeStyle>>(center: Coord, size: (Size, Size), style: S) -> Self {
+        Self {
+            center,
+            size,
+            rotation: 0.0,
+            style: style.into(),
+        }
+    }
+
+    /// Rotate the ellipse clockwise around its center by `degrees`; a non-finite
+    /// angle is ignored
+    pub fn rotate(mut self, degrees: f64) -> Self {
+        if degrees.is_finite() {
+            self.rotation = degrees;
+        }
+        self
+    }
+}
+
+impl<'b, Coord, Size: SizeDesc> PointCollection<'b, Coord> for &'b TypstEllipse<Coord, Size> {
+    type Point = &'b Coord;
+    type IntoIter = std::iter::Once<&'b Coord>;
+    fn point_iter(self) -> std::iter::Once<&'b Coord> {
+        std::iter::once(&self.center)
+    }
+}
+
+impl<Coord, Size: SizeDesc> Drawable<TypstBackend<'_>> for TypstEllipse<Coord, Size> {
+    fn draw<I: Iterator<Item = BackendCoord>>(
+        &self,
+        mut points: I,
+        backend: &mut TypstBackend,
+        ps: (u32, u32),
+    ) -> Result<(), DrawingErrorKind<std::io::Error>> {
+        let Some((x, y)) = points.next() else {
+            return Ok(());
+        };
+        if self.style.color.3 == 0.0 {
+            return Ok(());
+        }
+
+        let width = self.size.0.in_pixels(&ps).max(0);
+        let height = self.size.1.in_pixels(&ps).max(0);
+        let attrs = backend.shape_attrs(&self.style, self.style.filled, true);
+
+        let mut shape = format!(
+            "ellipse(width: {}, height: {}, {})",
+            backend.len(width),
+            backend.len(height),
+            attrs
+        );
+        if self.rotation != 0.0 {
+            shape = format!("rotate({}deg, {})", self.rotation, shape);
+        }
+
+        // The placed content is exactly the ellipse, so rotation pivots on its center
+        let cmd = format!(
+            "  #place(dx: {}, dy: {}, {})",
+            backend.len(f64::from(x) - f64::from(width) / 2.0),
+            backend.len(f64::from(y) - f64::from(height) / 2.0),
+            shape
+        );
+        let call = backend.begin_call(|| format!("TypstEllipse({:?})", (x, y)));
+        backend.write_command(&cmd);
+        backend.note_element("ellipse");
+        backend.log_command(|| {
+            JsonObject::new("ellipse")
+                .point("center", (x, y))
+                .number("width", width)
+                .number("height", height)
+                .number("rotation", self.rotation)
+                .color("color", self.style.color())
+                .number("stroke_width", self.style.stroke_width)
+                .flag("filled", self.style.filled)
+        })?;
+        // A turned ellipse stays within the circle around its longer axis
+        let (rx, ry) = match self.rotation % 180.0 {
+            0.0 => (width / 2, height / 2),
+            _ => (width.max(height) / 2, width.max(height) / 2),
+        };
+        let margin = backend.shape_margin(&self.style, self.style.filled, true);
+        backend.include_bounds((x - rx, y - ry), (x + rx, y + ry), margin + 1);
+        backend.end_call(call)?;
+        Ok(())
+    }
+}
+
+/// An arrow between two points, drawn as a line plus a filled arrowhead at the end
+pub struct TypstArrow<Coord> {
+    points: [Coord; 2],
+    head_size: u32,
+    style: ShapeStyle,
+}
+
+impl<Coord> TypstArrow<Coord> {
+    /// Create a new arrow element
+    /// - `from` The tail of the arrow
+    /// - `to` The point the arrowhead touches
+    /// - `style` The style of the shaft; the head is filled with the same color
+    pub fn new<S: Into<ShapeStyle>>(from: Coord, to: Coord, style: S) -> Self {
+        let style = style.into();
+        Self {
+            points: [from, to],
+            head_size: 6 + 2 * style.stroke_width,
+            style,
+        }
+    }
+
+    /// Set the length of the arrowhead in pixels
+    pub fn head_size(mut self, size: u32) -> Self {
+        self.head_size = size;
+        self
+    }
+}
+
+impl<'b, Coord> PointCollection<'b, Coord> for &'b TypstArrow<Coord> {
+    type Point = &'b Coord;
+    type IntoIter = &'b [Coord];
+    fn point_iter(self) -> &'b [Coord] {
+        &self.points
+    }
+}
+
+impl<Coord> Drawable<TypstBackend<'_>> for TypstArrow<Coord> {
+    fn draw<I: Iterator<Item = BackendCoord>>(
+        &self,
+        mut points: I,
+        backend: &mut TypstBackend,
+        _: (u32, u32),
+    ) -> Result<(), DrawingErrorKind<std::io::Error>> {
+        let (Some(from), Some(to)) = (points.next(), points.next()) else {
+            return Ok(());
+        };
+        if self.style.color.3 == 0.0 || from == to {
+            return Ok(());
+        }
+
+        let dx = f64::from(to.0 - from.0);
+        let dy = f64::from(to.1 - from.1);
+        let length = (dx * dx + dy * dy).sqrt();
+        let (ux, uy) = (dx / length, dy / length);
+
+        // Keep the shaft out of the head so a wide stroke doesn't blunt the tip
+        let head = f64::from(self.head_size).min(length);
+        let base = (f64::from(to.0) - ux * head, f64::from(to.1) - uy * head);
+        let shaft_end = (base.0.round() as i32, base.1.round() as i32);
+        let call = backend.begin_call(|| format!("TypstArrow({:?}, {:?})", from, to));
+        if shaft_end != from {
+            backend.draw_line(from, shaft_end, &self.style)?;
+        }
+
+        let half_width = head / 2.0;
+        let corners = [
+            (to.0.into(), to.1.into()),
+            (base.0 - uy * half_width, base.1 + ux * half_width),
+            (base.0 + uy * half_width, base.1 - ux * half_width),
+        ];
+        let points_str = corners
+            .iter()
+            .map(|&(x, y): &(f64, f64)| format!("({}, {})", backend.len(x), backend.len(y)))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let cmd = format!(
+            "  #place(polygon({}, {}))",
+            backend.shape_attrs(&self.style, true, false),
+            points_str
+        );
+        backend.write_command(&cmd);
+        backend.note_element("arrow");
+        backend.log_command(|| {
+            JsonObject::new("arrow")
+                .point("from", from)
+                .point("to", to)
+                .number("head_size", self.head_size)
+                .color("color", self.style.color())
+                .number("stroke_width", self.style.stroke_width)
+        })?;
+        let corners = corners.map(|(x, y)| (x.round() as i32, y.round() as i32));
+        backend.include_points(&corners, 1);
+        backend.end_call(call)?;
+        Ok(())
+    }
+}
+
+/// A text box connected to a target point by a leader line
+///
+/// The box is clamped inside the canvas at Typst layout time, so a label near
+/// the edge is moved inward instead of being clipped; the leader line follows it.
+pub struct TypstCallout<Coord> {
+    target: Coord,
+    offset: (i32, i32),
+    text: String,
+    font_family: String,
+    font_size: f64,
+    text_color: BackendColor,
+    padding: u32,
+    background: Option<BackendColor>,
+    border: Option<ShapeStyle>,
+    leader: ShapeStyle,
+}
+
+impl<Coord> TypstCallout<Coord> {
+    /// Create a new callout element
+    /// - `target` The point the leader line points at
+    /// - `offset` The position of the box center relative to the target, in pixels
+    /// - `text` The text inside the box
+    pub fn new<T: Into<String>>(target: Coord, offset: (i32, i32), text: T) -> Self {
+        let black = BackendColor {
+            alpha: 1.0,
+            rgb: (0, 0, 0),
+        };
+        let leader = ShapeStyle {
+            color: plotters::style::BLACK.into(),
+            filled: false,
+            stroke_width: 1,
+        };
+        Self {
+            target,
+            offset,
+            text: text.into(),
+            font_family: "sans-serif".to_string(),
+            font_size: 12.0,
+            text_color: black,
+            padding: 4,
+            background: Some(BackendColor {
+                alpha: 1.0,
+                rgb: (255, 255, 255),
+            }),
+            border: Some(leader),
+            leader,
+        }
+    }
+
+    /// Set the font family, size and color of the text
+    pub fn text_style<S: BackendTextStyle>(mut self, style: &S) -> Self {
+        self.font_family = style.family().as_str().to_string();
+        self.font_size = style.size();
+        self.text_color = style.color();
+        self
+    }
+
+    /// Set the padding between the text and the border in pixels
+    pub fn padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Set the background of the box, `None` for a transparent box
+    pub fn background<S: BackendStyle>(mut self, style: Option<&S>) -> Self {
+        self.background = style.map(|s| s.color());
+        self
+    }
+
+    /// Set the border of the box, `None` for no border
+    pub fn border<S: Into<ShapeStyle>>(mut self, style: Option<S>) -> Self {
+        self.border = style.map(Into::into);
+        self
+    }
+
+    /// Set the style of the leader line
+    pub fn leader<S: Into<ShapeStyle>>(mut self, style: S) -> Self {
+        self.leader = style.into();
+        self
+    }
+}
+
+impl<'b, Coord> PointCollection<'b, Coord> for &'b TypstCallout<Coord> {
+    type Point = &'b Coord;
+    type IntoIter = std::iter::Once<&'b Coord>;
+    fn point_iter(self) -> std::iter::Once<&'b Coord> {
+        std::iter::once(&self.target)
+    }
+}
+
+impl<Coord> Drawable<TypstBackend<'_>> for TypstCallout<Coord> {
+    fn draw<I: Iterator<Item = BackendCoord>>(
+        &self,
+        mut points: I,
+        backend: &mut TypstBackend,
+        _: (u32, u32),
+    ) -> Result<(), DrawingErrorKind<std::io::Error>> {
+        let Some((x, y)) = points.next() else {
+            return Ok(());
+        };
+
+        let (width, height) = backend.get_size();
+        let (label_x, label_y) = (x + self.offset.0, y + self.offset.1);
+
+        let fill = background_fill(backend, self.background);
+        let stroke = border_stroke(backend, self.border);
+        let leader = if self.leader.color.3 > 0.0 {
+            format!(
+                "place(line(start: ({}, {}), end: (x + m.width / 2, y + m.height / 2), stroke: {})); ",
+                backend.len(x),
+                backend.len(y),
+                backend.stroke(self.leader.stroke_width, &backend.typst_color(self.leader.color()))
+            )
+        } else {
+            String::new()
+        };
+
+        // Measure the box, clamp it inside the canvas, then draw the leader under it
+        let cmd = format!(
+            "  #context {{ let b = box(inset: {}, fill: {}, stroke: {}, text(size: {}{}, font: \"{}\"{}{})[{}]); let m = measure(b); let x = calc.max(0pt, calc.min({} - m.width, {} - m.width / 2)); let y = calc.max(0pt, calc.min({} - m.height, {} - m.height / 2)); {}place(dx: x, dy: y, b) }}",
+            backend.len(self.padding),
+            fill,
+            stroke,
+            backend.len(self.font_size / 1.24),
+            backend.text_fill(self.text_color),
+            typst_font_family(&self.font_family),
+            TEXT_DEFAULTS,
+            backend.text_features(),
+            backend.escape_label(&self.text),
+            backend.abs_len(width),
+            backend.abs_len(label_x),
+            backend.abs_len(height),
+            backend.abs_len(label_y),
+            leader
+        );
+        let call = backend.begin_call(|| format!("TypstCallout({:?}, {:?})", (x, y), self.text));
+        backend.write_command(&cmd);
+        backend.note_element("callout");
+        backend.note_font(typst_font_family(&self.font_family));
+        backend.log_command(|| {
+            JsonObject::new("callout")
+                .point("target", (x, y))
+                .point("at", (label_x, label_y))
+                .string("text", &self.text)
+                .color("color", self.text_color)
+                .string("font", &self.font_family)
+                .number("size", self.font_size)
+        })?;
+        // Estimated like a label, see `TypstBackend::include_text`
+        let font_size = self.font_size / 1.24;
+        let half_width = (self.text.chars().count() as f64 * font_size * 0.3).ceil() as i32;
+        let half_height = (font_size / 2.0).ceil() as i32;
+        backend.note_label(
+            &self.text,
+            (
+                label_x - half_width,
+                label_y - half_height,
+                label_x + half_width,
+                label_y + half_height,
+            ),
+        );
+        let margin = self.padding as i32
+            + self
+                .border
+                .map_or(0, |b| backend.half_stroke(b.stroke_width));
+        backend.include_bounds(
+            (label_x - half_width, label_y - half_height),
+            (label_x + half_width, label_y + half_height),
+            margin,
+        );
+        backend.include_bounds((x, y), (x, y), 0);
+        backend.end_call(call)?;
+        Ok(())
+    }
+}
+
+/// Text rotated by an arbitrary angle around its anchor point, and optionally
+/// underlined or struck through
+///
+/// Plotters only turns text by multiples of 90 degrees; this element draws slanted
+/// labels, such as dates below an axis at 45 degrees, with the anchor of its style.
+pub struct TypstText<'a, Coord> {
+    text: String,
+    pos: Coord,
+    style: TextStyle<'a>,
+    rotation: f64,
+    decoration: Decoration,
+}
+
+impl<'a, Coord> TypstText<'a, Coord> {
+    /// Create a new text element
+    /// - `text` The text to draw
+    /// - `pos` The anchor point of the text
+    /// - `style` The font and anchor of the text
+    pub fn new<T: Into<String>, S: Into<TextStyle<'a>>>(text: T, pos: Coord, style: S) -> Self {
+        Self {
+            text: text.into(),
+            pos,
+            style: style.into(),
+            rotation: 0.0,
+            decoration: Decoration::default(),
+        }
+    }
+
+    /// Rotate the text clockwise around its anchor point by `degrees`; a non-finite
+    /// angle is ignored
+    pub fn rotate(mut self, degrees: f64) -> Self {
+        if degrees.is_finite() {
+            self.rotation = degrees;
+        }
+        self
+    }
+
+    /// Underline the text, e.g. to emphasize a threshold
+    pub fn underline(mut self) -> Self {
+        self.decoration.underline = true;
+        self
+    }
+
+    /// Strike the text through, e.g. to mark a deprecated series
+    pub fn strike(mut self) -> Self {
+        self.decoration.strike = true;
+        self
+    }
+}
+
+impl<'b, Coord> PointCollection<'b, Coord> for &'b TypstText<'_, Coord> {
+    type Point = &'b Coord;
+    type IntoIter = std::iter::Once<&'b Coord>;
+    fn point_iter(self) -> std::iter::Once<&'b Coord> {
+        std::iter::once(&self.pos)
+    }
+}
+
+impl<Coord> Drawable<TypstBackend<'_>> for TypstText<'_, Coord> {
+    fn draw<I: Iterator<Item = BackendCoord>>(
+        &self,
+        mut points: I,
+        backend: &mut TypstBackend,
+        _: (u32, u32),
+    ) -> Result<(), DrawingErrorKind<std::io::Error>> {
+        if let Some(pos) = points.next() {
+            let call = backend.begin_call(|| format!("TypstText({:?}, {:?})", self.text, pos));
+            backend.write_text(&self.text, &self.style, pos, self.rotation, self.decoration);
+            backend.log_text(&self.text, &self.style, pos, self.rotation)?;
+            backend.end_call(call)?;
+        }
+        Ok(())
+    }
+}
+
+/// The shape of a [`TypstMarker`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarkerShape {
+    /// A five-pointed star
+    Star,
+    /// A square standing on one corner
+    Diamond,
+    /// An upward-pointing triangle
+    Triangle,
+    /// A diagonal cross
+    Cross,
+    /// An upright plus sign
+    Plus,
+}
+
+/// Format an optional box background as a Typst `fill` value
+fn background_fill(backend: &TypstBackend, background: Option<BackendColor>) -> String {
+    match background {
+        Some(color) if color.alpha > 0.0 => backend.typst_color(color),
+        _ => "none".to_string(),
+    }
+}
+
+/// Format an optional box border as a Typst `stroke` value
+fn border_stroke(backend: &TypstBackend, border: Option<ShapeStyle>) -> String {
+    match border {
+        Some(style) if style.color.3 > 0.0 => {
+            backend.stroke(style.stroke_width, &backend.typst_color(style.color()))
+        }
+        _ => "none".to_string(),
+    }
+}
+
+/// Round a length to two decimals so generated vertices stay short
+fn round_length(value: f64) -> f64 {
+    // Adding zero turns `-0.0` into `0.0`, which prints without the sign
+    (value * 100.0).round() / 100.0 + 0.0
+}
+
+/// A scatter-plot marker emitted as a single native Typst shape
+pub struct TypstMarker<Coord, Size: SizeDesc> {
+    center: Coord,
+    size: Size,
+    shape: MarkerShape,
+    style: ShapeStyle,
+}
+
+impl<Coord, Size: SizeDesc> TypstMarker<Coord, Size> {
+    /// Create a new marker element
+    /// - `center` The center of the marker
+    /// - `size` The radius of the circle the marker is inscribed in
+    /// - `shape` The shape of the marker
+    /// - `style` The style of the marker; crosses and plus signs are always stroked
+    pub fn new<S: Into<ShapeStyle>>(
+        center: Coord,
+        size: Size,
+        shape: MarkerShape,
+        style: S,
+    ) -> Self {
+        Self {
+            center,
+            size,
+            shape,
+            style: style.into(),
+        }
+    }
+}
+
+impl<'b, Coord, Size: SizeDesc> PointCollection<'b, Coord> for &'b TypstMarker<Coord, Size> {
+    type Point = &'b Coord;
+    type IntoIter = std::iter::Once<&'b Coord>;
+    fn point_iter(self) -> std::iter::Once<&'b Coord> {
+        std::iter::once(&self.center)
+    }
+}
+
+impl<Coord, Size: SizeDesc> Drawable<TypstBackend<'_>> for TypstMarker<Coord, Size> {
+    fn draw<I: Iterator<Item = BackendCoord>>(
+        &self,
+        mut points: I,
+        backend: &mut TypstBackend,
+        ps: (u32, u32),
+    ) -> Result<(), DrawingErrorKind<std::io::Error>> {
+        let Some((x, y)) = points.next() else {
+            return Ok(());
+        };
+        if self.style.color.3 == 0.0 {
+            return Ok(());
+        }
+
+        let radius = f64::from(self.size.in_pixels(&ps).max(0));
+        let color = backend.typst_color(self.style.color());
+
+        // Vertices are relative to the placed center, angles clockwise from 12 o'clock
+        let vertex = |angle: f64, r: f64| {
+            let (sin, cos) = angle.to_radians().sin_cos();
+            format!(
+                "({}, {})",
+                backend.len(round_length(r * sin)),
+                backend.len(round_length(-r * cos))
+            )
+        };
+        let shape = match self.shape {
+            MarkerShape::Star | MarkerShape::Diamond | MarkerShape::Triangle => {
+                let vertices = match self.shape {
+                    MarkerShape::Star => (0..10u8)
+                        .map(|i| vertex(f64::from(i) * 36.0, radius * [1.0, 0.4][i as usize % 2]))
+                        .collect::<Vec<_>>(),
+                    MarkerShape::Diamond => (0..4u8)
+                        .map(|i| vertex(f64::from(i) * 90.0, radius))
+                        .collect(),
+                    _ => (0..3u8)
+                        .map(|i| vertex(f64::from(i) * 120.0, radius))
+                        .collect(),
+                };
+                let attrs = backend.shape_attrs(&self.style, self.style.filled, true);
+                format!("polygon({}, {})", attrs, vertices.join(", "))
+            }
+            MarkerShape::Cross | MarkerShape::Plus => {
+                let start = if self.shape == MarkerShape::Cross {
+                    45.0
+                } else {
+                    0.0
+                };
+                let stroke = backend.stroke(self.style.stroke_width.max(1), &color);
+                format!(
+                    "{{ place(line(start: {}, end: {}, stroke: {})); place(line(start: {}, end: {}, stroke: {})) }}",
+                    vertex(start, radius),
+                    vertex(start + 180.0, radius),
+                    stroke,
+                    vertex(start + 90.0, radius),
+                    vertex(start + 270.0, radius),
+                    stroke
+                )
+            }
+        };
+
+        let cmd = format!(
+            "  #place(dx: {}, dy: {}, {})",
+            backend.len(x),
+            backend.len(y),
+            backend.marker_mark(shape)
+        );
+        let call = backend.begin_call(|| format!("TypstMarker({:?})", (x, y)));
+        backend.write_command(&cmd);
+        backend.note_element("marker");
+        backend.log_command(|| {
+            JsonObject::new("marker")
+                .point("center", (x, y))
+                .string("marker", &format!("{:?}", self.shape).to_lowercase())
+                .number("radius", radius)
+                .color("color", self.style.color())
+                .number("stroke_width", self.style.stroke_width)
+                .flag("filled", self.style.filled)
+        })?;
+        let r = radius.ceil() as i32;
+        let margin = backend.half_stroke(self.style.stroke_width.max(1));
+        backend.include_bounds((x - r, y - r), (x + r, y + r), margin);
+        backend.end_call(call)?;
+        Ok(())
+    }
+}
+
+/// A continuous colorbar emitted as a single gradient-filled Typst `rect`
+///
+/// The bar is vertical when it is taller than wide, with the low end of the value
+/// range at the bottom and tick labels on the right; otherwise it is horizontal,
+/// low end on the left, with tick labels below.
+pub struct TypstColorbar<'a, Coord> {
+    corners: [Coord; 2],
+    colors: Vec<BackendColor>,
+    range: (f64, f64),
+    ticks: usize,
+    formatter: Box<dyn Fn(f64) -> String + 'a>,
+    label_style: TextStyle<'a>,
+    border: Option<ShapeStyle>,
+}
+
+impl<'a, Coord> TypstColorbar<'a, Coord> {
+    /// Create a new colorbar element
+    /// - `corners` Two opposite corners of the bar
+    /// - `colors` Evenly spaced gradient stops, from the low to the high end of `range`
+    /// - `range` The values at the two ends of the bar
+    pub fn new<C: Color, I: IntoIterator<Item = C>>(
+        corners: [Coord; 2],
+        colors: I,
+        range: (f64, f64),
+    ) -> Self {
+        Self {
+            corners,
+            colors: colors.into_iter().map(|c| c.to_backend_color()).collect(),
+            range,
+            ticks: 5,
+            formatter: Box::new(|value| format!("{}", (value * 1000.0).round() / 1000.0 + 0.0)),
+            label_style: ("sans-serif", 12).into(),
+            border: Some(plotters::style::BLACK.into()),
+        }
+    }
+
+    /// Set the number of evenly spaced tick labels, including both ends; `0` disables them
+    pub fn ticks(mut self, count: usize) -> Self {
+        self.ticks = count;
+        self
+    }
+
+    /// Set the function turning tick values into label text
+    pub fn label_formatter<F: Fn(f64) -> String + 'a>(mut self, formatter: F) -> Self {
+        self.formatter = Box::new(formatter);
+        self
+    }
+
+    /// Set the style of the tick labels
+    pub fn label_style<S: Into<TextStyle<'a>>>(mut self, style: S) -> Self {
+        self.label_style = style.into();
+        self
+    }
+
+    /// Set the border of the bar, `None` for no border
+    pub fn border<S: Into<ShapeStyle>>(mut self, style: Option<S>) -> Self {
+        self.border = style.map(Into::into);
+        self
+    }
+}
+
+impl<'b, Coord> PointCollection<'b, Coord> for &'b TypstColorbar<'_, Coord> {
+    type Point = &'b Coord;
+    type IntoIter = &'b [Coord];
+    fn point_iter(self) -> &'b [Coord] {
+        &self.corners
+    }
+}
+
+impl<Coord> Drawable<TypstBackend<'_>> for TypstColorbar<'_, Coord> {
+    fn draw<I: Iterator<Item = BackendCoord>>(
+        &self,
+        mut points: I,
+        backend: &mut TypstBackend,
+        _: (u32, u32),
+    ) -> Result<(), DrawingErrorKind<std::io::Error>> {
+        let (Some(a), Some(b)) = (points.next(), points.next()) else {
+            return Ok(());
+        };
+        let (x0, y0) = (a.0.min(b.0), a.1.min(b.1));
+        let (x1, y1) = (a.0.max(b.0), a.1.max(b.1));
+        let vertical = y1 - y0 > x1 - x0;
+
+        // A gradient needs two stops; a single color degenerates to a solid fill
+        let fill = match self.colors.as_slice() {
+            [] => return Ok(()),
+            [color] => backend.typst_color(*color),
+            colors => format!(
+                "gradient.linear({}, space: rgb, dir: {})",
+                colors
+                    .iter()
+                    .map(|c| backend.typst_color(*c))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                if vertical { "btt" } else { "ltr" }
+            ),
+        };
+        let stroke = border_stroke(backend, self.border);
+
+        let cmd = format!(
+            "  #place(dx: {}, dy: {}, rect(width: {}, height: {}, fill: {}, stroke: {}))",
+            backend.len(x0),
+            backend.len(y0),
+            backend.len(x1 - x0),
+            backend.len(y1 - y0),
+            fill,
+            stroke
+        );
+        let call = backend.begin_call(|| format!("TypstColorbar({:?}, {:?})", (x0, y0), (x1, y1)));
+        backend.write_command(&cmd);
+        backend.note_element("colorbar");
+        backend.log_command(|| {
+            JsonObject::new("colorbar")
+                .point("from", (x0, y0))
+                .point("to", (x1, y1))
+                .number("stops", self.colors.len())
+        })?;
+        let margin = self
+            .border
+            .map_or(0, |b| backend.half_stroke(b.stroke_width));
+        backend.include_bounds((x0, y0), (x1, y1), margin);
+
+        let tick_color = self.label_style.color;
+        for i in 0..self.ticks {
+            let t = if self.ticks == 1 {
+                0.0
+            } else {
+                i as f64 / (self.ticks - 1) as f64
+            };
+            let value = self.range.0 + (self.range.1 - self.range.0) * t;
+            let text = (self.formatter)(value);
+
+            if vertical {
+                let y = y1 - ((y1 - y0) as f64 * t).round() as i32;
+                backend.draw_line((x1, y), (x1 + 4, y), &tick_color)?;
+                let style = self.label_style.pos(Pos::new(HPos::Left, VPos::Center));
+                backend.draw_text(&text, &style, (x1 + 6, y))?;
+            } else {
+                let x = x0 + ((x1 - x0) as f64 * t).round() as i32;
+                backend.draw_line((x, y1), (x, y1 + 4), &tick_color)?;
+                let style = self.label_style.pos(Pos::new(HPos::Center, VPos::Top));
+                backend.draw_text(&text, &style, (x, y1 + 6))?;
+            }
+        }
+        backend.end_call(call)?;
+
+        Ok(())
+    }
+}
+
+/// The name a legend is bound to in [`TypstBackend::detach_legend`] mode
+const LEGEND_BINDING: &str = "chart_legend";
+
+/// A series legend emitted as a Typst `grid` of swatches and labels
+///
+/// Filled styles get a square swatch and stroked styles a line swatch. Since the
+/// labels are laid out by Typst, columns line up regardless of the font, and the
+/// text can be restyled with `show`/`set` rules in the including document.
+///
+/// With [`TypstBackend::detach_legend`], the legend is bound to `chart_legend`
+/// instead of being drawn, and its position is ignored.
+pub struct TypstLegend<'a, Coord> {
+    position: Coord,
+    entries: Vec<(String, ShapeStyle)>,
+    columns: usize,
+    label_style: TextStyle<'a>,
+    padding: u32,
+    background: Option<BackendColor>,
+    border: Option<ShapeStyle>,
+}
+
+impl<'a, Coord> TypstLegend<'a, Coord> {
+    /// Create a new, empty legend with its upper-left corner at `position`
+    pub fn new(position: Coord) -> Self {
+        Self {
+            position,
+            entries: vec![],
+            columns: 1,
+            label_style: ("sans-serif", 12).into(),
+            padding: 5,
+            background: Some(BackendColor {
+                alpha: 1.0,
+                rgb: (255, 255, 255),
+            }),
+            border: Some(plotters::style::BLACK.into()),
+        }
+    }
+
+    /// Append an entry showing `style` next to `label`
+    pub fn entry<T: Into<String>, S: Into<ShapeStyle>>(mut self, label: T, style: S) -> Self {
+        self.entries.push((label.into(), style.into()));
+        self
+    }
+
+    /// Set the number of entries per row
+    pub fn columns(mut self, columns: usize) -> Self {
+        self.columns = columns.max(1);
+        self
+    }
+
+    /// Set the style of the labels
+    pub fn label_style<S: Into<TextStyle<'a>>>(mut self, style: S) -> Self {
+        self.label_style = style.into();
+        self
+    }
+
+    /// Set the padding between the entries and the border in pixels
+    pub fn padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Set the background of the legend, `None` for a transparent legend
+    pub fn background<S: BackendStyle>(mut self, style: Option<&S>) -> Self {
+        self.background = style.map(|s| s.color());
+        self
+    }
+
+    /// Set the border of the legend, `None` for no border
+    pub fn border<S: Into<ShapeStyle>>(mut self, style: Option<S>) -> Self {
+        self.border = style.map(Into::into);
+        self
+    }
+}
+
+impl<'b, Coord> PointCollection<'b, Coord> for &'b TypstLegend<'_, Coord> {
+    type Point = &'b Coord;
+    type IntoIter = std::iter::Once<&'b Coord>;
+    fn point_iter(self) -> std::iter::Once<&'b Coord> {
+        std::iter::once(&self.position)
+    }
+}
+
+impl<Coord> Drawable<TypstBackend<'_>> for TypstLegend<'_, Coord> {
+    fn draw<I: Iterator<Item = BackendCoord>>(
+        &self,
+        mut points: I,
+        backend: &mut TypstBackend,
+        _: (u32, u32),
+    ) -> Result<(), DrawingErrorKind<std::io::Error>> {
+        let Some((x, y)) = points.next() else {
+            return Ok(());
+        };
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+
+        let cells = self
+            .entries
+            .iter()
+            .map(|(label, style)| {
+                let color = backend.typst_color(style.color());
+                let swatch = if style.filled {
+                    format!(
+                        "square(size: {}, fill: {}, stroke: none)",
+                        backend.len(8),
+                        color
+                    )
+                } else {
+                    format!(
+                        "line(length: {}, stroke: {})",
+                        backend.len(20),
+                        backend.stroke(style.stroke_width.max(1), &color)
+                    )
+                };
+                format!("{}, [{}]", swatch, backend.escape_label(label))
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let family = self.label_style.font.get_family();
+        let legend = format!(
+            "box(inset: {}, fill: {}, stroke: {}, {{ set text(size: {}{}, font: \"{}\"{}{}); grid(columns: {}, column-gutter: {gutter}, row-gutter: {gutter}, align: horizon, {}) }})",
+            backend.len(self.padding),
+            background_fill(backend, self.background),
+            border_stroke(backend, self.border),
+            backend.len(self.label_style.font.get_size() / 1.24),
+            backend.text_fill(self.label_style.color),
+            typst_font_family(family.as_str()),
+            TEXT_DEFAULTS,
+            backend.text_features(),
+            2 * self.columns.min(self.entries.len()),
+            cells,
+            gutter = backend.len(5)
+        );
+        let call = backend.begin_call(|| format!("TypstLegend({:?})", (x, y)));
+        if backend.options.detached_legend {
+            backend.write_binding(LEGEND_BINDING, &format!("[#{}]", legend));
+        } else {
+            let cmd = format!(
+                "  #place(dx: {}, dy: {}, {})",
+                backend.len(x),
+                backend.len(y),
+                legend
+            );
+            backend.write_command(&cmd);
+        }
+        backend.note_element("legend");
+        backend.set_anchor("legend", (x, y));
+        backend.note_font(typst_font_family(family.as_str()));
+        backend.log_command(|| {
+            let labels = self.entries.iter().map(|(label, _)| label.as_str());
+            JsonObject::new("legend")
+                .point("at", (x, y))
+                .strings("labels", labels)
+        })?;
+        // Estimated from the widest label in each column, as laid out by Typst
+        let font_size = self.label_style.font.get_size() / 1.24;
+        let columns = self.columns.min(self.entries.len());
+        let rows = self.entries.len().div_ceil(columns);
+        let width: f64 = (0..columns)
+            .map(|column| {
+                let label = self.entries[column..]
+                    .iter()
+                    .step_by(columns)
+                    .map(|(label, _)| label.chars().count())
+                    .max()
+                    .unwrap_or(0);
+                20.0 + 5.0 + label as f64 * font_size * 0.6
+            })
+            .sum::<f64>()
+            + 5.0 * (columns - 1) as f64;
+        let height = rows as f64 * font_size.max(8.0) + 5.0 * (rows - 1) as f64;
+        // The inset grows the box to the right and down from its placed corner
+        let inset = 2 * self.padding as i32;
+        let margin = self
+            .border
+            .map_or(0, |b| backend.half_stroke(b.stroke_width));
+        let (right, bottom) = (
+            x + width.ceil() as i32 + inset,
+            y + height.ceil() as i32 + inset,
+        );
+        if !backend.options.detached_legend {
+            backend.include_bounds((x, y), (right, bottom), margin);
+        }
+        // Only the box of the whole legend is known
+        for (label, _) in &self.entries {
+            backend.note_label(label, (x, y, right, bottom));
+        }
+        backend.end_call(call)?;
+        Ok(())
+    }
+}
+
+/// Marks the start or end of a group of elements revealed from a given slide step
+///
+/// Everything drawn between [`TypstReveal::begin`] and [`TypstReveal::end`] is wrapped
+/// in `#only("<step>")[..]`, as understood by slide packages such as touying or
+/// polylux. The function name can be changed with
+/// [`TypstBackend::reveal_function`]. Beginning a group closes the one before, and
+/// an open group is closed when the chart is presented.
+pub struct TypstReveal {
+    step: RevealStep,
+}
+
+enum RevealStep {
+    Begin(String),
+    Next,
+    End,
+}
+
+impl TypstReveal {
+    /// Start a group shown from `step` on, e.g. `"2-"` or `"3"`
+    pub fn begin<S: Into<String>>(step: S) -> Self {
+        Self {
+            step: RevealStep::Begin(step.into()),
+        }
+    }
+
+    /// Start a group shown from the next automatic step on: the first such group
+    /// appears on slide 2, after the frame drawn outside of any group, the next on
+    /// slide 3 and so on
+    pub fn next() -> Self {
+        Self {
+            step: RevealStep::Next,
+        }
+    }
+
+    /// End the open group
+    pub fn end() -> Self {
+        Self {
+            step: RevealStep::End,
+        }
+    }
+}
+
+impl<'b, Coord: 'b> PointCollection<'b, Coord> for &'b TypstReveal {
+    type Point = &'b Coord;
+    type IntoIter = std::iter::Empty<&'b Coord>;
+    fn point_iter(self) -> std::iter::Empty<&'b Coord> {
+        std::iter::empty()
+    }
+}
+
+impl Drawable<TypstBackend<'_>> for TypstReveal {
+    fn draw<I: Iterator<Item = BackendCoord>>(
+        &self,
+        _: I,
+        backend: &mut TypstBackend,
+        _: (u32, u32),
+    ) -> Result<(), DrawingErrorKind<std::io::Error>> {
+        match &self.step {
+            RevealStep::Begin(step) => backend.begin_step(step),
+            RevealStep::Next => backend.begin_next_step(),
+            RevealStep::End => backend.end_step(),
+        }
+        Ok(())
+    }
+}
+
+/// An element of a series drawn by [`RevealSeries::draw_series_step`], framed by
+/// the markers of its reveal group
+enum Stepped<E> {
+    Begin,
+    Element(E),
+    End,
+}
+
+impl<'b, Coord, B: CoordMapper, E> PointCollection<'b, Coord, B> for &'b Stepped<E>
+where
+    &'b E: PointCollection<'b, Coord, B>,
+{
+    type Point = <&'b E as PointCollection<'b, Coord, B>>::Point;
+    type IntoIter = std::iter::Flatten<
+        std::option::IntoIter<<&'b E as PointCollection<'b, Coord, B>>::IntoIter>,
+    >;
+    fn point_iter(self) -> Self::IntoIter {
+        match self {
+            Stepped::Element(element) => Some(element.point_iter()),
+            _ => None,
+        }
+        .into_iter()
+        .flatten()
+    }
+}
+
+impl<'a, B: CoordMapper, E: Drawable<TypstBackend<'a>, B>> Drawable<TypstBackend<'a>, B>
+    for Stepped<E>
+{
+    fn draw<I: Iterator<Item = B::Output>>(
+        &self,
+        points: I,
+        backend: &mut TypstBackend<'a>,
+        parent_dim: (u32, u32),
+    ) -> Result<(), DrawingErrorKind<std::io::Error>> {
+        match self {
+            Stepped::Begin => backend.begin_next_step(),
+            Stepped::Element(element) => element.draw(points, backend, parent_dim)?,
+            Stepped::End => backend.end_step(),
+        }
+        Ok(())
+    }
+}
+
+/// Draw each series of a chart as its own reveal group
+pub trait RevealSeries<'a, 'b, CT: CoordTranslate> {
+    /// Like [`ChartContext::draw_series`], but wrap the series in a group shown from
+    /// the next automatic step on (see [`TypstReveal::next`]), so successive series
+    /// build up one slide at a time
+    fn draw_series_step<B, E, S>(
+        &mut self,
+        series: S,
+    ) -> Result<&mut SeriesAnno<'a, TypstBackend<'b>>, DrawingAreaErrorKind<std::io::Error>>
+    where
+        B: CoordMapper,
+        for<'c> &'c E: PointCollection<'c, CT::From, B>,
+        E: Drawable<TypstBackend<'b>, B>,
+        S: IntoIterator<Item = E>;
+}
+
+impl<'a, 'b, CT: CoordTranslate> RevealSeries<'a, 'b, CT>
+    for ChartContext<'a, TypstBackend<'b>, CT>
+{
+    fn draw_series_step<B, E, S>(
+        &mut self,
+        series: S,
+    ) -> Result<&mut SeriesAnno<'a, TypstBackend<'b>>, DrawingAreaErrorKind<std::io::Error>>
+    where
+        B: CoordMapper,
+        for<'c> &'c E: PointCollection<'c, CT::From, B>,
+        E: Drawable<TypstBackend<'b>, B>,
+        S: IntoIterator<Item = E>,
+    {
+        let elements = std::iter::once(Stepped::Begin)
+            .chain(series.into_iter().map(Stepped::Element))
+            .chain(std::iter::once(Stepped::End));
+        self.draw_series::<B, Stepped<E>, Stepped<E>, _>(elements)
+    }
+}
+
+/// Circles of one size and style around many points, drawn in a single command
+/// with [`TypstBackend::draw_circles`]
+pub struct TypstCircles<Coord> {
+    centers: Vec<Coord>,
+    radius: u32,
+    style: ShapeStyle,
+}
+
+impl<Coord> TypstCircles<Coord> {
+    /// Create circles of `radius` pixels around each of `centers`
+    pub fn new<I: IntoIterator<Item = Coord>, S: Into<ShapeStyle>>(
+        centers: I,
+        radius: u32,
+        style: S,
+    ) -> Self {
+        Self {
+            centers: centers.into_iter().collect(),
+            radius,
+            style: style.into(),
+        }
+    }
+}
+
+impl<'b, Coord> PointCollection<'b, Coord> for &'b TypstCircles<Coord> {
+    type Point = &'b Coord;
+    type IntoIter = std::slice::Iter<'b, Coord>;
+    fn point_iter(self) -> Self::IntoIter {
+        self.centers.iter()
+    }
+}
+
+impl<Coord> Drawable<TypstBackend<'_>> for TypstCircles<Coord> {
+    fn draw<I: Iterator<Item = BackendCoord>>(
+        &self,
+        points: I,
+        backend: &mut TypstBackend,
+        _: (u32, u32),
+    ) -> Result<(), DrawingErrorKind<std::io::Error>> {
+        backend.draw_circles(points, self.radius, &self.style, self.style.filled)
+    }
+}
+
+/// Rects of one style, such as the bars of a bar chart or the cells of a heat map,
+/// drawn in a single command with [`TypstBackend::draw_rects`]
+pub struct TypstRects<Coord> {
+    /// The upper left and bottom right corners of each rect, one after the other
+    corners: Vec<Coord>,
+    style: ShapeStyle,
+}
+
+impl<Coord> TypstRects<Coord> {
+    /// Create rects between each pair of opposite corners of `rects`
+    pub fn new<I: IntoIterator<Item = (Coord, Coord)>, S: Into<ShapeStyle>>(
+        rects: I,
+        style: S,
+    ) -> Self {
+        Self {
+            corners: rects.into_iter().flat_map(|(a, b)| [a, b]).collect(),
+            style: style.into(),
+        }
+    }
+}
+
+impl<'b, Coord> PointCollection<'b, Coord> for &'b TypstRects<Coord> {
+    type Point = &'b Coord;
+    type IntoIter = std::slice::Iter<'b, Coord>;
+    fn point_iter(self) -> Self::IntoIter {
+        self.corners.iter()
+    }
+}
+
+impl<Coord> Drawable<TypstBackend<'_>> for TypstRects<Coord> {
+    fn draw<I: Iterator<Item = BackendCoord>>(
+        &self,
+        points: I,
+        backend: &mut TypstBackend,
+        _: (u32, u32),
+    ) -> Result<(), DrawingErrorKind<std::io::Error>> {
+        let corners: Vec<_> = points.collect();
+        let rects = corners.chunks_exact(2).map(|pair| {
+            let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+            ((x0.min(x1), y0.min(y1)), (x0.max(x1), y0.max(y1)))
+        });
+        backend.draw_rects(rects, &self.style, self.style.filled)
+    }
+}
+
+/// Draw many shapes of one style onto a chart in a single command each
+pub trait BulkSeries<CT: CoordTranslate> {
+    /// Draw a circle of `radius` pixels around each of `points`, see [`TypstCircles`]
+    fn draw_circles<I: IntoIterator<Item = CT::From>, S: Into<ShapeStyle>>(
+        &self,
+        points: I,
+        radius: u32,
+        style: S,
+    ) -> Result<(), DrawingAreaErrorKind<std::io::Error>>;
+
+    /// Draw a rect between each pair of opposite corners, see [`TypstRects`]
+    fn draw_rects<I: IntoIterator<Item = (CT::From, CT::From)>, S: Into<ShapeStyle>>(
+        &self,
+        rects: I,
+        style: S,
+    ) -> Result<(), DrawingAreaErrorKind<std::io::Error>>;
+}
+
+impl<CT: CoordTranslate> BulkSeries<CT> for ChartContext<'_, TypstBackend<'_>, CT> {
+    fn draw_circles<I: IntoIterator<Item = CT::From>, S: Into<ShapeStyle>>(
+        &self,
+        points: I,
+        radius: u32,
+        style: S,
+    ) -> Result<(), DrawingAreaErrorKind<std::io::Error>> {
+        self.plotting_area()
+            .draw(&TypstCircles::new(points, radius, style))
+    }
+
+    fn draw_rects<I: IntoIterator<Item = (CT::From, CT::From)>, S: Into<ShapeStyle>>(
+        &self,
+        rects: I,
+        style: S,
+    ) -> Result<(), DrawingAreaErrorKind<std::io::Error>> {
+        self.plotting_area().draw(&TypstRects::new(rects, style))
+    }
+}
+
+/// Marks the start or end of a region clipped to a drawing area
+///
+/// Drawn on a [`DrawingArea`] with [`Shift`] coordinates, [`TypstClip::begin`]
+/// opens a `box(clip: true)` covering the area: nothing drawn until the matching
+/// [`TypstClip::end`] shows outside of it, even if its coordinates overflow. Regions
+/// nest, and the ones still open are closed when the chart is presented. To clip
+/// the series of a chart, begin the region on
+/// `chart.plotting_area().strip_coord_spec()`; see also [`ClipArea::clipped`].
+pub struct TypstClip {
+    begin: bool,
+}
+
+impl TypstClip {
+    /// Start a region clipped to the area the element is drawn on
+    pub fn begin() -> Self {
+        Self { begin: true }
+    }
+
+    /// End the innermost region
+    pub fn end() -> Self {
+        Self { begin: false }
+    }
+}
+
+/// The upper left corner of the area a [`TypstClip`] is drawn on
+const AREA_ORIGIN: (i32, i32) = (0, 0);
+
+impl<'b> PointCollection<'b, (i32, i32)> for &'b TypstClip {
+    type Point = &'b (i32, i32);
+    type IntoIter = std::option::IntoIter<&'b (i32, i32)>;
+    fn point_iter(self) -> Self::IntoIter {
+        self.begin.then_some(&AREA_ORIGIN).into_iter()
+    }
+}
+
+impl Drawable<TypstBackend<'_>> for TypstClip {
+    fn draw<I: Iterator<Item = BackendCoord>>(
+        &self,
+        mut points: I,
+        backend: &mut TypstBackend,
+        (width, height): (u32, u32),
+    ) -> Result<(), DrawingErrorKind<std::io::Error>> {
+        match points.next() {
+            Some((x, y)) if self.begin => {
+                backend.push_clip((x, y), (x + width as i32, y + height as i32))
+            }
+            _ => backend.pop_clip(),
+        }
+        Ok(())
+    }
+}
+
+/// Draw into a drawing area without leaking into its surroundings
+pub trait ClipArea {
+    /// Run `draw` on the area within a region clipped to it, see [`TypstClip`]
+    fn clipped<R, F: FnOnce(&Self) -> R>(
+        &self,
+        draw: F,
+    ) -> Result<R, DrawingAreaErrorKind<std::io::Error>>;
+}
+
+impl ClipArea for DrawingArea<TypstBackend<'_>, Shift> {
+    fn clipped<R, F: FnOnce(&Self) -> R>(
+        &self,
+        draw: F,
+    ) -> Result<R, DrawingAreaErrorKind<std::io::Error>> {
+        self.draw(&TypstClip::begin())?;
+        let result = draw(self);
+        self.draw(&TypstClip::end())?;
+        Ok(result)
+    }
+}
+
+/// Marks the start of a logical part of the chart with a comment
+///
+/// The comment reads `// section: <label>`, so people and scripts editing a large
+/// generated file can find e.g. the mesh or one series. Nothing is drawn.
+pub struct TypstSection {
+    label: Option<String>,
+}
+
+impl TypstSection {
+    /// Mark the start of the part `label`, such as `"mesh"` or `"legend"`
+    pub fn new<S: Into<String>>(label: S) -> Self {
+        Self {
+            label: Some(label.into()),
+        }
+    }
+
+    /// Mark the start of the next series, labelled `series 1`, `series 2` and so on
+    pub fn series() -> Self {
+        Self { label: None }
+    }
+}
+
+impl<'b, Coord: 'b> PointCollection<'b, Coord> for &'b TypstSection {
+    type Point = &'b Coord;
+    type IntoIter = std::iter::Empty<&'b Coord>;
+    fn point_iter(self) -> std::iter::Empty<&'b Coord> {
+        std::iter::empty()
+    }
+}
+
+impl Drawable<TypstBackend<'_>> for TypstSection {
+    fn draw<I: Iterator<Item = BackendCoord>>(
+        &self,
+        _: I,
+        backend: &mut TypstBackend,
+        _: (u32, u32),
+    ) -> Result<(), DrawingErrorKind<std::io::Error>> {
+        backend.write_section(self.label.as_deref());
+        Ok(())
+    }
+}
+
+/// Names a point of the chart, such as the origin or the end of an axis, in
+/// [`TypstBackend::named_anchors`] mode; nothing is drawn
+pub struct TypstAnchor<Coord> {
+    name: String,
+    position: Coord,
+}
+
+impl<Coord> TypstAnchor<Coord> {
+    /// Name the point at `position` `name`, replacing a point of the same name
+    pub fn new<S: Into<String>>(name: S, position: Coord) -> Self {
+        Self {
+            name: name.into(),
+            position,
+        }
+    }
+}
+
+impl<'b, Coord> PointCollection<'b, Coord> for &'b TypstAnchor<Coord> {
+    type Point = &'b Coord;
+    type IntoIter = std::iter::Once<&'b Coord>;
+    fn point_iter(self) -> std::iter::Once<&'b Coord> {
+        std::iter::once(&self.position)
+    }
+}
+
+impl<Coord> Drawable<TypstBackend<'_>> for TypstAnchor<Coord> {
+    fn draw<I: Iterator<Item = BackendCoord>>(
+        &self,
+        mut points: I,
+        backend: &mut TypstBackend,
+        _: (u32, u32),
+    ) -> Result<(), DrawingErrorKind<std::io::Error>> {
+        if let Some(point) = points.next() {
+            backend.set_anchor(&self.name, point);
+        }
+        Ok(())
+    }
+}
+
+/// Emits `#let chart_coord(x, y) = (..)`, the function mapping data coordinates
+/// of the drawing area to offsets from the upper left corner of the canvas, so the
+/// including document can place its own marks at data coordinates; nothing is drawn
+///
+/// The transform is the one taking the two corners given to [`Self::new`] to the
+/// canvas, so it holds for linear axes, up to the rounding of the corners to whole
+/// pixels; corners far apart, such as the ends of the axis ranges, keep the error
+/// small.
+///
+/// ```ignore
+/// chart
+///     .plotting_area()
+///     .draw(&TypstCoordMap::new((0.0, 0.0), (10.0, 100.0)))?;
+/// // In the document: #place(dx: chart_coord(2.5, 40).at(0), ..)
+/// ```
+pub struct TypstCoordMap<X, Y> {
+    corners: [(X, Y); 2],
+}
+
+impl<X, Y> TypstCoordMap<X, Y> {
+    /// Derive the transform from the data points `from` and `to`, which must differ
+    /// along both axes
+    pub fn new(from: (X, Y), to: (X, Y)) -> Self {
+        Self {
+            corners: [from, to],
+        }
+    }
+}
+
+impl<'b, X: 'b, Y: 'b> PointCollection<'b, (X, Y)> for &'b TypstCoordMap<X, Y> {
+    type Point = &'b (X, Y);
+    type IntoIter = std::slice::Iter<'b, (X, Y)>;
+    fn point_iter(self) -> std::slice::Iter<'b, (X, Y)> {
+        self.corners.iter()
+    }
+}
+
+impl<X: Clone + Into<f64>, Y: Clone + Into<f64>> Drawable<TypstBackend<'_>>
+    for TypstCoordMap<X, Y>
+{
+    fn draw<I: Iterator<Item = BackendCoord>>(
+        &self,
+        mut points: I,
+        backend: &mut TypstBackend,
+        _: (u32, u32),
+    ) -> Result<(), DrawingErrorKind<std::io::Error>> {
+        let (Some(from), Some(to)) = (points.next(), points.next()) else {
+            return Ok(());
+        };
+        let data = self.corners.clone().map(|(x, y)| (x.into(), y.into()));
+        backend
+            .set_coord_map(data, [from, to])
+            .map_err(DrawingErrorKind::DrawingError)
+    }
+}
+
+/// Marks the start of the commands of a layer in
+/// [`TypstBackend::split_layers`] mode; nothing is drawn otherwise
+///
+/// Layers are switched outside of clip regions and reveal groups.
+pub struct TypstLayer {
+    data: bool,
+}
+
+impl TypstLayer {
+    /// Put what is drawn next, such as the axes and mesh, in the `chart_frame` layer
+    pub fn frame() -> Self {
+        Self { data: false }
+    }
+
+    /// Put what is drawn next, such as the data series, in the `chart_data` layer
+    pub fn data() -> Self {
+        Self { data: true }
+    }
+}
+
+impl<'b, Coord: 'b> PointCollection<'b, Coord> for &'b TypstLayer {
+    type Point = &'b Coord;
+    type IntoIter = std::iter::Empty<&'b Coord>;
+    fn point_iter(self) -> std::iter::Empty<&'b Coord> {
+        std::iter::empty()
+    }
+}
+
+impl Drawable<TypstBackend<'_>> for TypstLayer {
+    fn draw<I: Iterator<Item = BackendCoord>>(
+        &self,
+        _: I,
+        backend: &mut TypstBackend,
+        _: (u32, u32),
+    ) -> Result<(), DrawingErrorKind<std::io::Error>> {
+        backend
+            .begin_layer(self.data)
+            .map_err(DrawingErrorKind::DrawingError)
+    }
+}
+
+/// A value that can be written as a Typst literal
+pub trait TypstValue {
+    /// The Typst source of the value
+    fn to_typst(&self) -> String;
+}
+
+macro_rules! impl_typst_value_int {
+    ($($t:ty),*) => {
+        $(impl TypstValue for $t {
+            fn to_typst(&self) -> String {
+                self.to_string()
+            }
+        })*
+    };
+}
+
+impl_typst_value_int!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+macro_rules! impl_typst_value_float {
+    ($($t:ty),*) => {
+        $(impl TypstValue for $t {
+            fn to_typst(&self) -> String {
+                if self.is_nan() {
+                    "calc.nan".to_string()
+                } else if self.is_infinite() {
+                    format!("{}calc.inf", if *self < 0.0 { "-" } else { "" })
+                } else if self.fract() == 0.0 {
+                    // Keep the value a float, `1` would read back as an integer
+                    if self.abs() < 1e15 {
+                        format!("{}.0", self)
+                    } else {
+                        format!("{:e}", self)
+                    }
+                } else {
+                    self.to_string()
+                }
+            }
+        })*
+    };
+}
+
+impl_typst_value_float!(f32, f64);
+
+impl TypstValue for bool {
+    fn to_typst(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl TypstValue for str {
+    fn to_typst(&self) -> String {
+        format!("\"{}\"", self.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+impl TypstValue for String {
+    fn to_typst(&self) -> String {
+        self.as_str().to_typst()
+    }
+}
+
+impl<T: TypstValue + ?Sized> TypstValue for &T {
+    fn to_typst(&self) -> String {
+        (**self).to_typst()
+    }
+}
+
+/// Format a sequence of Typst sources as an array, which needs a trailing comma
+/// when it has a single item
+pub(crate) fn typst_array<I: IntoIterator<Item = String>>(items: I) -> String {
+    let items: Vec<String> = items.into_iter().collect();
+    match items.len() {
+        1 => format!("({},)", items[0]),
+        _ => format!("({})", items.join(", ")),
+    }
+}
+
+/// Exports the data of a series as a Typst binding
+///
+/// Drawing this element emits `#let <name> = ((x, y), ...)` before the canvas, so
+/// a document can `#import` the numbers of the chart next to including it, e.g.
+/// to write `n = #data.len()` in a caption. Nothing is drawn on the canvas.
+pub struct TypstData {
+    name: String,
+    points: Vec<(String, String)>,
+}
+
+impl TypstData {
+    /// Create a new binding
+    /// - `name` The name of the binding; characters not allowed in a Typst
+    ///   identifier are replaced by `_`
+    /// - `points` The data of the series
+    pub fn new<X: TypstValue, Y: TypstValue, I: IntoIterator<Item = (X, Y)>>(
+        name: &str,
+        points: I,
+    ) -> Self {
+        Self {
+            name: typst_identifier(name),
+            points: points
+                .into_iter()
+                .map(|(x, y)| (x.to_typst(), y.to_typst()))
+                .collect(),
+        }
+    }
+}
+
+impl<'b, Coord: 'b> PointCollection<'b, Coord> for &'b TypstData {
+    type Point = &'b Coord;
+    type IntoIter = std::iter::Empty<&'b Coord>;
+    fn point_iter(self) -> std::iter::Empty<&'b Coord> {
+        std::iter::empty()
+    }
+}
+
+impl Drawable<TypstBackend<'_>> for TypstData {
+    fn draw<I: Iterator<Item = BackendCoord>>(
+        &self,
+        _: I,
+        backend: &mut TypstBackend,
+        _: (u32, u32),
+    ) -> Result<(), DrawingErrorKind<std::io::Error>> {
+        let value = typst_array(self.points.iter().map(|(x, y)| format!("({}, {})", x, y)));
+        backend.write_binding(&self.name, &value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        BulkSeries, ClipArea, MarkerShape, RevealSeries, TypstAnchor, TypstArrow, TypstCallout,
+        TypstClip, TypstColorbar, TypstCoordMap, TypstData, TypstEllipse, TypstLayer, TypstLegend,
+        TypstMarker, TypstReveal, TypstSection, TypstText,
+    };
+    use crate::test::checked_save_file;
+    use crate::TypstBackend;
+    use plotters::prelude::*;
+    use plotters::style::text_anchor::{HPos, Pos, VPos};
+
+    #[test]
+    fn test_draw_ellipse() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (300, 300)).into_drawing_area();
+
+            root.draw(&TypstEllipse::new((150, 150), (80, 40), RED.filled()).rotate(30.0))
+                .unwrap();
+            root.draw(&TypstEllipse::new((50, 50), (20, 10), BLUE))
+                .unwrap();
+        }
+
+        checked_save_file("test_draw_ellipse", &content);
+        assert!(content.contains(
+            "#place(dx: 110pt, dy: 130pt, rotate(30deg, ellipse(width: 80pt, height: 40pt, fill: rgb(255, 0, 0), stroke: none)))"
+        ));
+        assert!(content.contains(
+            "#place(dx: 40pt, dy: 45pt, ellipse(width: 20pt, height: 10pt, fill: none, stroke: 1pt + rgb(0, 0, 255)))"
+        ));
+    }
+
+    #[test]
+    fn test_bulk_series() {
+        let points: Vec<_> = (0..50)
+            .map(|i| (i as f64 / 50.0, (i as f64 * 0.754_878).fract()))
+            .collect();
+        let bars: Vec<_> = (0..5)
+            .map(|i| {
+                (
+                    (i as f64 / 5.0, 0.0),
+                    ((i as f64 + 0.8) / 5.0, 0.1 * i as f64),
+                )
+            })
+            .collect();
+        let draw = |backend: TypstBackend, bulk: bool| {
+            let root = backend.into_drawing_area();
+            let chart = ChartBuilder::on(&root)
+                .margin(10)
+                .build_cartesian_2d(0.0..1.0, 0.0..1.0)
+                .unwrap();
+            if bulk {
+                chart
+                    .draw_circles(points.clone(), 3, BLUE.filled())
+                    .unwrap();
+                chart
+                    .draw_circles(points.clone(), 3, BLUE.filled())
+                    .unwrap();
+                chart.draw_rects(bars.clone(), GREEN.filled()).unwrap();
+            } else {
+                let plot = chart.plotting_area();
+                for _ in 0..2 {
+                    for &p in &points {
+                        plot.draw(&Circle::new(p, 3, BLUE.filled())).unwrap();
+                    }
+                }
+                for &(a, b) in &bars {
+                    plot.draw(&Rectangle::new([a, b], GREEN.filled())).unwrap();
+                }
+            }
+            root.present().unwrap();
+        };
+
+        let backend = TypstBackend::new_buffered((200, 120));
+        let output = backend.output().unwrap();
+        draw(backend, true);
+        let content = output.take();
+        checked_save_file("test_bulk_series", &content);
+        assert_eq!(content.matches("#let bulk-").count(), 2);
+        assert!(content.contains(
+            "#let bulk-2 = (w, h) => rect(width: w * 1pt, height: h * 1pt, fill: rgb(0, 255, 0), stroke: none)"
+        ));
+        assert_eq!(content.matches("  #for (x, y) in ((7, ").count(), 2);
+        assert!(content.contains(
+            ") { place(dx: x * 1pt, dy: y * 1pt, bulk-1) }\n  #for (x, y, w, h) in ((10, "
+        ));
+
+        #[cfg(feature = "test-util")]
+        {
+            let single = TypstBackend::new_buffered((200, 120));
+            let output = single.output().unwrap();
+            draw(single, false);
+            assert_eq!(
+                crate::test_util::render(&content),
+                crate::test_util::render(&output.take())
+            );
+        }
+    }
+
+    #[test]
+    fn test_draw_arrow() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (300, 300)).into_drawing_area();
+
+            root.draw(&TypstArrow::new((10, 100), (110, 100), BLACK).head_size(10))
+                .unwrap();
+            root.draw(&TypstArrow::new(
+                (150, 150),
+                (150, 250),
+                RED.stroke_width(3),
+            ))
+            .unwrap();
+        }
+
+        checked_save_file("test_draw_arrow", &content);
+        assert!(content.contains("#place(dx: 10pt, dy: 100pt, line(length: 90pt, angle: 0deg"));
+        assert!(content.contains(
+            "#place(polygon(fill: rgb(0, 0, 0), stroke: none, (110pt, 100pt), (100pt, 105pt), (100pt, 95pt)))"
+        ));
+        // The default head grows with the stroke width
+        assert!(content.contains("line(length: 88pt, angle: 90deg, stroke: 3pt + rgb(255, 0, 0))"));
+
+        // The head leaves out the stroke like any other filled shape in compact mode
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (300, 300))
+                .compact()
+                .into_drawing_area();
+            root.draw(&TypstArrow::new((10, 100), (110, 100), BLACK).head_size(10))
+                .unwrap();
+        }
+        assert!(content.contains(
+            "#place(polygon(fill: rgb(0, 0, 0), (110pt, 100pt), (100pt, 105pt), (100pt, 95pt)))"
+        ));
+    }
+
+    #[test]
+    fn test_draw_callout() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (300, 200)).into_drawing_area();
+            let chart = ChartBuilder::on(&root)
+                .build_cartesian_2d(0.0..10.0, 0.0..10.0)
+                .unwrap();
+
+            chart
+                .plotting_area()
+                .draw(&TypstCallout::new((5.0, 5.0), (40, -40), "Peak #1"))
+                .unwrap();
+            chart
+                .plotting_area()
+                .draw(
+                    &TypstCallout::new((10.0, 10.0), (20, -20), "edge")
+                        .padding(2)
+                        .background(None::<&RGBColor>)
+                        .border(None::<ShapeStyle>)
+                        .leader(RED),
+                )
+                .unwrap();
+        }
+
+        checked_save_file("test_draw_callout", &content);
+        assert!(content.contains("[Peak \\#1]"));
+        assert!(content.contains("calc.min(300pt - m.width, 189pt - m.width / 2)"));
+        assert!(content.contains("line(start: (149pt, 100pt)"));
+        assert!(content.contains("box(inset: 2pt, fill: none, stroke: none"));
+        assert!(content.contains("stroke: 1pt + rgb(255, 0, 0)"));
+    }
+
+    #[test]
+    fn test_draw_markers() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (300, 100)).into_drawing_area();
+
+            for (i, shape) in [
+                MarkerShape::Star,
+                MarkerShape::Diamond,
+                MarkerShape::Triangle,
+                MarkerShape::Cross,
+                MarkerShape::Plus,
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                let x = 30 + 60 * i as i32;
+                root.draw(&TypstMarker::new((x, 50), 10, shape, RED.filled()))
+                    .unwrap();
+            }
+        }
+
+        checked_save_file("test_draw_markers", &content);
+        assert_eq!(content.matches("polygon(").count(), 3);
+        assert!(content.contains(
+            "#place(dx: 90pt, dy: 50pt, polygon(fill: rgb(255, 0, 0), stroke: none, (0pt, -10pt), (10pt, 0pt), (0pt, 10pt), (-10pt, 0pt)))"
+        ));
+        assert!(content.contains(
+            "#place(dx: 270pt, dy: 50pt, { place(line(start: (0pt, -10pt), end: (0pt, 10pt), stroke: 1pt + rgb(255, 0, 0)));"
+        ));
+        assert!(content.contains("start: (7.07pt, -7.07pt)"));
+    }
+
+    #[test]
+    fn test_draw_colorbar() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (300, 300)).into_drawing_area();
+
+            root.draw(&TypstColorbar::new(
+                [(250, 20), (270, 280)],
+                [BLUE, GREEN, RED],
+                (0.0, 1.0),
+            ))
+            .unwrap();
+            root.draw(
+                &TypstColorbar::new([(20, 250), (200, 270)], [BLACK, WHITE], (-5.0, 5.0))
+                    .ticks(3)
+                    .label_formatter(|v| format!("{:+}", v))
+                    .border(None::<ShapeStyle>),
+            )
+            .unwrap();
+        }
+
+        checked_save_file("test_draw_colorbar", &content);
+        assert!(content.contains(
+            "rect(width: 20pt, height: 260pt, fill: gradient.linear(rgb(0, 0, 255), rgb(0, 255, 0), rgb(255, 0, 0), space: rgb, dir: btt), stroke: 1pt + rgb(0, 0, 0))"
+        ));
+        assert!(content.contains("dir: ltr), stroke: none"));
+        assert!(content.contains("0.25"));
+        assert!(content.contains("+0"));
+        assert!(content.contains("+5"));
+        assert!(content.contains("-5"));
+    }
+
+    #[test]
+    fn test_draw_legend() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (300, 200)).into_drawing_area();
+
+            root.draw(
+                &TypstLegend::new((200, 10))
+                    .entry("sin(x)", RED)
+                    .entry("cos(x)", BLUE.stroke_width(2))
+                    .entry("#samples", GREEN.filled()),
+            )
+            .unwrap();
+            root.draw(
+                &TypstLegend::new((10, 150))
+                    .entry("a", RED)
+                    .entry("b", BLUE)
+                    .entry("c", GREEN)
+                    .columns(2)
+                    .background(None::<&RGBColor>),
+            )
+            .unwrap();
+        }
+
+        checked_save_file("test_draw_legend", &content);
+        assert!(content.contains("grid(columns: 2, column-gutter: 5pt"));
+        assert!(content.contains("line(length: 20pt, stroke: 1pt + rgb(255, 0, 0)), [sin(x)]"));
+        assert!(content.contains("line(length: 20pt, stroke: 2pt + rgb(0, 0, 255)), [cos(x)]"));
+        assert!(
+            content.contains("square(size: 8pt, fill: rgb(0, 255, 0), stroke: none), [\\#samples]")
+        );
+        assert!(content.contains("fill: none, stroke: 1pt + rgb(0, 0, 0), { set text("));
+        assert!(content.contains("grid(columns: 4,"));
+    }
+
+    #[test]
+    fn test_detached_legend() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (100, 50))
+                .detach_legend()
+                .namespace("sales")
+                .into_drawing_area();
+            root.draw(&TypstLegend::new((60, 10)).entry("north", RED))
+                .unwrap();
+        }
+        // The document places the legend below the chart
+        content.push_str("#sales_chart_legend\n");
+
+        checked_save_file("test_detached_legend", &content);
+        assert!(content.starts_with("#let sales_chart_legend = [#box(inset: 5pt"));
+        assert!(!content.contains("#place(dx: 60pt"));
+    }
+
+    #[test]
+    fn test_reveal_groups() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (200, 100))
+                .reveal_function("uncover")
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            let mut chart = ChartBuilder::on(&root)
+                .build_cartesian_2d(0..10, 0..10)
+                .unwrap();
+
+            chart
+                .plotting_area()
+                .draw(&TypstReveal::begin("2-"))
+                .unwrap();
+            chart
+                .draw_series(LineSeries::new((0..10).map(|x| (x, x)), RED))
+                .unwrap();
+            chart
+                .plotting_area()
+                .draw(&TypstReveal::begin("3"))
+                .unwrap();
+            chart
+                .draw_series(std::iter::once(Circle::new((5, 5), 5, BLUE.filled())))
+                .unwrap();
+        }
+
+        // Slide packages define the guard, show every group for the snapshot
+        checked_save_file(
+            "test_reveal_groups",
+            &format!("#let uncover(step, body) = body\n{}", content),
+        );
+        assert!(content.contains("  #uncover(\"2-\")[\n  #place(dx: 0pt, dy: 99pt, line("));
+        assert!(content.contains("  ]\n  #uncover(\"3\")[\n  #place(dx: 94pt, dy: 45pt, circle("));
+        assert!(content.ends_with("  ]\n]\n"));
+    }
+
+    #[test]
+    fn test_clips_across_steps() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (200, 100)).into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            let (left, right) = root.split_horizontally(100);
+            root.draw(&TypstReveal::begin("2-")).unwrap();
+            left.draw(&TypstClip::begin()).unwrap();
+            left.draw(&Circle::new((100, 50), 40, RED.filled()))
+                .unwrap();
+            let inner = left.margin(20, 20, 20, 20);
+            inner.draw(&TypstClip::begin()).unwrap();
+            // A step change within both regions
+            root.draw(&TypstReveal::begin("3-")).unwrap();
+            inner.draw(&Circle::new((0, 0), 30, BLUE.filled())).unwrap();
+            inner.draw(&TypstClip::end()).unwrap();
+            root.draw(&TypstReveal::end()).unwrap();
+            left.draw(&Circle::new((0, 100), 30, GREEN.filled()))
+                .unwrap();
+            left.draw(&TypstClip::end()).unwrap();
+            // A step begun within a region outlasts it
+            right.draw(&TypstClip::begin()).unwrap();
+            root.draw(&TypstReveal::begin("4-")).unwrap();
+            right
+                .draw(&Circle::new((0, 50), 30, BLACK.filled()))
+                .unwrap();
+            right.draw(&TypstClip::end()).unwrap();
+            root.draw(&Circle::new((150, 50), 5, WHITE.filled()))
+                .unwrap();
+        }
+
+        checked_save_file(
+            "test_clips_across_steps",
+            &format!("#let only(step, body) = body\n{}", content),
+        );
+        crate::test::assert_nested_groups(&content);
+        // The regions open around a step change are closed and reopened
+        assert!(content.contains("  ])))\n  ])))\n  ]\n  #only(\"3-\")[\n  #place(dx: 0pt, dy: 0pt, box(width: 100pt, height: 100pt, clip: true"));
+        let step = content.find("  #only(\"4-\")[").unwrap();
+        let white = content.rfind("fill: rgb(255, 255, 255)").unwrap();
+        assert!(step < white && content[white..].contains("  ]\n"));
+    }
+
+    #[test]
+    fn test_reveal_series_steps() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (300, 200)).into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            let mut chart = ChartBuilder::on(&root)
+                .margin(10)
+                .build_cartesian_2d(0..10, 0..10)
+                .unwrap();
+
+            chart
+                .draw_series_step(LineSeries::new((0..10).map(|x| (x, x)), RED))
+                .unwrap()
+                .label("rising")
+                .legend(|(x, y)| PathElement::new([(x, y), (x + 20, y)], RED));
+            chart
+                .draw_series_step((0..10).map(|x| Circle::new((x, 9 - x), 3, BLUE.filled())))
+                .unwrap()
+                .label("falling")
+                .legend(|(x, y)| Circle::new((x + 10, y), 3, BLUE.filled()));
+            chart.configure_series_labels().draw().unwrap();
+        }
+
+        checked_save_file(
+            "test_reveal_series_steps",
+            &format!("#let only(step, body) = body\n{}", content),
+        );
+        let second = content.find("  #only(\"2-\")[\n").unwrap();
+        let third = content.find("  #only(\"3-\")[\n").unwrap();
+        assert!(second < third);
+        // The two groups, and the block scoping the text settings of the labels
+        assert_eq!(content.matches("  ]\n").count(), 3);
+        // The legend comes after the last group, shown from the first slide
+        let legend = content.rfind("[falling])").unwrap();
+        assert!(content[third..legend].contains("  ]\n"));
+    }
+
+    #[test]
+    fn test_export_data() {
+        let rising: Vec<(i32, f64)> = (0..4).map(|x| (x, x as f64 * 1.5)).collect();
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (200, 100)).into_drawing_area();
+            let mut chart = ChartBuilder::on(&root)
+                .build_cartesian_2d(0..4, 0.0..5.0)
+                .unwrap();
+            chart
+                .draw_series(LineSeries::new(rising.iter().copied(), RED))
+                .unwrap();
+            let area = chart.plotting_area();
+            area.draw(&TypstData::new("rising", rising.iter().copied()))
+                .unwrap();
+            area.draw(&TypstData::new("2 labels", [("a \"b\"", true)]))
+                .unwrap();
+            area.draw(&TypstData::new(
+                "odd",
+                [(1u8, f32::NAN), (2, f32::NEG_INFINITY)],
+            ))
+            .unwrap();
+        }
+
+        checked_save_file(
+            "test_export_data",
+            &format!("{}#rising.len() #_2_labels.first() #odd", content),
+        );
+        assert!(content.starts_with(concat!(
+            "#let rising = ((0, 0.0), (1, 1.5), (2, 3.0), (3, 4.5))\n",
+            "#let _2_labels = ((\"a \\\"b\\\"\", true),)\n",
+            "#let odd = ((1, calc.nan), (2, -calc.inf))\n",
+            "#box("
+        )));
+    }
+
+    #[test]
+    fn test_namespaced_bindings() {
+        let chart = |backend: TypstBackend, values: [f64; 2]| {
+            let root = backend.into_drawing_area();
+            root.draw(&TypstData::new("data", [(0, values[0]), (1, values[1])]))
+                .unwrap();
+            root.present().unwrap();
+        };
+        let (mut first, mut second) = (String::default(), String::default());
+        chart(
+            TypstBackend::with_string(&mut first, (50, 50)).namespace("sales"),
+            [1.0, 2.0],
+        );
+        chart(
+            TypstBackend::with_string(&mut second, (50, 50)).namespace("2024 costs"),
+            [3.0, 4.0],
+        );
+        assert!(first.starts_with("#let sales_data = ((0, 1.0), (1, 2.0))\n"));
+        assert!(second.starts_with("#let _2024_costs_data = ((0, 3.0), (1, 4.0))\n"));
+
+        let (mut hashed, mut same, mut other) = Default::default();
+        chart(
+            TypstBackend::with_string(&mut hashed, (50, 50)).hashed_namespace(),
+            [1.0, 2.0],
+        );
+        chart(
+            TypstBackend::with_string(&mut same, (50, 50)).hashed_namespace(),
+            [1.0, 2.0],
+        );
+        chart(
+            TypstBackend::with_string(&mut other, (50, 50)).hashed_namespace(),
+            [3.0, 4.0],
+        );
+        let name = |content: &String| content[5..content.find(" = ").unwrap()].to_string();
+        assert_eq!(name(&hashed), name(&same));
+        assert_ne!(name(&hashed), name(&other));
+        assert!(name(&hashed).starts_with('c') && name(&hashed).ends_with("_data"));
+
+        checked_save_file(
+            "test_namespaced_bindings",
+            &format!(
+                "{}{}{}{}#sales_data.len() #_2024_costs_data.last() #{}.first()",
+                first,
+                second,
+                hashed,
+                other,
+                name(&other)
+            ),
+        );
+    }
+
+    #[test]
+    fn test_rotated_text() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (300, 200)).into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            let mut chart = ChartBuilder::on(&root)
+                .margin(10)
+                .x_label_area_size(60)
+                .y_label_area_size(30)
+                .build_cartesian_2d(0..4, 0..10)
+                .unwrap();
+            chart
+                .configure_mesh()
+                .x_label_formatter(&|_| String::new())
+                .draw()
+                .unwrap();
+            let style = ("sans-serif", 12)
+                .into_text_style(&root)
+                .pos(Pos::new(HPos::Right, VPos::Top));
+            for (x, date) in ["2024-01", "2024-02", "2024-03", "2024-04"]
+                .iter()
+                .enumerate()
+            {
+                chart
+                    .plotting_area()
+                    .draw(&TypstText::new(*date, (x as i32, 0), style.clone()).rotate(-45.0))
+                    .unwrap();
+            }
+            // A non-finite angle leaves the text upright
+            root.draw(&TypstText::new("upright", (250, 20), ("sans-serif", 12)).rotate(f64::NAN))
+                .unwrap();
+        }
+
+        checked_save_file("test_rotated_text", &content);
+        assert!(!content.contains("NaN"));
+        assert_eq!(
+            content
+                .matches("rotate(-45deg, origin: top + right, box[")
+                .count(),
+            4
+        );
+        assert!(content.contains("#place(top + left, dx: 250pt, dy: 20pt, box["));
+    }
+
+    #[test]
+    fn test_text_decorations() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (200, 100)).into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            let style = ("sans-serif", 16).into_text_style(&root);
+            root.draw(&TypstText::new("Threshold", (10, 10), style.clone()).underline())
+                .unwrap();
+            root.draw(&TypstText::new("Legacy API", (10, 40), style.clone()).strike())
+                .unwrap();
+            root.draw(
+                &TypstText::new("Both", (10, 70), style.color(&RED))
+                    .underline()
+                    .strike(),
+            )
+            .unwrap();
+        }
+
+        checked_save_file("test_text_decorations", &content);
+        assert!(content.contains("#underline[Threshold]"));
+        assert!(content.contains("#strike[Legacy API]"));
+        assert!(content.contains("#underline[#strike[Both]]"));
+    }
+
+    #[test]
+    fn test_section_comments() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (300, 200)).into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            let mut chart = ChartBuilder::on(&root)
+                .margin(10)
+                .x_label_area_size(20)
+                .y_label_area_size(20)
+                .build_cartesian_2d(0..10, 0..10)
+                .unwrap();
+
+            chart
+                .plotting_area()
+                .draw(&TypstSection::new("mesh"))
+                .unwrap();
+            chart.configure_mesh().draw().unwrap();
+            for offset in 0..2 {
+                chart.plotting_area().draw(&TypstSection::series()).unwrap();
+                chart
+                    .draw_series(LineSeries::new((0..10).map(|x| (x, x + offset)), RED))
+                    .unwrap()
+                    .label(format!("offset {}", offset));
+            }
+            chart
+                .plotting_area()
+                .draw(&TypstSection::new("legend\nbox"))
+                .unwrap();
+            chart.configure_series_labels().draw().unwrap();
+        }
+
+        checked_save_file("test_section_comments", &content);
+        let mesh = content.find("\n  // section: mesh\n").unwrap();
+        let first = content.find("\n  // section: series 1\n").unwrap();
+        let second = content.find("\n  // section: series 2\n").unwrap();
+        let legend = content.find("\n  // section: legend box\n").unwrap();
+        assert!(mesh < first && first < second && second < legend);
+    }
+
+    #[test]
+    fn test_named_anchors() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (300, 200))
+                .named_anchors()
+                .padding(5)
+                .into_drawing_area();
+            let mut chart = ChartBuilder::on(&root)
+                .margin(10)
+                .build_cartesian_2d(0..10, 0..10)
+                .unwrap();
+            let area = chart.plotting_area().clone();
+            area.draw(&TypstAnchor::new("origin", (0, 0))).unwrap();
+            area.draw(&TypstAnchor::new("x-end", (10, 0))).unwrap();
+            for offset in [0, 5] {
+                area.draw(&TypstSection::series()).unwrap();
+                chart
+                    .draw_series(LineSeries::new((2..8).map(|x| (x, x / 2 + offset)), RED))
+                    .unwrap();
+            }
+            chart
+                .plotting_area()
+                .draw(&TypstLegend::new((6, 9)).entry("a", RED))
+                .unwrap();
+        }
+
+        checked_save_file("test_named_anchors", &content);
+        let anchors = content.lines().next().unwrap();
+        assert!(anchors.starts_with(
+            "#let chart_anchors = (\"origin\": (15pt, 194pt), \"x-end\": (294pt, 194pt), \"series-1-first\": (70pt, 177pt), "
+        ));
+        for name in ["series-1-last", "series-2-first", "series-2-last", "legend"] {
+            assert!(anchors.contains(&format!("\"{}\": (", name)));
+        }
+    }
+
+    #[test]
+    fn test_coord_map() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (300, 200))
+                .padding(5)
+                .into_drawing_area();
+            let chart = ChartBuilder::on(&root)
+                .margin(10)
+                .build_cartesian_2d(0.0..10.0, 0.0..100.0)
+                .unwrap();
+            chart
+                .plotting_area()
+                .draw(&TypstCoordMap::new((0.0, 0.0), (10.0, 100.0)))
+                .unwrap();
+            let error = chart
+                .plotting_area()
+                .draw(&TypstCoordMap::new((0.0, 0.0), (0.0, 100.0)));
+            assert!(error.is_err());
+        }
+        // The document marks a data point of the chart
+        content.push_str(
+            "#place(dx: chart_coord(2.5, 40).at(0), dy: chart_coord(2.5, 40).at(1))[x]\n",
+        );
+
+        checked_save_file("test_coord_map", &content);
+        assert!(content
+            .starts_with("#let chart_coord(x, y) = (15pt + x * 27.9pt, 194pt + y * -1.79pt)\n"));
+    }
+
+    #[test]
+    fn test_split_layers() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (300, 200))
+                .split_layers()
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            let mut chart = ChartBuilder::on(&root)
+                .margin(10)
+                .x_label_area_size(20)
+                .y_label_area_size(20)
+                .build_cartesian_2d(0..10, 0..10)
+                .unwrap();
+            chart.configure_mesh().draw().unwrap();
+            chart.plotting_area().draw(&TypstLayer::data()).unwrap();
+            chart
+                .draw_series(LineSeries::new((0..10).map(|x| (x, x)), RED))
+                .unwrap();
+            chart.plotting_area().draw(&TypstLayer::frame()).unwrap();
+            chart
+                .plotting_area()
+                .draw(&Text::new("note", (1, 8), ("sans-serif", 12)))
+                .unwrap();
+
+            let error = root.clipped(|area| area.draw(&TypstLayer::data()));
+            assert!(matches!(error, Ok(Err(_))));
+        }
+
+        checked_save_file("test_split_layers", &content);
+        let frame = content.find("#let chart_frame = [\n").unwrap();
+        let data = content.find("#let chart_data = [\n").unwrap();
+        let canvas = content.find("#box(").unwrap();
+        assert!(frame < data && data < canvas);
+        // The note drawn after the series is in the frame layer
+        assert!(content[frame..data].contains("[note]"));
+        assert!(content[data..canvas].contains("rgb(255, 0, 0)"));
+        assert!(!content[frame..data].contains("rgb(255, 0, 0)"));
+        assert!(content.ends_with("  #chart_frame\n  #chart_data\n]\n"));
+        // The text settings of the labels come with each layer
+        let text_rule = content
+            .lines()
+            .find(|line| line.contains("#set text(size"))
+            .unwrap();
+        assert!(content[frame..data].contains(text_rule));
+        assert!(content[data..canvas].contains(text_rule));
+
+        // The layers stacked by a document look like the chart
+        #[cfg(feature = "test-util")]
+        {
+            let stacked = format!(
+                "{}#box(width: 300pt, height: 200pt, clip: true, fill: rgb(255, 255, 255))[\n  #chart_frame\n  #chart_data\n]\n",
+                &content[..canvas]
+            );
+            assert_eq!(
+                crate::test_util::render(&stacked),
+                crate::test_util::render(&content)
+            );
+        }
+    }
+
+    #[test]
+    fn test_clipped_areas() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (300, 150)).into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            let (left, right) = root.split_horizontally(150);
+            left.clipped(|area| {
+                area.fill(&RGBColor(230, 230, 250)).unwrap();
+                // Runs far beyond the area, into the right half and off the canvas
+                area.draw(&PathElement::new(
+                    [(-50, 75), (400, 20)],
+                    BLUE.stroke_width(3),
+                ))
+                .unwrap();
+                let inner = area.margin(40, 40, 40, 40);
+                inner.clipped(|inner| {
+                    inner
+                        .draw(&Circle::new((35, 35), 50, RED.filled()))
+                        .unwrap();
+                })
+            })
+            .unwrap()
+            .unwrap();
+
+            let mut chart = ChartBuilder::on(&right)
+                .margin(10)
+                .build_cartesian_2d(0.0..1.0, 0.0..1.0)
+                .unwrap();
+            let plot = chart.plotting_area().strip_coord_spec();
+            plot.draw(&TypstClip::begin()).unwrap();
+            chart
+                .draw_series(LineSeries::new([(-1.0, 0.0), (2.0, 1.5)], GREEN))
+                .unwrap();
+            // Left open, closed when presenting
+        }
+
+        checked_save_file("test_clipped_areas", &content);
+        assert!(content.contains(
+            "  #place(dx: 0pt, dy: 0pt, box(width: 150pt, height: 150pt, clip: true, place(dx: 0pt, dy: 0pt, box(width: 300pt, height: 150pt)[\n"
+        ));
+        assert!(content.contains(
+            "  #place(dx: 40pt, dy: 40pt, box(width: 70pt, height: 70pt, clip: true, place(dx: -40pt, dy: -40pt, box(width: 300pt, height: 150pt)[\n"
+        ));
+        assert!(content.contains("  #place(dx: 160pt, dy: 10pt, box(width: 130pt"));
+        assert_eq!(content.matches("clip: true, place(").count(), 3);
+        assert_eq!(content.matches("  ])))\n").count(), 3);
+    }
+}
@@ -0,0 +1,138 @@
+/*!
+Checking the fonts of a chart
+
+Behind the `fontdb` feature, the font families a chart uses, after mapping generic
+families such as `sans-serif` to Typst fonts, are looked up in the installed fonts
+or in a given font set, so a missing font shows up when the chart is generated
+instead of as fallback glyphs in the compiled document.
+
+The installed fonts are loaded once per process, on first use.
+*/
+
+use std::io::{Error, ErrorKind};
+use std::sync::{Arc, OnceLock};
+
+use fontdb::Database;
+
+use crate::TypstBackend;
+
+/// The fonts installed on the system
+fn system_fonts() -> &'static Arc<Database> {
+    static FONTS: OnceLock<Arc<Database>> = OnceLock::new();
+    FONTS.get_or_init(|| {
+        let mut fonts = Database::new();
+        fonts.load_system_fonts();
+        Arc::new(fonts)
+    })
+}
+
+impl TypstBackend<'_> {
+    /// Fail to present the chart when one of its font families is not installed on
+    /// the system; see [`Self::missing_fonts`] to only warn about them
+    pub fn check_fonts(mut self) -> Self {
+        self.options.font_check = Some(Arc::clone(system_fonts()));
+        self
+    }
+
+    /// Fail to present the chart when one of its font families is not in `fonts`,
+    /// e.g. the fonts passed to `typst compile --font-path`
+    pub fn check_fonts_in(mut self, fonts: Database) -> Self {
+        self.options.font_check = Some(Arc::new(fonts));
+        self
+    }
+
+    /// The font families used so far that are missing from the fonts given to
+    /// [`Self::check_fonts_in`], or from the installed fonts, sorted by name
+    pub fn missing_fonts(&self) -> Vec<String> {
+        let fonts = self.options.font_check.as_ref().unwrap_or(system_fonts());
+        self.missing_fonts_in(fonts)
+    }
+
+    fn missing_fonts_in(&self, fonts: &Database) -> Vec<String> {
+        // Typst matches family names regardless of case
+        let installed: Vec<String> = fonts
+            .faces()
+            .flat_map(|face| face.families.iter())
+            .map(|(family, _)| family.to_lowercase())
+            .collect();
+        self.font_families
+            .iter()
+            .filter(|family| !installed.contains(&family.to_lowercase()))
+            .cloned()
+            .collect()
+    }
+
+    /// In font check mode, fail if a font family of the chart is missing
+    pub(crate) fn verify_fonts(&self) -> Result<(), Error> {
+        let Some(fonts) = &self.options.font_check else {
+            return Ok(());
+        };
+        let missing = self.missing_fonts_in(fonts);
+        if missing.is_empty() {
+            return Ok(());
+        }
+        Err(Error::new(
+            ErrorKind::NotFound,
+            format!("fonts not found: {}", missing.join(", ")),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::TypstBackend;
+    use fontdb::{Database, FaceInfo, Language, Source, Stretch, Style, Weight, ID};
+    use plotters::prelude::*;
+    use std::sync::Arc;
+
+    /// A font set with the single family `family`, without any font data
+    fn font_set(family: &str) -> Database {
+        let mut fonts = Database::new();
+        fonts.push_face_info(FaceInfo {
+            id: ID::dummy(),
+            source: Source::Binary(Arc::new(Vec::<u8>::new())),
+            index: 0,
+            families: vec![(family.to_string(), Language::English_UnitedStates)],
+            post_script_name: String::new(),
+            style: Style::Normal,
+            weight: Weight::NORMAL,
+            stretch: Stretch::Normal,
+            monospaced: false,
+        });
+        fonts
+    }
+
+    #[test]
+    fn test_missing_fonts() {
+        let mut content = String::default();
+        let mut backend = TypstBackend::with_string(&mut content, (100, 50))
+            .check_fonts_in(font_set("Liberation Sans"));
+        // Generic families are mapped, and families match regardless of case
+        for (family, y) in [
+            ("sans-serif", 10),
+            ("liberation sans", 20),
+            ("No Such Font", 30),
+        ] {
+            let style = TextStyle::from((family, 12).into_font());
+            backend.draw_text("a", &style, (10, y)).unwrap();
+        }
+
+        assert_eq!(backend.missing_fonts(), ["No Such Font"]);
+        let error = backend.present().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Drawing backend error: fonts not found: No Such Font"
+        );
+    }
+
+    #[test]
+    fn test_system_fonts_shared() {
+        let first = TypstBackend::new_buffered((100, 50)).check_fonts();
+        let second = TypstBackend::new_buffered((100, 50)).check_fonts();
+        // The installed fonts are loaded once
+        assert!(Arc::ptr_eq(
+            first.options.font_check.as_ref().unwrap(),
+            second.options.font_check.as_ref().unwrap()
+        ));
+    }
+}
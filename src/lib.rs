@@ -3,15 +3,61 @@ The Typst drawing backend for plotters
 */
 
 use plotters_backend::{
-    text_anchor::{HPos, VPos},
+    text_anchor::{HPos, Pos, VPos},
     BackendColor, BackendCoord, BackendStyle, BackendTextStyle, DrawingBackend, DrawingErrorKind,
     FontStyle, FontTransform,
 };
 
-use std::fmt::Write as _;
-use std::fs::File;
-use std::io::{BufWriter, Error, Write};
-use std::path::Path;
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Write as _};
+use std::fs::{File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{BufWriter, Error, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use manifest::{grow, JsonObject, Manifest};
+
+#[cfg(feature = "compile")]
+pub mod compile;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod document;
+#[cfg(feature = "plotters")]
+pub mod element;
+#[cfg(feature = "fontdb")]
+mod fonts;
+mod manifest;
+pub mod package;
+pub mod pool;
+#[cfg(feature = "image")]
+pub mod raster;
+mod spill;
+pub mod table;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+
+/// The commonly used types, so a chart needs a single `use plotters_typst::prelude::*`
+pub mod prelude {
+    pub use crate::document::TypstDocument;
+    #[cfg(feature = "plotters")]
+    pub use crate::element::{
+        BulkSeries, ClipArea, MarkerShape, RevealSeries, TypstAnchor, TypstArrow, TypstCallout,
+        TypstCircles, TypstClip, TypstColorbar, TypstCoordMap, TypstData, TypstEllipse, TypstLayer,
+        TypstLegend, TypstMarker, TypstRects, TypstReveal, TypstSection, TypstText, TypstValue,
+    };
+    pub use crate::package::LocalPackage;
+    pub use crate::pool::TypstPool;
+    pub use crate::table::DataTable;
+    pub use crate::{ColorblindPalette, Progress, TypstBackend, TypstOutput, WriteReport};
+    #[cfg(feature = "plotters")]
+    pub use plotters::prelude::*;
+}
 
 struct Rgb(u8, u8, u8);
 
@@ -30,774 +76,6443 @@ fn make_typst_color(color: BackendColor) -> String {
     }
 }
 
-enum Target<'a> {
-    File(String, &'a Path),
-    Buffer(&'a mut String),
+/// A palette telling colors apart with the common color vision deficiencies
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum ColorblindPalette {
+    /// The seven colors of Okabe and Ito, safe for deuteranopia, protanopia and
+    /// tritanopia
+    OkabeIto,
+    /// Eight colors along the viridis color map, from dark purple to yellow
+    Viridis,
 }
 
-impl Target<'_> {
-    fn get_mut(&mut self) -> &mut String {
+impl ColorblindPalette {
+    /// The colors of the palette, in the order they are given out
+    pub fn colors(self) -> &'static [(u8, u8, u8)] {
         match self {
-            Target::File(ref mut buf, _) => buf,
-            Target::Buffer(buf) => buf,
+            Self::OkabeIto => &[
+                (230, 159, 0),
+                (86, 180, 233),
+                (0, 158, 115),
+                (240, 228, 66),
+                (0, 114, 178),
+                (213, 94, 0),
+                (204, 121, 167),
+            ],
+            Self::Viridis => &[
+                (68, 1, 84),
+                (70, 50, 126),
+                (54, 92, 141),
+                (39, 127, 142),
+                (31, 161, 135),
+                (74, 193, 109),
+                (160, 218, 57),
+                (253, 231, 37),
+            ],
         }
     }
 }
 
-/// The Typst drawing backend
-pub struct TypstBackend<'a> {
-    target: Target<'a>,
+/// Writes a color as a Typst expression, see [`TypstBackend::color_serializer`]
+type ColorSerializer = dyn Fn(BackendColor) -> String;
+
+/// Writes markup onto the canvas, see [`TypstBackend::overlay`]
+type CanvasHook = dyn Fn(&CanvasInfo) -> String;
+
+/// What a hook writing markup onto the canvas knows of the chart, see
+/// [`TypstBackend::overlay`]
+pub struct CanvasInfo {
     size: (u32, u32),
-    saved: bool,
+    coord_map: Option<(f64, f64, f64, f64)>,
+    em: Option<f64>,
+    rounded: bool,
+    smart_punctuation: bool,
 }
 
-impl<'a> TypstBackend<'a> {
-    fn escape_text(text: &str) -> String {
-        text.replace('\\', r"\\")
-            .replace('"', r#"\""#)
-            .replace('#', r"\#")
-            .replace('$', r"\$")
+impl CanvasInfo {
+    /// The size of the canvas in pixels, once cropped
+    pub fn size(&self) -> (u32, u32) {
+        self.size
     }
 
-    fn write_command(&mut self, command: &str) {
-        let buf = self.target.get_mut();
-        buf.push_str(command);
-        buf.push('\n');
+    /// The length of `pixels` pixels in the units of the chart, such as `12pt`
+    pub fn len(&self, pixels: f64) -> String {
+        Length {
+            value: pixels,
+            em: self.em,
+            rounded: self.rounded,
+        }
+        .to_string()
     }
 
-    fn init_canvas(&mut self, size: (u32, u32)) {
-        let buf = self.target.get_mut();
-        // Create a box with absolute positioning and clipping for the canvas
-        writeln!(
-            buf,
-            "#box(width: {}pt, height: {}pt, clip: true)[",
-            size.0, size.1
-        )
-        .unwrap();
+    /// The offsets `(dx, dy)` of the data point `(x, y)` from the upper left corner
+    /// of the canvas, once a [`TypstCoordMap`](element::TypstCoordMap) is drawn
+    pub fn coord(&self, x: f64, y: f64) -> Option<(String, String)> {
+        let (x0, x_scale, y0, y_scale) = self.coord_map?;
+        Some((self.len(x0 + x * x_scale), self.len(y0 + y * y_scale)))
     }
 
-    /// Create a new Typst drawing backend
-    pub fn new<T: AsRef<Path> + ?Sized>(path: &'a T, size: (u32, u32)) -> Self {
-        let mut ret = Self {
-            target: Target::File(String::default(), path.as_ref()),
-            size,
-            saved: false,
-        };
-
-        ret.init_canvas(size);
-        ret
+    /// `text` escaped for Typst markup like the labels of the chart
+    pub fn escape(&self, text: &str) -> String {
+        escape_markup(text, !self.smart_punctuation)
     }
+}
 
-    /// Create a new Typst drawing backend and store the document into a String buffer
-    pub fn with_string(buf: &'a mut String, size: (u32, u32)) -> Self {
-        let mut ret = Self {
-            target: Target::Buffer(buf),
-            size,
-            saved: false,
-        };
+/// The palette color given to each color of a chart
+type PaletteColors = HashMap<(u8, u8, u8), (u8, u8, u8)>;
 
-        ret.init_canvas(size);
-        ret
+/// Format `color` as a Typst gray of the same luminance
+fn make_typst_luma(color: BackendColor) -> String {
+    let lightness = gray_level(color);
+    if color.alpha < 1.0 {
+        format!("luma({}, {}%)", lightness, (color.alpha * 100.0) as u32)
+    } else {
+        format!("luma({})", lightness)
     }
 }
 
-impl<'a> DrawingBackend for TypstBackend<'a> {
-    type ErrorType = Error;
+/// The level of the gray of the same luminance as `color`
+pub(crate) fn gray_level(color: BackendColor) -> u8 {
+    // The relative luminance of the linear channels, encoded back like sRGB
+    let linear = |c: u8| {
+        let c = f64::from(c) / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let (r, g, b) = color.rgb;
+    let y = 0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b);
+    let encoded = if y <= 0.0031308 {
+        12.92 * y
+    } else {
+        1.055 * y.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0).round() as u8
+}
 
-    fn get_size(&self) -> (u32, u32) {
-        self.size
+/// Map generic font families to Typst fonts
+fn typst_font_family(family: &str) -> &str {
+    match family {
+        "sans-serif" => "Liberation Sans",
+        "serif" => "Liberation Serif",
+        "monospace" => "Liberation Mono",
+        other => other,
     }
+}
 
-    fn ensure_prepared(&mut self) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
-        Ok(())
+/// Turn `name` into a valid Typst identifier
+fn typst_identifier(name: &str) -> String {
+    let mut ident: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if !ident.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        ident.insert(0, '_');
     }
+    ident
+}
 
-    fn present(&mut self) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
-        if !self.saved {
-            // Close the box
-            self.write_command("]");
-
-            match self.target {
-                Target::File(ref buf, path) => {
-                    let outfile = File::create(path).map_err(DrawingErrorKind::DrawingError)?;
-                    let mut outfile = BufWriter::new(outfile);
-                    outfile
-                        .write_all(buf.as_ref())
-                        .map_err(DrawingErrorKind::DrawingError)?;
-                }
-                Target::Buffer(_) => {}
+/// Turn `name` into a valid Typst label, without the angle brackets
+fn typst_label(name: &str) -> String {
+    let label: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '_' | '-' | ':' | '.') {
+                c
+            } else {
+                '_'
             }
-            self.saved = true;
-        }
-        Ok(())
+        })
+        .collect();
+    if label.is_empty() {
+        return "_".to_string();
     }
+    label
+}
 
-    fn draw_pixel(
-        &mut self,
-        point: BackendCoord,
-        color: BackendColor,
-    ) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
-        if color.alpha == 0.0 {
-            return Ok(());
-        }
+/// A length in backend pixels, displayed in the output unit
+#[derive(Clone, Copy)]
+pub(crate) struct Length {
+    value: f64,
+    em: Option<f64>,
+    /// Round to four decimals, see [`TypstBackend::reproducible`]
+    rounded: bool,
+}
 
-        let cmd =
-            format!(
-            "  #place(dx: {}pt, dy: {}pt, rect(width: 1pt, height: 1pt, fill: {}, stroke: none))",
-            point.0, point.1, make_typst_color(color)
-        );
-        self.write_command(&cmd);
-        Ok(())
+impl fmt::Display for Length {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (value, unit) = match self.em {
+            None if self.rounded => (round_decimals(self.value), "pt"),
+            None => (self.value, "pt"),
+            Some(em) => (round_decimals(self.value / em), "em"),
+        };
+        write_number(f, value)?;
+        f.write_str(unit)
     }
+}
 
-    fn draw_line<S: BackendStyle>(
-        &mut self,
-        from: BackendCoord,
-        to: BackendCoord,
-        style: &S,
-    ) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
-        if style.color().alpha == 0.0 {
-            return Ok(());
-        }
+/// Write `value` as `{}` displays it, with the integer and shortest float
+/// formatters of `itoa` and `ryu`, which are much faster for the millions of
+/// lengths of a large chart
+fn write_number<W: fmt::Write>(out: &mut W, value: f64) -> fmt::Result {
+    let magnitude = value.abs();
+    if value.fract() == 0.0 && magnitude < 1e15 {
+        out.write_str(itoa::Buffer::new().format(value as i64))
+    } else if (1e-5..1e15).contains(&magnitude) {
+        // Outside this range `ryu` switches to scientific notation
+        out.write_str(ryu::Buffer::new().format_finite(value))
+    } else {
+        write!(out, "{}", value)
+    }
+}
 
-        let color = make_typst_color(style.color());
-        let stroke_width = style.stroke_width();
+/// Round `value` to four decimals
+fn round_decimals(value: f64) -> f64 {
+    // Adding zero turns `-0.0` into `0.0`, which prints without the sign
+    (value * 1e4).round() / 1e4 + 0.0
+}
 
-        let dx = (to.0 - from.0) as f64;
-        let dy = (to.1 - from.1) as f64;
-        let length = (dx * dx + dy * dy).sqrt();
-        let angle = dy.atan2(dx).to_degrees();
+/// The 64-bit FNV-1a hash of `parts`, which unlike the hasher of the standard
+/// library is the same with every Rust version and on every platform
+fn stable_hash(parts: &[&str]) -> u64 {
+    parts
+        .iter()
+        .flat_map(|part| part.bytes())
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
 
-        let cmd = format!(
-            "  #place(dx: {}pt, dy: {}pt, line(length: {}pt, angle: {}deg, stroke: {}pt + {}))",
-            from.0, from.1, length, angle, stroke_width, color
-        );
-        self.write_command(&cmd);
-        Ok(())
-    }
+/// The callback of [`TypstBackend::on_progress`]
+type ProgressCallback<'a> = Box<dyn FnMut(Progress) + 'a>;
 
-    fn draw_rect<S: BackendStyle>(
-        &mut self,
-        upper_left: BackendCoord,
-        bottom_right: BackendCoord,
-        style: &S,
-        fill: bool,
-    ) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
-        if style.color().alpha == 0.0 {
-            return Ok(());
-        }
+enum Target<'a> {
+    File(&'a Path),
+    Buffer(&'a mut String),
+    Shared(TypstOutput),
+    Writer(Box<dyn Write + 'a>),
+}
 
-        let color = make_typst_color(style.color());
-        let width = bottom_right.0 - upper_left.0;
-        let height = bottom_right.1 - upper_left.1;
+/// A shared handle to the document of a backend created with
+/// [`TypstBackend::new_buffered`], readable once the chart is presented
+#[derive(Clone, Debug, Default)]
+pub struct TypstOutput(Rc<RefCell<String>>);
 
-        let (fill_attr, stroke_attr) = if fill {
-            (format!("fill: {}", color), "stroke: none".to_string())
-        } else {
-            (
-                "fill: none".to_string(),
-                format!("stroke: {}pt + {}", style.stroke_width(), color),
-            )
-        };
+impl TypstOutput {
+    /// Move the document out, leaving the handle empty
+    pub fn take(&self) -> String {
+        std::mem::take(&mut self.0.borrow_mut())
+    }
 
-        let cmd = format!(
-            "  #place(dx: {}pt, dy: {}pt, rect(width: {}pt, height: {}pt, {}, {}))",
-            upper_left.0, upper_left.1, width, height, fill_attr, stroke_attr
-        );
-        self.write_command(&cmd);
-        Ok(())
+    /// A copy of the document
+    pub fn contents(&self) -> String {
+        self.0.borrow().clone()
     }
+}
 
-    fn draw_path<S: BackendStyle, I: IntoIterator<Item = BackendCoord>>(
-        &mut self,
-        path: I,
-        style: &S,
-    ) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
-        if style.color().alpha == 0.0 {
-            return Ok(());
-        }
+/// How far drawing a chart got, passed to the callback of
+/// [`TypstBackend::on_progress`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    /// The number of primitives drawn so far, such as lines, shapes and labels
+    pub primitives: usize,
+    /// The number of bytes of bindings and drawing commands written so far
+    pub bytes: usize,
+}
 
-        let points: Vec<_> = path.into_iter().collect();
-        if points.len() < 2 {
-            return Ok(());
+/// What presenting a chart wrote, returned by [`TypstBackend::present_report`] to
+/// log with each generated chart
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriteReport {
+    /// The file written, for a backend created with [`TypstBackend::new`]
+    pub path: Option<PathBuf>,
+    /// The number of bytes of the document written
+    pub bytes: usize,
+    /// The number of primitives drawn, such as lines, shapes and labels
+    pub primitives: usize,
+    /// The number of primitives drawn of each kind, such as `"line"`
+    pub elements: BTreeMap<&'static str, usize>,
+    /// What may be wrong with the chart, such as content drawn outside of the canvas
+    pub warnings: Vec<String>,
+}
+
+/// Settings chosen through the builder methods of [`TypstBackend`]
+#[derive(Clone, Default)]
+struct Options {
+    outline: Option<(BackendColor, u32)>,
+    inline: bool,
+    float_safe: bool,
+    baseline: Option<i32>,
+    scaling: Option<Scaling>,
+    em: Option<f64>,
+    transparent: bool,
+    frame: Option<(BackendColor, u32, u32)>,
+    reveal_function: Option<String>,
+    debug_overlay: bool,
+    preamble: String,
+    template: Option<Template>,
+    page: Option<Page>,
+    namespace: Option<Namespace>,
+    append: bool,
+    stroke: Option<StrokeDefaults>,
+    stroke_scale: Option<f64>,
+    tabular_figures: bool,
+    disable_ligatures: bool,
+    /// The OpenType features enabled on all text, without repeats
+    font_features: Vec<String>,
+    strict_fonts: bool,
+    smart_punctuation: bool,
+    inherit_paragraph: bool,
+    inherit_text_color: bool,
+    grayscale: bool,
+    overflow_visible: bool,
+    padding: u32,
+    auto_crop: bool,
+    manifest: bool,
+    source_map: bool,
+    reproducible: bool,
+    compact: bool,
+    batch_bars: bool,
+    decimate: bool,
+    #[cfg(feature = "image")]
+    raster_threshold: Option<usize>,
+    #[cfg(feature = "image")]
+    raster_dpi: Option<f64>,
+    #[cfg(feature = "image")]
+    raster_only: Option<Vec<String>>,
+    spill_cap: Option<usize>,
+    max_bytes: Option<usize>,
+    max_primitives: Option<usize>,
+    cancel: Option<Arc<AtomicBool>>,
+    figure_caption: Option<String>,
+    detached_legend: bool,
+    layers: bool,
+    split_series: bool,
+    anchors: bool,
+    figure_label: Option<String>,
+    /// The fonts the font families of the chart are looked up in
+    #[cfg(feature = "fontdb")]
+    font_check: Option<Arc<fontdb::Database>>,
+    /// Pixels per point of PNG exports
+    #[cfg(feature = "compile")]
+    pixel_per_pt: Option<f32>,
+    /// The page of the document exported to images
+    #[cfg(feature = "compile")]
+    export_page: usize,
+    palette: Option<ColorblindPalette>,
+    color_serializer: Option<Rc<ColorSerializer>>,
+    overlays: Vec<Rc<CanvasHook>>,
+    underlays: Vec<Rc<CanvasHook>>,
+}
+
+/// Stroke attributes merged into every stroke of the chart
+#[derive(Clone, Default)]
+struct StrokeDefaults {
+    cap: Option<String>,
+    join: Option<String>,
+    miter_limit: Option<f64>,
+    dash: Option<String>,
+}
+
+/// The name of the binding holding the [`StrokeDefaults`]
+const STROKE_BINDING: &str = "chart-stroke";
+
+/// The line caps of a Typst stroke
+pub(crate) const STROKE_CAPS: [&str; 3] = ["butt", "round", "square"];
+
+/// The line joins of a Typst stroke
+pub(crate) const STROKE_JOINS: [&str; 3] = ["miter", "round", "bevel"];
+
+/// The named dash patterns of a Typst stroke
+pub(crate) const STROKE_DASHES: [&str; 10] = [
+    "solid",
+    "dotted",
+    "densely-dotted",
+    "loosely-dotted",
+    "dashed",
+    "densely-dashed",
+    "loosely-dashed",
+    "dash-dotted",
+    "densely-dash-dotted",
+    "loosely-dash-dotted",
+];
+
+impl StrokeDefaults {
+    /// The defaults as a Typst stroke dictionary
+    fn dict(&self) -> String {
+        let quoted = |value: &str| format!("\"{}\"", TypstBackend::escape_text(value));
+        let mut fields = Vec::new();
+        if let Some(cap) = &self.cap {
+            fields.push(format!("cap: {}", quoted(cap)));
         }
+        if let Some(join) = &self.join {
+            fields.push(format!("join: {}", quoted(join)));
+        }
+        if let Some(limit) = self.miter_limit {
+            fields.push(format!("miter-limit: {}", limit));
+        }
+        if let Some(dash) = &self.dash {
+            fields.push(format!("dash: {}", quoted(dash)));
+        }
+        format!("({})", fields.join(", "))
+    }
+}
 
-        // Draw as individual line segments to avoid auto-closing
-        for window in points.windows(2) {
-            let from = window[0];
-            let to = window[1];
-            self.draw_line(from, to, style)?;
+/// The prefix of the `#let` bindings of a chart
+#[derive(Clone)]
+enum Namespace {
+    Prefix(String),
+    /// Derived from a hash of the chart when it is presented
+    Hashed,
+}
+
+/// Stands for the hashed namespace until the chart is complete
+const NAMESPACE_PLACEHOLDER: &str = "\u{1}namespace\u{1}";
+
+/// Encloses the index of the text settings of a label, which are only written once
+/// the settings shared by all labels are known
+const TEXT_STYLE_MARK: char = '\u{2}';
+
+/// The text settings Typst starts with, for text that must not pick up the settings
+/// hoisted from the labels
+#[cfg(feature = "plotters")]
+const TEXT_DEFAULTS: &str =
+    ", weight: \"regular\", style: \"normal\", top-edge: \"cap-height\", bottom-edge: \"baseline\"";
+
+/// The `set text` arguments of a label, in order
+type TextArgs = Vec<(&'static str, String)>;
+
+/// Distinct values written as marks until the chart is complete, such as the text
+/// settings of the labels or the marker shapes, with the number of times each is
+/// used
+struct Marked<T> {
+    values: Vec<(T, usize)>,
+    ids: HashMap<T, usize>,
+}
+
+impl<T> Default for Marked<T> {
+    fn default() -> Self {
+        Self {
+            values: Vec::new(),
+            ids: HashMap::new(),
         }
+    }
+}
 
-        Ok(())
+impl<T: Clone + Eq + Hash> Marked<T> {
+    /// The index of `value` enclosed in `mark`, counting one more use of it
+    fn mark(&mut self, value: T, mark: char) -> String {
+        let id = match self.ids.get(&value) {
+            Some(&id) => id,
+            None => {
+                self.values.push((value.clone(), 0));
+                self.ids.insert(value, self.values.len() - 1);
+                self.values.len() - 1
+            }
+        };
+        self.values[id].1 += 1;
+        format!("{m}{}{m}", id, m = mark)
     }
+}
 
-    fn fill_polygon<S: BackendStyle, I: IntoIterator<Item = BackendCoord>>(
-        &mut self,
-        path: I,
-        style: &S,
-    ) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
-        if style.color().alpha == 0.0 {
-            return Ok(());
-        }
+/// Encloses the index of a marker shape, written once bound or inline once it is
+/// known how often the shape repeats
+pub(crate) const MARKER_MARK: char = '\u{3}';
 
-        let points: Vec<_> = path.into_iter().collect();
-        if points.is_empty() {
-            return Ok(());
-        }
+/// How often a marker shape repeats before it is bound to a name once and placed by
+/// that name
+const MARKER_INSTANCE_MIN: usize = 16;
 
-        let color = make_typst_color(style.color());
+/// The filled rects standing on a common baseline written last, such as the bars
+/// of a histogram, in histogram batching mode
+struct Bars {
+    /// The byte range of their command in `buf`
+    command: Range<usize>,
+    /// The `fill:` and `stroke:` attributes they share
+    attrs: String,
+    /// The bottom edge they share
+    baseline: i32,
+    /// The right edge of each by its left edge, to tell whether a new one overlaps
+    spans: BTreeMap<i32, i32>,
+    /// The left, top and right edges of the first one, while it is still written as
+    /// a rect of its own
+    first: Option<(i32, i32, i32)>,
+}
 
-        let points_str = points
-            .iter()
-            .map(|(x, y)| format!("({}pt, {}pt)", x, y))
-            .collect::<Vec<_>>()
-            .join(", ");
+/// Encloses the name of the layer the commands after it belong to, in layers mode
+const LAYER_MARK: char = '\u{4}';
 
-        let cmd = format!(
-            "  #place(polygon(fill: {}, stroke: none, {}))",
-            color, points_str
-        );
-        self.write_command(&cmd);
-        Ok(())
-    }
+/// Encloses the number of the series the commands after it belong to, or nothing
+/// for the commands of the main file, in split series mode
+const SERIES_MARK: char = '\u{5}';
 
-    fn draw_circle<S: BackendStyle>(
-        &mut self,
-        center: BackendCoord,
-        radius: u32,
-        style: &S,
-        fill: bool,
-    ) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
-        if style.color().alpha == 0.0 {
-            return Ok(());
+/// The name the named points are bound to, in anchors mode
+const ANCHORS_BINDING: &str = "chart_anchors";
+
+/// The name of the function mapping data to canvas coordinates
+const COORD_BINDING: &str = "chart_coord";
+
+/// The names the layers are bound to, in layers mode
+const LAYER_BINDINGS: [&str; 2] = ["chart_frame", "chart_data"];
+
+/// Replace the indices enclosed in `mark` in `buf` by their `replacements`
+fn replace_marks(buf: &str, mark: char, replacements: &[String]) -> String {
+    let mut replaced = String::with_capacity(buf.len());
+    // The marks alternate with the commands around them
+    for (i, part) in buf.split(mark).enumerate() {
+        if i % 2 == 0 {
+            replaced.push_str(part);
+        } else if let Some(replacement) = part
+            .parse::<usize>()
+            .ok()
+            .and_then(|id| replacements.get(id))
+        {
+            replaced.push_str(replacement);
         }
+    }
+    replaced
+}
 
-        let color = make_typst_color(style.color());
-        let (fill_attr, stroke_attr) = if fill {
-            (format!("fill: {}", color), "stroke: none".to_string())
-        } else {
-            (
-                "fill: none".to_string(),
-                format!("stroke: {}pt + {}", style.stroke_width(), color),
-            )
-        };
+/// The page of a standalone document
+#[derive(Clone, Default)]
+struct Page {
+    header: Option<String>,
+    footer: Option<String>,
+}
 
-        // Typst circle is positioned by center minus radius to get top-left
-        let cmd = format!(
-            "  #place(dx: {}pt, dy: {}pt, circle(radius: {}pt, {}, {}))",
-            center.0 - radius as i32,
-            center.1 - radius as i32,
-            radius,
-            fill_attr,
-            stroke_attr
+impl Page {
+    /// The `set page` rule sizing the page to the chart
+    fn set_rule(&self) -> String {
+        // Leave room for the header and footer in the margins they are placed in
+        let margin = |markup: &Option<String>| if markup.is_some() { 48 } else { 20 };
+        let mut rule = format!(
+            "#set page(width: auto, height: auto, margin: (x: 20pt, top: {}pt, bottom: {}pt)",
+            margin(&self.header),
+            margin(&self.footer)
         );
-        self.write_command(&cmd);
-        Ok(())
+        if let Some(header) = &self.header {
+            write!(rule, ", header: [{}]", header).unwrap();
+        }
+        if let Some(footer) = &self.footer {
+            write!(rule, ", footer: [{}]", footer).unwrap();
+        }
+        rule.push_str(")\n");
+        rule
     }
+}
 
-    fn draw_text<S: BackendTextStyle>(
-        &mut self,
-        text: &str,
-        style: &S,
-        pos: BackendCoord,
-    ) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
-        let color = style.color();
-        if color.alpha == 0.0 {
-            return Ok(());
-        }
+/// A wrapper around the chart containing a [`TEMPLATE_PLACEHOLDER`]
+#[derive(Clone)]
+enum Template {
+    Markup(String),
+    File(PathBuf),
+}
 
-        let (x0, y0) = pos;
-        let text_color = make_typst_color(color);
-        let font_size = style.size() / 1.24; // Similar adjustment as SVG backend
-        let escaped_text = Self::escape_text(text);
+/// The placeholder of a template replaced by the chart
+pub const TEMPLATE_PLACEHOLDER: &str = "{{chart}}";
 
-        // Map generic font families to Typst fonts
-        let family_str = style.family();
-        let font_family = match family_str.as_str() {
-            "sans-serif" => "Liberation Sans",
-            "serif" => "Liberation Serif",
-            "monospace" => "Liberation Mono",
-            other => other,
+impl Template {
+    /// Split the template at its placeholder
+    fn split(&self) -> Result<(String, String), Error> {
+        let text = match self {
+            Template::Markup(markup) => markup.clone(),
+            Template::File(path) => std::fs::read_to_string(path)?,
         };
+        match text.split_once(TEMPLATE_PLACEHOLDER) {
+            Some((head, tail)) => Ok((head.to_string(), tail.to_string())),
+            None => Err(Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("template has no {} placeholder", TEMPLATE_PLACEHOLDER),
+            )),
+        }
+    }
+}
 
-        // For vertical alignment, we use top-edge and bottom-edge
-        // top-edge accepts: "ascender", "cap-height", "x-height", "baseline", "bounds", or length
-        // bottom-edge accepts: "baseline", "descender", "bounds", or length
-        let (top_edge, bottom_edge) = match style.anchor().v_pos {
-            VPos::Top => ("\"bounds\"", "\"bounds\""),
-            VPos::Center => ("\"cap-height\"", "\"baseline\""),
-            VPos::Bottom => ("\"baseline\"", "\"baseline\""),
-        };
+/// How the canvas is scaled when displayed
+#[derive(Clone, Copy, PartialEq)]
+enum Scaling {
+    /// A fixed factor
+    Factor(f64),
+    /// Fill the width of the including container
+    FitWidth,
+}
 
-        // Handle font style
-        let font_weight = match style.style() {
-            FontStyle::Bold => "\"bold\"",
-            _ => "\"regular\"",
-        };
+/// Lines drawn along a text
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Decoration {
+    pub(crate) underline: bool,
+    pub(crate) strike: bool,
+}
 
-        let font_style_attr = match style.style() {
-            FontStyle::Italic | FontStyle::Oblique => "\"italic\"",
-            _ => "\"normal\"",
-        };
+/// Colors of the debug overlay: bounding boxes and anchor crosses
+const DEBUG_FILL: &str = "rgb(255, 0, 255, 8%)";
+const DEBUG_STROKE: &str = "rgb(255, 0, 255, 60%)";
+const DEBUG_ANCHOR: &str = "rgb(0, 160, 255)";
 
-        // Handle rotation
-        let rotation_attr = match style.transform() {
-            FontTransform::Rotate90 => "rotate(90deg, ",
-            FontTransform::Rotate180 => "rotate(180deg, ",
-            FontTransform::Rotate270 => "rotate(270deg, ",
-            _ => "",
-        };
+/// The Typst drawing backend
+pub struct TypstBackend<'a> {
+    target: Target<'a>,
+    buf: String,
+    size: (u32, u32),
+    saved: bool,
+    background: Option<BackendColor>,
+    /// The slide step of the reveal group currently open
+    step: Option<String>,
+    /// The slide step given to the next automatic reveal group
+    next_step: u32,
+    /// `#let` bindings written before the canvas
+    bindings: String,
+    /// The number of series sections marked so far
+    series_sections: u32,
+    /// The series marked by the last section, if it marked one
+    series: Option<u32>,
+    /// The named points of the chart, in anchors mode
+    anchors: Vec<(String, BackendCoord)>,
+    /// The transform `(x0, x_scale, y0, y_scale)` from data to backend pixels given
+    /// by the last coordinate map
+    coord_map: Option<(f64, f64, f64, f64)>,
+    /// The corners of the clip regions open, innermost last
+    clips: Vec<(BackendCoord, BackendCoord)>,
+    /// The bounding box `(left, top, right, bottom)` of everything drawn so far,
+    /// which may reach past the canvas
+    bounds: Option<(i32, i32, i32, i32)>,
+    /// The palette colors given to the colors of the chart so far
+    palette_colors: RefCell<PaletteColors>,
+    /// The length of the appended file before the chart was first written
+    append_start: Option<u64>,
+    /// The text settings of the labels
+    text_styles: Marked<TextArgs>,
+    /// The marker shapes
+    markers: Marked<String>,
+    /// The bindings of the shapes drawn in bulk, by shape
+    bulk_shapes: HashMap<String, String>,
+    /// The bars written last, in histogram batching mode
+    bars: Option<Bars>,
+    /// The series that may be rasterized, in raster fallback mode
+    #[cfg(feature = "image")]
+    dense_groups: Vec<raster::DenseGroup>,
+    /// The index of the series drawn into, in raster fallback mode
+    #[cfg(feature = "image")]
+    dense_group: Option<usize>,
+    /// The drawing commands moved out of `buf`, in spill mode
+    spill: Option<spill::Spill>,
+    /// The number of primitives drawn, such as lines and labels, in total and by kind
+    primitives: usize,
+    elements: BTreeMap<&'static str, usize>,
+    /// The number of bytes of the document written when the chart was presented
+    written: usize,
+    /// What was drawn, in manifest mode
+    manifest: Manifest,
+    /// The font families used by the chart, as written to the output
+    #[cfg(feature = "fontdb")]
+    font_families: std::collections::BTreeSet<String>,
+    /// Where every emitted command is logged as a line of JSON
+    command_log: Option<Box<dyn Write + 'a>>,
+    /// The callback of [`Self::on_progress`], called every so many primitives
+    progress: Option<(usize, ProgressCallback<'a>)>,
+    /// In source map mode, the byte range of the commands written by each drawing
+    /// call in `buf`, with the call
+    calls: Vec<(Range<usize>, String)>,
+    /// The byte offset in `buf` where the drawing call open began
+    open_call: Option<usize>,
+    /// The document lines written by each drawing call, as of the last time the
+    /// document was completed
+    source_map: Vec<(Range<usize>, String)>,
+    /// The label of the figure, once the chart is wrapped in one
+    label: Option<String>,
+    options: Options,
+}
 
-        let rotation_close = if rotation_attr.is_empty() { "" } else { ")" };
+/// Escape `text` as markup of a label
+///
+/// Everything with a meaning in markup is escaped: strong and emphasis
+/// delimiters, brackets, references, comments, list markers and the like. When
+/// `literal`, as long as smart punctuation is not enabled, quotes, dashes,
+/// ellipses and tildes are escaped too, so a label like `"A"--'B'` reads as typed.
+/// A hyphen before a digit is left alone, to print as a minus sign. Control
+/// characters are dropped, but for white space, which turns into spaces.
+pub(crate) fn escape_markup(text: &str, literal: bool) -> String {
+    // Digits followed by a dot and white space at the start would make a list
+    let start = text.len() - text.trim_start().len();
+    let digits = text[start..].len()
+        - text[start..]
+            .trim_start_matches(|c: char| c.is_ascii_digit())
+            .len();
+    let enum_dot = (digits > 0 && text[start + digits..].starts_with('.'))
+        .then_some(start + digits)
+        .filter(|&dot| {
+            text[dot + 1..]
+                .chars()
+                .next()
+                .is_none_or(char::is_whitespace)
+        });
 
-        // Use a simple approach: text in a box with manual horizontal alignment
-        let aligned_text = match style.anchor().h_pos {
-            HPos::Left => escaped_text.clone(),
-            HPos::Right => {
-                // Right align: measure and shift
-                format!(
-                    "#context {{ let m = measure([{}]); h(-m.width); [{}] }}",
-                    escaped_text, escaped_text
-                )
+    let mut escaped = String::with_capacity(text.len());
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, c)| c);
+        let escape = match c {
+            '\t' | '\n' | '\r' => {
+                escaped.push(' ');
+                continue;
             }
-            HPos::Center => {
-                // Center align: measure and shift by half
-                format!(
-                    "#context {{ let m = measure([{}]); h(-m.width / 2); [{}] }}",
-                    escaped_text, escaped_text
-                )
+            c if c.is_control() => continue,
+            '\\' | '#' | '$' | '*' | '_' | '`' | '<' | '>' | '@' | '[' | ']' | '=' | '+' | '/' => {
+                true
             }
+            '"' | '\'' | '~' => literal,
+            // A list marker at the start, or the soft hyphen shorthand `-?`
+            '-' if i == start && next.is_none_or(char::is_whitespace) => true,
+            '-' if next == Some('?') => true,
+            '-' => literal && next == Some('-'),
+            '.' if Some(i) == enum_dot => true,
+            '.' => literal && next == Some('.'),
+            _ => false,
         };
-
-        let cmd = format!(
-            "  #place(dx: {}pt, dy: {}pt, {}box[#set text(size: {}pt, fill: {}, weight: {}, style: {}, font: \"{}\", top-edge: {}, bottom-edge: {}); {}]{})",
-            x0,
-            y0,
+        if escape {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl<'a> TypstBackend<'a> {
+    fn escape_text(text: &str) -> String {
+        text.replace('\\', r"\\")
+            .replace('"', r#"\""#)
+            .replace('#', r"\#")
+            .replace('$', r"\$")
+    }
+
+    /// Escape `text` as markup of a label, see [`escape_markup`]
+    pub(crate) fn escape_label(&self, text: &str) -> String {
+        escape_markup(text, !self.options.smart_punctuation)
+    }
+
+    /// Format `color` as a Typst color, remapped onto the colorblind palette and in
+    /// gray in grayscale mode, unless a serializer is set
+    pub(crate) fn typst_color(&self, color: BackendColor) -> String {
+        let color = self.palette_color(color);
+        if let Some(serializer) = &self.options.color_serializer {
+            serializer(color)
+        } else if self.options.grayscale {
+            make_typst_luma(color)
+        } else {
+            make_typst_color(color)
+        }
+    }
+
+    /// The color of the colorblind palette standing for `color`: each distinct hue
+    /// takes the next palette color when first drawn, while grays stay as they are
+    fn palette_color(&self, color: BackendColor) -> BackendColor {
+        let Some(palette) = self.options.palette else {
+            return color;
+        };
+        let (r, g, b) = color.rgb;
+        if r.max(g).max(b) - r.min(g).min(b) < 16 {
+            return color;
+        }
+        let mut assigned = self.palette_colors.borrow_mut();
+        let colors = palette.colors();
+        let next = colors[assigned.len() % colors.len()];
+        BackendColor {
+            rgb: *assigned.entry(color.rgb).or_insert(next),
+            alpha: color.alpha,
+        }
+    }
+
+    /// Format `value` backend pixels as a Typst length in the output unit
+    pub(crate) fn len<T: Into<f64>>(&self, value: T) -> Length {
+        Length {
+            value: value.into(),
+            em: self.options.em,
+            rounded: self.options.reproducible,
+        }
+    }
+
+    /// Like [`Self::len`], but resolved to an absolute length so it can be compared
+    /// with measured sizes; only valid inside a context
+    pub(crate) fn abs_len<T: Into<f64>>(&self, value: T) -> String {
+        match self.options.em {
+            Some(_) => format!("({}).to-absolute()", self.len(value)),
+            None => self.len(value).to_string(),
+        }
+    }
+
+    fn write_command(&mut self, command: &str) {
+        self.buf.push_str(command);
+        self.buf.push('\n');
+    }
+
+    /// Like [`Self::write_command`], formatting the command straight into the
+    /// buffer instead of into a string of its own
+    fn write_command_args(&mut self, command: fmt::Arguments) {
+        self.buf.write_fmt(command).unwrap();
+        self.buf.push('\n');
+    }
+
+    /// In source map mode, attribute the commands written until [`Self::end_call`] to
+    /// the drawing call described by `call`, marked with a comment, unless they are
+    /// part of an enclosing call already; returns whether the call was begun
+    pub(crate) fn begin_call<F: FnOnce() -> String>(&mut self, call: F) -> bool {
+        if !self.options.source_map || self.open_call.is_some() {
+            return false;
+        }
+        let (start, call) = (self.buf.len(), call());
+        self.open_call = Some(start);
+        self.write_command(&format!("  // source: {}", call));
+        self.calls.push((start..start, call));
+        true
+    }
+
+    /// End the drawing call begun by [`Self::begin_call`] if it returned `begun`, and
+    /// spill the commands to disk if they grew beyond the cap of
+    /// [`Self::spill_to_disk`]
+    pub(crate) fn end_call(&mut self, begun: bool) -> Result<(), DrawingErrorKind<Error>> {
+        if begun {
+            if let (Some(start), Some(call)) = (self.open_call.take(), self.calls.last_mut()) {
+                call.0 = start..self.buf.len();
+            }
+        }
+        let bytes = self.bindings.len() + self.spilled_bytes() + self.buf.len();
+        self.check_cancelled()
+            .and_then(|_| self.check_budget(bytes))
+            .and_then(|_| self.spill_commands())
+            .map_err(DrawingErrorKind::DrawingError)
+    }
+
+    /// Fail if the flag given to [`Self::cancel_on`] is set
+    fn check_cancelled(&self) -> Result<(), Error> {
+        match &self.options.cancel {
+            Some(flag) if flag.load(Ordering::Relaxed) => Err(Error::new(
+                std::io::ErrorKind::Interrupted,
+                format!(
+                    "the chart was cancelled after {} primitives, its output is incomplete",
+                    self.primitives
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail if the chart, of `bytes` bytes so far, exceeds the budgets of
+    /// [`Self::size_budget`] and [`Self::primitive_budget`]
+    fn check_budget(&self, bytes: usize) -> Result<(), Error> {
+        let exceeded = match (self.options.max_bytes, self.options.max_primitives) {
+            (Some(max), _) if bytes > max => format!("{} bytes of at most {}", bytes, max),
+            (_, Some(max)) if self.primitives > max => {
+                format!("{} primitives of at most {}", self.primitives, max)
+            }
+            _ => return Ok(()),
+        };
+        Err(Error::new(
+            std::io::ErrorKind::FileTooLarge,
+            format!(
+                "the chart exceeds its size budget with {}; decimate or rasterize its dense series",
+                exceeded
+            ),
+        ))
+    }
+
+    /// Turn the byte ranges of the drawing calls into ranges of 1-based lines of the
+    /// drawing commands, before placeholders change the length of the lines
+    fn resolve_calls(&self) -> Vec<(Range<usize>, String)> {
+        let mut line = 1;
+        let mut offset = 0;
+        let mut line_at = |byte: usize| {
+            line += self.buf[offset..byte].matches('\n').count();
+            offset = byte;
+            line
+        };
+        self.calls
+            .iter()
+            .map(|(range, call)| {
+                let start = line_at(range.start);
+                (start..line_at(range.end), call.clone())
+            })
+            .collect()
+    }
+
+    /// Wrap the following commands in a guard showing them from slide `step` on,
+    /// closing the group open before
+    #[cfg(feature = "plotters")]
+    pub(crate) fn begin_step(&mut self, step: &str) {
+        self.set_step(Some(step.to_string()));
+    }
+
+    /// Close the reveal group open, if any, and begin one showing the following
+    /// commands from slide `step` on if there is one
+    ///
+    /// The reveal group encloses the clip regions open, which are closed before and
+    /// reopened after, so their brackets never interleave.
+    fn set_step(&mut self, step: Option<String>) {
+        if self.step.is_none() && step.is_none() {
+            return;
+        }
+        let clips = std::mem::take(&mut self.clips);
+        for _ in &clips {
+            self.pop_clip_command();
+        }
+        if self.step.take().is_some() {
+            self.write_command("  ]");
+        }
+        if let Some(step) = step {
+            let function = self.options.reveal_function.as_deref().unwrap_or("only");
+            let cmd = format!("  #{}(\"{}\")[", function, Self::escape_text(&step));
+            self.write_command(&cmd);
+            self.step = Some(step);
+        }
+        for (upper_left, bottom_right) in clips {
+            self.push_clip(upper_left, bottom_right);
+        }
+    }
+
+    /// Begin a group shown from the next automatic step on
+    #[cfg(feature = "plotters")]
+    pub(crate) fn begin_next_step(&mut self) {
+        let step = format!("{}-", self.next_step);
+        self.next_step += 1;
+        self.begin_step(&step);
+    }
+
+    /// The identifier `name` is bound to, with the namespace of the chart
+    pub(crate) fn binding_name(&self, name: &str) -> String {
+        match &self.options.namespace {
+            Some(Namespace::Prefix(prefix)) => format!("{}_{}", prefix, name),
+            Some(Namespace::Hashed) => format!("{}_{}", NAMESPACE_PLACEHOLDER, name),
+            None => name.to_string(),
+        }
+    }
+
+    /// Bind `value` to `name` before the canvas, where the binding is visible to a
+    /// document importing the chart
+    pub(crate) fn write_binding(&mut self, name: &str, value: &str) {
+        let name = self.binding_name(name);
+        writeln!(self.bindings, "#let {} = {}", name, value).unwrap();
+    }
+
+    /// Replace the placeholder of a hashed namespace by a prefix derived from the
+    /// content, so identical charts share names and different ones do not collide
+    fn resolve_namespace(&mut self) {
+        if !matches!(self.options.namespace, Some(Namespace::Hashed)) {
+            return;
+        }
+        let hash = if self.options.reproducible {
+            stable_hash(&[&self.bindings, &self.buf])
+        } else {
+            let mut hasher = DefaultHasher::new();
+            self.bindings.hash(&mut hasher);
+            self.buf.hash(&mut hasher);
+            hasher.finish()
+        };
+        let prefix = format!("c{:08x}", hash as u32);
+        self.bindings = self.bindings.replace(NAMESPACE_PLACEHOLDER, &prefix);
+        if self.buf.contains(NAMESPACE_PLACEHOLDER) {
+            self.buf = self.buf.replace(NAMESPACE_PLACEHOLDER, &prefix);
+        }
+        // The markup around the canvas is built afterwards, with the final names
+        self.options.namespace = Some(Namespace::Prefix(prefix));
+    }
+
+    /// A mark standing for the `set text` rule of a label with the settings `args`,
+    /// see [`Self::resolve_text_styles`]
+    fn text_style_mark(&mut self, args: TextArgs) -> String {
+        self.text_styles.mark(args, TEXT_STYLE_MARK)
+    }
+
+    /// Hoist the text settings shared by the labels into one `set text` rule, which
+    /// is returned to open the block of the commands, and leave only the deviations
+    /// to the rules of the labels
+    fn resolve_text_styles(&mut self) -> String {
+        let styles = &self.text_styles.values;
+        let Some((first, _)) = styles.first() else {
+            return String::new();
+        };
+
+        // Every setting all labels have takes the value most labels use; black text
+        // may inherit its color, so there is no color for all text
+        let mut shared: TextArgs = Vec::new();
+        for (key, _) in first {
+            if *key == "fill" && self.options.inherit_text_color {
+                continue;
+            }
+            let mut counts: HashMap<&str, usize> = HashMap::new();
+            for (args, count) in styles {
+                if let Some((_, value)) = args.iter().find(|(k, _)| k == key) {
+                    *counts.entry(value).or_default() += count;
+                }
+            }
+            if counts.values().sum::<usize>() < styles.iter().map(|(_, count)| count).sum() {
+                continue;
+            }
+            // Ties are broken by the value so the output does not depend on hashing
+            if let Some((value, _)) = counts
+                .into_iter()
+                .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(a.0)))
+            {
+                shared.push((key, value.to_string()));
+            }
+        }
+
+        let rules: Vec<String> = styles
+            .iter()
+            .map(|(args, _)| {
+                let own: Vec<String> = args
+                    .iter()
+                    .filter(|arg| !shared.contains(arg))
+                    .map(|(key, value)| format!("{}: {}", key, value))
+                    .collect();
+                if own.is_empty() {
+                    String::new()
+                } else {
+                    format!("#set text({}); ", own.join(", "))
+                }
+            })
+            .collect();
+
+        self.resolve_marks(TEXT_STYLE_MARK, rules);
+
+        let shared: Vec<String> = shared
+            .iter()
+            .map(|(key, value)| format!("{}: {}", key, value))
+            .collect();
+        format!(
+            "  #set text({}{})\n",
+            shared.join(", "),
+            self.text_features()
+        )
+    }
+
+    /// A mark standing for the marker `shape`, see [`Self::resolve_markers`]
+    pub(crate) fn marker_mark(&mut self, shape: String) -> String {
+        self.markers.mark(shape, MARKER_MARK)
+    }
+
+    /// Replace the marks enclosed in `mark` with what they stand for, in the commands
+    /// held in memory and in those spilled to disk as they are read back
+    fn resolve_marks(&mut self, mark: char, replacements: Vec<String>) {
+        self.buf = replace_marks(&self.buf, mark, &replacements);
+        if let Some(spill) = &mut self.spill {
+            // A flushed chart is resolved again, with what the marks stand for now
+            spill.marks.retain(|(m, _)| *m != mark);
+            spill.marks.push((mark, replacements));
+        }
+    }
+
+    /// Bind the marker shapes placed many times to names, so a large scatter plot
+    /// describes its marker once, and write the other shapes inline
+    fn resolve_markers(&mut self) {
+        if self.markers.values.is_empty() {
+            return;
+        }
+        let mut instances = 0;
+        let mut replacements = Vec::with_capacity(self.markers.values.len());
+        // The shapes stay recorded, for a flushed chart still to be continued
+        for (shape, count) in self.markers.values.clone() {
+            if count >= MARKER_INSTANCE_MIN {
+                instances += 1;
+                let name = format!("marker-{}", instances);
+                self.write_binding(&name, &shape);
+                replacements.push(self.binding_name(&name));
+            } else {
+                replacements.push(shape);
+            }
+        }
+        self.resolve_marks(MARKER_MARK, replacements);
+    }
+
+    /// Write a comment marking the start of the chart part `label`, or of the next
+    /// series when there is no label
+    #[cfg(feature = "plotters")]
+    pub(crate) fn write_section(&mut self, label: Option<&str>) {
+        let (label, series) = match label {
+            Some(label) => {
+                self.series = None;
+                #[cfg(feature = "image")]
+                self.end_dense_group();
+                (label.replace(['\n', '\r'], " "), String::new())
+            }
+            None => {
+                self.series_sections += 1;
+                self.series = Some(self.series_sections);
+                let series = self.series_sections.to_string();
+                let label = format!("series {}", series);
+                #[cfg(feature = "image")]
+                self.begin_dense_group(label.clone());
+                (label, series)
+            }
+        };
+        let split = self.options.split_series
+            && !self.options.layers
+            && matches!(self.target, Target::File(_));
+        // The section comment ends the series before and stays in the main file
+        if split {
+            write!(self.buf, "{m}{m}", m = SERIES_MARK).unwrap();
+        }
+        self.write_command(&format!("  // section: {}", label));
+        if split && !series.is_empty() {
+            write!(self.buf, "{m}{}{m}", series, m = SERIES_MARK).unwrap();
+        }
+        if self.options.manifest {
+            self.manifest.begin_group(&label);
+        }
+    }
+
+    /// In layers mode, put the commands to come in the data layer if `data`, or in
+    /// the frame layer
+    #[cfg(feature = "plotters")]
+    pub(crate) fn begin_layer(&mut self, data: bool) -> Result<(), Error> {
+        if !self.options.layers {
+            return Ok(());
+        }
+        if self.step.is_some() || !self.clips.is_empty() {
+            return Err(Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "cannot switch layers within a clip region or reveal group",
+            ));
+        }
+        #[cfg(feature = "image")]
+        match data {
+            true => self.begin_dense_group("data".to_string()),
+            false => self.end_dense_group(),
+        }
+        write!(
+            self.buf,
+            "{m}{}{m}",
+            LAYER_BINDINGS[data as usize],
+            m = LAYER_MARK
+        )
+        .unwrap();
+        Ok(())
+    }
+
+    /// In layers mode, bind the commands of each layer to its name and place the
+    /// layers by name, the frame below the data; the hoisted text rule `text_style`
+    /// is moved to the top of each layer, which is placed on its own as well
+    fn resolve_layers(&mut self, text_style: &mut String) {
+        if !self.options.layers {
+            return;
+        }
+        let text_style = std::mem::take(text_style);
+        let mut layers = [String::new(), String::new()];
+        let mut layer = 0;
+        // The layer names alternate with the commands of the layers
+        for (i, part) in self.buf.split(LAYER_MARK).enumerate() {
+            if i % 2 == 0 {
+                layers[layer].push_str(part);
+            } else {
+                layer = (part == LAYER_BINDINGS[1]) as usize;
+            }
+        }
+        self.buf.clear();
+        for (name, commands) in LAYER_BINDINGS.iter().zip(layers) {
+            self.write_binding(name, &format!("[\n{}{}]", text_style, commands));
+            let name = self.binding_name(name);
+            self.write_command(&format!("  #{}", name));
+        }
+    }
+
+    /// In anchors mode, name `point`, replacing the point named `name` before
+    pub(crate) fn set_anchor(&mut self, name: &str, point: BackendCoord) {
+        if !self.options.anchors {
+            return;
+        }
+        match self.anchors.iter_mut().find(|(n, _)| n == name) {
+            Some((_, p)) => *p = point,
+            None => self.anchors.push((name.to_string(), point)),
+        }
+    }
+
+    /// In anchors mode, track `point` as the last point of the series marked by the
+    /// last section, and as its first point if it has none yet
+    fn note_series_point(&mut self, point: BackendCoord) {
+        let Some(series) = self.series.filter(|_| self.options.anchors) else {
+            return;
+        };
+        let first = format!("series-{}-first", series);
+        if !self.anchors.iter().any(|(name, _)| *name == first) {
+            self.set_anchor(&first, point);
+        }
+        self.set_anchor(&format!("series-{}-last", series), point);
+    }
+
+    /// In anchors mode, bind the named points to `chart_anchors`, as offsets from
+    /// the upper left corner of the canvas
+    fn resolve_anchors(&mut self) {
+        if self.anchors.is_empty() {
+            return;
+        }
+        let (left, top, _, _) = self.crop_area();
+        let padding = self.options.padding as i32;
+        let entries: Vec<String> = self
+            .anchors
+            .iter()
+            .map(|(name, (x, y))| {
+                format!(
+                    "\"{}\": ({}, {})",
+                    Self::escape_text(name),
+                    self.len(x - left + padding),
+                    self.len(y - top + padding)
+                )
+            })
+            .collect();
+        let dict = format!("({})", entries.join(", "));
+        self.write_binding(ANCHORS_BINDING, &dict);
+    }
+
+    /// Map data to canvas coordinates with the transform taking the data point
+    /// `data[i]` to the backend pixel `pixels[i]`, along each axis
+    #[cfg(feature = "plotters")]
+    pub(crate) fn set_coord_map(
+        &mut self,
+        data: [(f64, f64); 2],
+        pixels: [BackendCoord; 2],
+    ) -> Result<(), Error> {
+        let [(x0, y0), (x1, y1)] = data;
+        if x0 == x1 || y0 == y1 {
+            return Err(Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the corners of a coordinate map must differ along both axes",
+            ));
+        }
+        let [(px0, py0), (px1, py1)] = pixels;
+        let x_scale = f64::from(px1 - px0) / (x1 - x0);
+        let y_scale = f64::from(py1 - py0) / (y1 - y0);
+        self.coord_map = Some((
+            f64::from(px0) - x0 * x_scale,
+            x_scale,
+            f64::from(py0) - y0 * y_scale,
+            y_scale,
+        ));
+        Ok(())
+    }
+
+    /// Bind the function mapping data to canvas coordinates, if a coordinate map
+    /// was drawn
+    fn resolve_coord_map(&mut self) {
+        let Some((x0, x_scale, y0, y_scale)) = self.coord_map else {
+            return;
+        };
+        let (left, top, _, _) = self.crop_area();
+        let padding = f64::from(self.options.padding);
+        let name = self.binding_name(COORD_BINDING);
+        writeln!(
+            self.bindings,
+            "#let {}(x, y) = ({} + x * {}, {} + y * {})",
+            name,
+            self.len(x0 - f64::from(left) + padding),
+            self.len(x_scale),
+            self.len(y0 - f64::from(top) + padding),
+            self.len(y_scale)
+        )
+        .unwrap();
+    }
+
+    /// Close the open reveal group, if any
+    pub(crate) fn end_step(&mut self) {
+        self.set_step(None);
+    }
+
+    /// Begin a region clipped to the rectangle from `upper_left` to `bottom_right`,
+    /// in backend pixels: nothing drawn until the matching [`Self::pop_clip`] shows
+    /// outside of it
+    ///
+    /// Meant for custom elements restricting their own rendering, as clip paths do
+    /// in SVG. Regions nest, and the ones still open are closed when the chart is
+    /// presented. A reveal group begun or ended within a region applies to the
+    /// commands that follow, within the region and after it, as anywhere else. See
+    /// [`TypstClip`](crate::element::TypstClip) to clip a drawing area.
+    pub fn push_clip(&mut self, upper_left: BackendCoord, bottom_right: BackendCoord) {
+        let (x0, y0) = upper_left;
+        let (x1, y1) = bottom_right;
+        let (width, height) = self.size;
+        // The content is moved back to the origin of the canvas, so the commands
+        // inside the region keep their coordinates
+        let cmd = format!(
+            "  #place(dx: {}, dy: {}, box(width: {}, height: {}, clip: true, place(dx: {}, dy: {}, box(width: {}, height: {})[",
+            self.len(x0),
+            self.len(y0),
+            self.len((x1 - x0).max(0)),
+            self.len((y1 - y0).max(0)),
+            self.len(-x0),
+            self.len(-y0),
+            self.len(width),
+            self.len(height)
+        );
+        self.write_command(&cmd);
+        self.float_safe_container(self.size);
+        self.clips.push((upper_left, bottom_right));
+    }
+
+    /// End the innermost clip region; without one, nothing happens
+    pub fn pop_clip(&mut self) {
+        if self.clips.pop().is_some() {
+            self.pop_clip_command();
+        }
+    }
+
+    /// Write the end of a clip region
+    fn pop_clip_command(&mut self) {
+        self.write_command("  ])))");
+    }
+
+    /// Close the clip regions and the reveal group still open, innermost first
+    fn end_groups(&mut self) {
+        while !self.clips.is_empty() {
+            self.pop_clip();
+        }
+        self.end_step();
+    }
+
+    /// In debug overlay mode, outline the area from `upper_left` to `bottom_right`
+    /// and mark `anchor` with a cross
+    fn write_debug_overlay(
+        &mut self,
+        upper_left: BackendCoord,
+        bottom_right: BackendCoord,
+        anchor: BackendCoord,
+    ) {
+        if !self.options.debug_overlay {
+            return;
+        }
+        let (x0, x1) = (
+            upper_left.0.min(bottom_right.0),
+            upper_left.0.max(bottom_right.0),
+        );
+        let (y0, y1) = (
+            upper_left.1.min(bottom_right.1),
+            upper_left.1.max(bottom_right.1),
+        );
+        let cmd = format!(
+            "  #place(dx: {}, dy: {}, rect(width: {}, height: {}, fill: {}, stroke: {} + {}))",
+            self.len(x0),
+            self.len(y0),
+            self.len(x1 - x0),
+            self.len(y1 - y0),
+            DEBUG_FILL,
+            self.len(0.5),
+            DEBUG_STROKE
+        );
+        self.write_command(&cmd);
+        self.write_debug_anchor(anchor);
+    }
+
+    /// In debug overlay mode, mark `anchor` with a cross
+    fn write_debug_anchor(&mut self, (x, y): BackendCoord) {
+        if !self.options.debug_overlay {
+            return;
+        }
+        for (dx, dy, angle) in [(-3, 0, 0), (0, -3, 90)] {
+            let cmd = format!(
+                "  #place(dx: {}, dy: {}, line(length: {}, angle: {}deg, stroke: {} + {}))",
+                self.len(x + dx),
+                self.len(y + dy),
+                self.len(6),
+                angle,
+                self.len(0.5),
+                DEBUG_ANCHOR
+            );
+            self.write_command(&cmd);
+        }
+    }
+
+    /// Grow the bounding box of the drawing to cover the area from `upper_left` to
+    /// `bottom_right`, widened by `margin` on every side, e.g. for half a stroke
+    pub(crate) fn include_bounds(
+        &mut self,
+        upper_left: BackendCoord,
+        bottom_right: BackendCoord,
+        margin: i32,
+    ) {
+        let (x0, x1) = (
+            upper_left.0.min(bottom_right.0) - margin,
+            upper_left.0.max(bottom_right.0) + margin,
+        );
+        let (y0, y1) = (
+            upper_left.1.min(bottom_right.1) - margin,
+            upper_left.1.max(bottom_right.1) + margin,
+        );
+        self.bounds = Some(grow(self.bounds, (x0, y0, x1, y1)));
+        if self.options.manifest {
+            self.manifest.include((x0, y0, x1, y1));
+        }
+    }
+
+    /// In command log mode, write the entry `entry` describes to the log
+    pub(crate) fn log_command<F: FnOnce() -> JsonObject>(
+        &mut self,
+        entry: F,
+    ) -> Result<(), DrawingErrorKind<Error>> {
+        match &mut self.command_log {
+            Some(log) => writeln!(log, "{}", entry()).map_err(DrawingErrorKind::DrawingError),
+            None => Ok(()),
+        }
+    }
+
+    /// Record that the chart uses the font family `family`, as written to the output
+    pub(crate) fn note_font(&mut self, family: &str) {
+        #[cfg(feature = "fontdb")]
+        if !self.font_families.contains(family) {
+            self.font_families.insert(family.to_string());
+        }
+        #[cfg(not(feature = "fontdb"))]
+        let _ = family;
+    }
+
+    /// Count an element of `kind`, such as `"line"`, for the manifest in manifest
+    /// mode
+    pub(crate) fn note_element(&mut self, kind: &'static str) {
+        self.primitives += 1;
+        *self.elements.entry(kind).or_default() += 1;
+        if let Some((every, _)) = self.progress {
+            if self.primitives.is_multiple_of(every) {
+                let progress = Progress {
+                    primitives: self.primitives,
+                    bytes: self.bindings.len() + self.spilled_bytes() + self.buf.len(),
+                };
+                let (_, callback) = self.progress.as_mut().unwrap();
+                callback(progress);
+            }
+        }
+        if self.options.manifest {
+            self.manifest.element(kind);
+        }
+    }
+
+    /// In manifest mode, record the label `text`, estimated to cover `bounds`
+    pub(crate) fn note_label(&mut self, text: &str, bounds: (i32, i32, i32, i32)) {
+        if self.options.manifest {
+            self.manifest.label(text, bounds);
+        }
+    }
+
+    /// Grow the bounding box of the drawing to cover all of `points`
+    pub(crate) fn include_points(&mut self, points: &[BackendCoord], margin: i32) {
+        let Some(&first) = points.first() else {
+            return;
+        };
+        let upper_left = points
+            .iter()
+            .fold(first, |a, p| (a.0.min(p.0), a.1.min(p.1)));
+        let bottom_right = points
+            .iter()
+            .fold(first, |a, p| (a.0.max(p.0), a.1.max(p.1)));
+        self.include_bounds(upper_left, bottom_right, margin);
+    }
+
+    /// How far a stroke of `width` backend pixels reaches past the outline it follows
+    pub(crate) fn half_stroke<T: Into<f64>>(&self, width: T) -> i32 {
+        (width.into() * self.options.stroke_scale.unwrap_or(1.0) / 2.0).ceil() as i32
+    }
+
+    /// How far the stroke of a closed shape drawn with [`Self::shape_attrs`] reaches
+    /// past its outline
+    pub(crate) fn shape_margin<S: BackendStyle>(
+        &self,
+        style: &S,
+        fill: bool,
+        outlined: bool,
+    ) -> i32 {
+        match self.options.outline {
+            _ if !fill => self.half_stroke(style.stroke_width()),
+            Some((color, width)) if outlined && color.alpha > 0.0 => self.half_stroke(width),
+            _ => 0,
+        }
+    }
+
+    /// The area of the canvas kept in the output, `(left, top, width, height)`: the
+    /// bounding box of the drawing in auto-crop mode, the whole canvas otherwise
+    fn crop_area(&self) -> (i32, i32, u32, u32) {
+        let (width, height) = (self.size.0 as i32, self.size.1 as i32);
+        match self.bounds {
+            Some((l, t, r, b)) if self.options.auto_crop => {
+                // Whatever lies outside of the canvas was clipped anyway
+                let (l, t) = (l.clamp(0, width), t.clamp(0, height));
+                let (r, b) = (r.clamp(l, width), b.clamp(t, height));
+                (l, t, (r - l) as u32, (b - t) as u32)
+            }
+            _ => (0, 0, self.size.0, self.size.1),
+        }
+    }
+
+    fn covers_canvas(&self, upper_left: BackendCoord, bottom_right: BackendCoord) -> bool {
+        upper_left.0 <= 0
+            && upper_left.1 <= 0
+            && bottom_right.0 >= self.size.0 as i32 - 1
+            && bottom_right.1 >= self.size.1 as i32 - 1
+    }
+
+    /// Format a stroke of `width` backend pixels in `color`, merged with the stroke
+    /// defaults of the chart
+    pub(crate) fn stroke<T: Into<f64>>(&self, width: T, color: &str) -> String {
+        let width = width.into() * self.options.stroke_scale.unwrap_or(1.0);
+        match self.options.stroke {
+            Some(_) => format!(
+                "(..{}, thickness: {}, paint: {})",
+                self.binding_name(STROKE_BINDING),
+                self.len(width),
+                color
+            ),
+            None => format!("{} + {}", self.len(width), color),
+        }
+    }
+
+    /// Build the `fill:` and `stroke:` attributes of a closed shape
+    ///
+    /// In compact mode, the attribute Typst defaults to the same value is left out:
+    /// a shape is not filled by default, and a filled shape has no stroke.
+    pub(crate) fn shape_attrs<S: BackendStyle>(
+        &self,
+        style: &S,
+        fill: bool,
+        outlined: bool,
+    ) -> String {
+        let color = self.typst_color(style.color());
+        let compact = self.options.compact;
+        if !fill {
+            let stroke = self.stroke(style.stroke_width(), &color);
+            return match compact {
+                true => format!("stroke: {}", stroke),
+                false => format!("fill: none, stroke: {}", stroke),
+            };
+        }
+
+        match self.options.outline {
+            Some((outline_color, width)) if outlined && outline_color.alpha > 0.0 => format!(
+                "fill: {}, stroke: {}",
+                color,
+                self.stroke(width, &self.typst_color(outline_color))
+            ),
+            _ if compact => format!("fill: {}", color),
+            _ => format!("fill: {}, stroke: none", color),
+        }
+    }
+
+    /// In histogram batching mode, merge the filled rect from `upper_left` to
+    /// `bottom_right` with `attrs` into the bars written last if it continues them;
+    /// returns whether it was merged, and otherwise has to be written as a rect
+    fn batch_bar(
+        &mut self,
+        upper_left: BackendCoord,
+        bottom_right: BackendCoord,
+        attrs: &str,
+    ) -> bool {
+        let options = &self.options;
+        if !options.batch_bars
+            || options.outline.is_some()
+            || options.source_map
+            || options.debug_overlay
+        {
+            return false;
+        }
+        let (left, right) = (
+            upper_left.0.min(bottom_right.0),
+            upper_left.0.max(bottom_right.0),
+        );
+        let (top, bottom) = (
+            upper_left.1.min(bottom_right.1),
+            upper_left.1.max(bottom_right.1),
+        );
+        let continued = self.bars.as_ref().is_some_and(|bars| {
+            bars.command.end == self.buf.len()
+                && bars.attrs == attrs
+                && bars.baseline == bottom
+                && bars
+                    .spans
+                    .range(..right)
+                    .next_back()
+                    .is_none_or(|(_, &end)| end <= left)
+        });
+        if !continued {
+            // The rect is written as it is, and may start new bars
+            let start = self.buf.len();
+            self.bars = Some(Bars {
+                command: start..start,
+                attrs: attrs.to_string(),
+                baseline: bottom,
+                spans: BTreeMap::from([(left, right)]),
+                first: Some((left, top, right)),
+            });
+            return false;
+        }
+
+        let mut bars = self.bars.take().unwrap();
+        if let Some((first_left, first_top, first_right)) = bars.first.take() {
+            // The second bar turns the rect of the first one into a polygon
+            self.buf.truncate(bars.command.start);
+            write!(self.buf, "  #place(polygon({}", attrs).unwrap();
+            self.write_bar_outline(first_left, first_top, first_right, bottom);
+        } else {
+            // Reopen the polygon to append the outline of the bar
+            self.buf.truncate(bars.command.end - "))\n".len());
+        }
+        self.write_bar_outline(left, top, right, bottom);
+        self.buf.push_str("))\n");
+        bars.command.end = self.buf.len();
+        bars.spans.insert(left, right);
+        self.bars = Some(bars);
+        true
+    }
+
+    /// Write the corners of a bar on the baseline `bottom` as polygon vertices, up
+    /// its left edge and down its right one
+    fn write_bar_outline(&mut self, left: i32, top: i32, right: i32, bottom: i32) {
+        for (x, y) in [(left, bottom), (left, top), (right, top), (right, bottom)] {
+            let (x, y) = (self.len(x), self.len(y));
+            write!(self.buf, ", ({}, {})", x, y).unwrap();
+        }
+    }
+
+    /// In compact mode, drop the zero offsets of the placed commands, which Typst
+    /// defaults to
+    fn compact_commands(&mut self) {
+        if !self.options.compact {
+            return;
+        }
+        let zero = self.len(0).to_string();
+        self.buf = compact_lines(&self.buf, &zero);
+        if let Some(spill) = &mut self.spill {
+            spill.zero = Some(zero);
+        }
+    }
+
+    /// The markup opening the canvas, written before all drawing commands
+    fn open_canvas(&self) -> String {
+        // The padding is the inset of the box, around the drawing
+        let padding = self.options.padding;
+        let (left, top, crop_width, crop_height) = self.crop_area();
+        let (width, height) = (crop_width + 2 * padding, crop_height + 2 * padding);
+        // The box bottom sits on the baseline by default, lower it so row `y` does
+        let baseline_shift = self
+            .options
+            .baseline
+            .map(|y| height as i32 - padding as i32 - y + top);
+
+        let mut open = String::new();
+        match self.options.scaling {
+            Some(scaling) => {
+                let factor = match scaling {
+                    Scaling::Factor(factor) => factor.to_string(),
+                    Scaling::FitWidth => {
+                        write!(
+                            open,
+                            "#layout(size => {{ let s = size.width / {}; ",
+                            self.abs_len(width)
+                        )
+                        .unwrap();
+                        "s".to_string()
+                    }
+                };
+                if scaling != Scaling::FitWidth {
+                    open.push('#');
+                }
+                // The outer box reserves the scaled size and carries the baseline
+                write!(
+                    open,
+                    "box(width: {f} * {}, height: {f} * {}",
+                    self.len(width),
+                    self.len(height),
+                    f = factor
+                )
+                .unwrap();
+                if let Some(shift) = baseline_shift {
+                    write!(open, ", baseline: {} * {}", factor, self.len(shift)).unwrap();
+                }
+                write!(
+                    open,
+                    ", scale(x: {f} * 100%, y: {f} * 100%, origin: top + left, ",
+                    f = factor
+                )
+                .unwrap();
+            }
+            None => open.push('#'),
+        }
+
+        // Create a box with absolute positioning for the canvas, which clips unless
+        // it flows inline with text or may overflow. A cropped canvas leaves the
+        // clipping to the full one inside, as the estimated extent of a label may
+        // fall short of the text Typst lays out.
+        let clip = !(self.options.inline || self.options.overflow_visible);
+        let cropped = (crop_width, crop_height) != self.size;
+        write!(
+            open,
+            "box(width: {}, height: {}, clip: {}",
+            self.len(width),
+            self.len(height),
+            clip && !cropped
+        )
+        .unwrap();
+        if let (Some(shift), None) = (baseline_shift, self.options.scaling) {
+            write!(open, ", baseline: {}", self.len(shift)).unwrap();
+        }
+        if padding > 0 {
+            write!(open, ", inset: {}", self.len(padding)).unwrap();
+        }
+        if let (Some(color), false) = (self.background, self.options.transparent) {
+            write!(open, ", fill: {}", self.typst_color(color)).unwrap();
+        }
+        if let Some((color, width, radius)) = self.options.frame {
+            write!(
+                open,
+                ", stroke: {}",
+                self.stroke(width, &self.typst_color(color))
+            )
+            .unwrap();
+            if radius > 0 {
+                write!(open, ", radius: {}", self.len(radius)).unwrap();
+            }
+        }
+        open.push_str(")[\n");
+        if self.options.float_safe {
+            open.push_str(&self.float_safe_place((crop_width, crop_height)));
+        }
+        // The underlays are drawn under everything, in the cropped canvas
+        let info = self.canvas_info();
+        for underlay in &self.options.underlays {
+            open.push_str(&underlay(&info));
+            if !open.ends_with('\n') {
+                open.push('\n');
+            }
+        }
+        // A cropped canvas shows a window into the full one, so the placements and
+        // the alignment of labels keep referring to the full canvas
+        if cropped {
+            writeln!(
+                open,
+                "  #place(dx: {}, dy: {}, box(width: {}, height: {}, clip: {})[",
+                self.len(-left),
+                self.len(-top),
+                self.len(self.size.0),
+                self.len(self.size.1),
+                clip
+            )
+            .unwrap();
+            if self.options.float_safe {
+                writeln!(open, "{}", self.float_safe_size(self.size)).unwrap();
+            }
+        }
+        // A document that hyphenates or justifies its paragraphs would break up long
+        // labels otherwise
+        if !self.options.inherit_paragraph {
+            open.push_str("  #set text(hyphenate: false)\n  #set par(justify: false)\n");
+        }
+        open
+    }
+
+    /// In float safe mode, the `place` function of the canvas, moving the content
+    /// within a block of no height instead of placing it; the content is aligned in
+    /// a box of `size`, the size of the container, which nested containers change
+    /// with [`Self::float_safe_size`]
+    fn float_safe_place(&self, (width, height): (u32, u32)) -> String {
+        format!(
+            concat!(
+                "  #let place(size: ({}, {}), ..args) = {{\n",
+                "    let (alignment, body) = if args.pos().len() > 1 {{ args.pos() }} else {{ (top + left, args.pos().first()) }}\n",
+                "    let (dx, dy) = (args.named().at(\"dx\", default: 0pt), args.named().at(\"dy\", default: 0pt))\n",
+                "    block(height: 0pt, above: 0pt, below: 0pt, box(width: size.at(0), height: size.at(1), align(alignment, move(dx: dx, dy: dy, body))))\n",
+                "  }}\n"
+            ),
+            self.len(width),
+            self.len(height)
+        )
+    }
+
+    /// The rebinding of `place` at the start of a container of `size` within the
+    /// canvas, in float safe mode
+    fn float_safe_size(&self, (width, height): (u32, u32)) -> String {
+        format!(
+            "  #let place = place.with(size: ({}, {}))",
+            self.len(width),
+            self.len(height)
+        )
+    }
+
+    /// In float safe mode, rebind `place` in the container of `size` just begun
+    fn float_safe_container(&mut self, size: (u32, u32)) {
+        if self.options.float_safe {
+            let cmd = self.float_safe_size(size);
+            self.write_command(&cmd);
+        }
+    }
+
+    /// Complete the chart, returning the markup written before and after the drawing
+    /// commands
+    fn finalize(&mut self) -> Result<(String, String), Error> {
+        self.check_cancelled()?;
+        #[cfg(feature = "fontdb")]
+        self.verify_fonts()?;
+        // The commands are rewritten, so no later bar continues the ones before
+        self.bars = None;
+        self.end_groups();
+        #[cfg(feature = "image")]
+        self.resolve_dense_groups()?;
+        // The commands are regrouped by layer, so their lines are not known
+        let calls = if self.options.layers {
+            Vec::new()
+        } else {
+            self.resolve_calls()
+        };
+        self.compact_commands();
+        // The stroke defaults come before the bindings written while drawing, such as
+        // the shapes of bulk commands, which may use them
+        if let Some(stroke) = &self.options.stroke {
+            let binding = format!(
+                "#let {} = {}\n",
+                self.binding_name(STROKE_BINDING),
+                stroke.dict()
+            );
+            self.bindings.insert_str(0, &binding);
+        }
+        self.resolve_anchors();
+        self.resolve_coord_map();
+        self.resolve_markers();
+        let mut text_style = self.resolve_text_styles();
+        self.resolve_layers(&mut text_style);
+        self.resolve_namespace();
+        let (head, tail) = match &self.options.template {
+            Some(template) => template.split()?,
+            None => Default::default(),
+        };
+        // The preamble and bindings come first, at the top level of the chart
+        let page = self.options.page.as_ref().map(Page::set_rule);
+        // A figure contains the whole chart, as content. A label not given is
+        // derived from the namespace or the chart itself, never from the order the
+        // charts are drawn in.
+        if let Some(caption) = self.options.figure_caption.as_ref() {
+            if self.label.is_none() {
+                self.label = Some(
+                    match (&self.options.figure_label, &self.options.namespace) {
+                        (Some(label), _) => label.clone(),
+                        (None, Some(Namespace::Prefix(prefix))) => format!("fig:{}", prefix),
+                        (None, _) => {
+                            let hash = stable_hash(&[caption, &self.bindings, &self.buf]);
+                            format!("fig:chart-{:08x}", hash as u32)
+                        }
+                    },
+                );
+            }
+        }
+        let (figure_open, figure_close) = match (&self.options.figure_caption, &self.label) {
+            (Some(caption), Some(label)) => (
+                "#figure([\n".to_string(),
+                format!(
+                    "], caption: [{}]) <{}>{}",
+                    self.escape_label(caption),
+                    label,
+                    if self.options.inline { "" } else { "\n" }
+                ),
+            ),
+            _ => Default::default(),
+        };
+        // The text settings hoisted from the labels apply to the commands only, not
+        // to the markup of the overlays and underlays
+        let (commands_open, commands_close) = if text_style.is_empty() {
+            (String::new(), "")
+        } else {
+            (format!("  #[\n{}", text_style), "  ]\n")
+        };
+        let open = format!(
+            "{}{}{}{}{}{}{}",
+            page.unwrap_or_default(),
+            head,
+            figure_open,
+            self.options.preamble,
+            self.bindings,
+            self.open_canvas(),
+            commands_open
+        );
+        let close = commands_close.to_string() + &self.close_canvas() + &figure_close + &tail;
+        self.check_budget(open.len() + self.spilled_bytes() + self.buf.len() + close.len())?;
+        let offset = open.matches('\n').count();
+        self.source_map = calls
+            .into_iter()
+            .map(|(lines, call)| (lines.start + offset..lines.end + offset, call))
+            .collect();
+        Ok((open, close))
+    }
+
+    /// The fill of text in `color`, or `None` for black text when the text color is
+    /// inherited from the document
+    fn text_color(&self, color: BackendColor) -> Option<String> {
+        if self.options.inherit_text_color && color.rgb == (0, 0, 0) && color.alpha >= 1.0 {
+            None
+        } else {
+            Some(self.typst_color(color))
+        }
+    }
+
+    /// The `fill` argument of text in `color`, starting with `, `, see
+    /// [`Self::text_color`]
+    #[cfg(feature = "plotters")]
+    pub(crate) fn text_fill(&self, color: BackendColor) -> String {
+        self.text_color(color)
+            .map(|color| format!(", fill: {}", color))
+            .unwrap_or_default()
+    }
+
+    /// Draw `text` at `pos`, rotated clockwise around it by `angle` degrees and
+    /// decorated with `decoration`
+    pub(crate) fn write_text<S: BackendTextStyle>(
+        &mut self,
+        text: &str,
+        style: &S,
+        pos: BackendCoord,
+        angle: f64,
+        decoration: Decoration,
+    ) {
+        let color = style.color();
+        if color.alpha == 0.0 {
+            return;
+        }
+
+        let (x0, y0) = pos;
+        let font_size = style.size() / 1.24; // Similar adjustment as SVG backend
+        let mut escaped_text = self.escape_label(text);
+        if decoration.strike {
+            escaped_text = format!("#strike[{}]", escaped_text);
+        }
+        if decoration.underline {
+            escaped_text = format!("#underline[{}]", escaped_text);
+        }
+        if self.options.debug_overlay {
+            // The text run itself is outlined, as only Typst knows its extent
+            escaped_text = format!(
+                "#box(fill: {}, stroke: {} + {})[{}]",
+                DEBUG_FILL,
+                self.len(0.5),
+                DEBUG_STROKE,
+                escaped_text
+            );
+        }
+
+        let family_str = style.family();
+        let font_family = typst_font_family(family_str.as_str());
+        self.note_font(font_family);
+
+        // The box of the text is the bounding box of its glyphs, like the layout box
+        // the bitmap backend anchors
+        let (top_edge, bottom_edge) = ("\"bounds\"", "\"bounds\"");
+
+        // Handle font style
+        let font_weight = match style.style() {
+            FontStyle::Bold => "\"bold\"",
+            _ => "\"regular\"",
+        };
+
+        let font_style_attr = match style.style() {
+            FontStyle::Italic | FontStyle::Oblique => "\"italic\"",
+            _ => "\"normal\"",
+        };
+
+        // The box of the text is aligned to the canvas such that its anchor lands on
+        // the placed point, e.g. right aligned text is placed from the right edge
+        let (width, height) = self.size;
+        let (h_align, dx) = match style.anchor().h_pos {
+            HPos::Left => ("left", f64::from(x0)),
+            HPos::Center => ("center", f64::from(x0) - f64::from(width) / 2.0),
+            HPos::Right => ("right", f64::from(x0) - f64::from(width)),
+        };
+        let (v_align, dy) = match style.anchor().v_pos {
+            VPos::Top => ("top", f64::from(y0)),
+            VPos::Center => ("horizon", f64::from(y0) - f64::from(height) / 2.0),
+            VPos::Bottom => ("bottom", f64::from(y0) - f64::from(height)),
+        };
+        let alignment = format!("{} + {}", v_align, h_align);
+        self.note_element("text");
+        self.include_text(text, font_size, style.anchor(), pos, angle);
+
+        // The same alignment within the box is the anchor, the box turns around it
+        let (rotation_attr, rotation_close) = if angle % 360.0 == 0.0 {
+            (String::new(), "")
+        } else {
+            (format!("rotate({}deg, origin: {}, ", angle, alignment), ")")
+        };
+
+        let mut args = vec![("size", self.len(font_size).to_string())];
+        if let Some(fill) = self.text_color(color) {
+            args.push(("fill", fill));
+        }
+        args.extend([
+            ("weight", font_weight.to_string()),
+            ("style", font_style_attr.to_string()),
+            ("font", format!("\"{}\"", font_family)),
+            ("top-edge", top_edge.to_string()),
+            ("bottom-edge", bottom_edge.to_string()),
+        ]);
+        let text_style = self.text_style_mark(args);
+
+        let cmd = format!(
+            "  #place({}, dx: {}, dy: {}, {}box[{}{}]{})",
+            alignment,
+            self.len(dx),
+            self.len(dy),
             rotation_attr,
-            font_size,
-            text_color,
-            font_weight,
-            font_style_attr,
-            font_family,
-            top_edge,
-            bottom_edge,
-            aligned_text,
+            text_style,
+            escaped_text,
             rotation_close
         );
-        self.write_command(&cmd);
-        Ok(())
+        self.write_command(&cmd);
+        self.write_debug_anchor(pos);
+    }
+
+    /// In command log mode, log a label of `text` anchored at `pos` and turned by
+    /// `angle` degrees
+    pub(crate) fn log_text<S: BackendTextStyle>(
+        &mut self,
+        text: &str,
+        style: &S,
+        pos: BackendCoord,
+        angle: f64,
+    ) -> Result<(), DrawingErrorKind<Error>> {
+        if style.color().alpha == 0.0 {
+            return Ok(());
+        }
+        self.log_command(|| {
+            JsonObject::new("text")
+                .string("text", text)
+                .point("at", pos)
+                .color("color", style.color())
+                .string("font", style.family().as_str())
+                .number("size", style.size())
+                .number("angle", angle)
+        })
+    }
+
+    /// Grow the bounding box of the drawing to cover a label of `text` anchored at
+    /// `pos` and turned by `angle` degrees around it
+    pub(crate) fn include_text(
+        &mut self,
+        text: &str,
+        font_size: f64,
+        anchor: Pos,
+        pos: BackendCoord,
+        angle: f64,
+    ) {
+        // Only Typst knows the layout of the text, estimate it from the font size
+        let width = text.chars().count() as f64 * font_size * 0.6;
+        let height = font_size;
+        let left = match anchor.h_pos {
+            HPos::Left => 0.0,
+            HPos::Center => -width / 2.0,
+            HPos::Right => -width,
+        };
+        let top = match anchor.v_pos {
+            VPos::Top => 0.0,
+            VPos::Center => -height / 2.0,
+            VPos::Bottom => -height,
+        };
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let corners = [
+            (left, top),
+            (left + width, top),
+            (left, top + height),
+            (left + width, top + height),
+        ]
+        .map(|(x, y)| {
+            (
+                pos.0 + (x * cos - y * sin).round() as i32,
+                pos.1 + (x * sin + y * cos).round() as i32,
+            )
+        });
+        self.include_points(&corners, 1);
+        let bounds = corners
+            .iter()
+            .fold((pos.0, pos.1, pos.0, pos.1), |(l, t, r, b), &(x, y)| {
+                (l.min(x), t.min(y), r.max(x), b.max(y))
+            });
+        self.note_label(text, bounds);
+    }
+
+    /// Write the document to the file at `path`, returning its length; in append
+    /// mode, the file is first cut back to where the chart started, replacing the
+    /// output of a former flush
+    fn write_file(&mut self, path: &Path, open: &str, close: &str) -> Result<usize, Error> {
+        let (outfile, separator) = if self.options.append {
+            let outfile = OpenOptions::new().create(true).append(true).open(path)?;
+            let start = match self.append_start {
+                Some(start) => start,
+                None => *self.append_start.insert(outfile.metadata()?.len()),
+            };
+            outfile.set_len(start)?;
+            (outfile, append_separator(path)?)
+        } else {
+            (File::create(path)?, "")
+        };
+        let commands = if self.options.split_series {
+            self.write_series_files(path)?
+        } else {
+            self.buf.clone()
+        };
+        let mut outfile = BufWriter::new(outfile);
+        let mut written = 0;
+        let mut write = |part: &str| {
+            written += part.len();
+            outfile.write_all(part.as_bytes())
+        };
+        for part in [separator, open] {
+            write(part)?;
+        }
+        if let Some(spill) = &self.spill {
+            spill.read(&mut write)?;
+        }
+        for part in [&commands, close] {
+            write(part)?;
+        }
+        outfile.flush()?;
+        Ok(written)
+    }
+
+    /// Write the commands of each series to `<stem>-series-<n>.typ` next to `path`,
+    /// returning the commands of the main file, which include the series files
+    fn write_series_files(&self, path: &Path) -> Result<String, Error> {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let mut main = String::new();
+        let mut series = String::new();
+        // The series numbers alternate with the commands of the series
+        let mut parts = self.buf.split(SERIES_MARK);
+        main.push_str(parts.next().unwrap_or_default());
+        while let (Some(number), Some(commands)) = (parts.next(), parts.next()) {
+            if number.is_empty() {
+                main.push_str(commands);
+                continue;
+            }
+            let name = format!("{}-series-{}.typ", stem, number);
+            // The preamble and bindings come first, as the commands of the series
+            // may use them and an included file does not see those of the main one
+            series.clear();
+            series.push_str(&self.options.preamble);
+            series.push_str(&self.bindings);
+            series.push_str(commands);
+            std::fs::write(path.with_file_name(&name), &series)?;
+            writeln!(main, "  #include \"{}\"", name).unwrap();
+        }
+        Ok(main)
+    }
+
+    /// Write the chart drawn so far to the file given to [`Self::new`] as a complete
+    /// document, so a long render can be followed with `typst watch` and an aborted
+    /// one leaves its progress behind; [`DrawingBackend::present`] still writes the
+    /// final document. Does nothing for the other targets.
+    ///
+    /// Each flush completes and rewrites the whole document drawn so far, so its
+    /// cost grows with the size of the chart: flushing after every `n` commands
+    /// costs time quadratic in the number of commands overall. Flush on a timer or
+    /// every few series rather than after each element.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        let Target::File(path) = self.target else {
+            return Ok(());
+        };
+        // Completing the document closes the open reveal group and clip regions and
+        // resolves the namespace, which must stay open for the commands still to come
+        let state = (
+            self.buf.clone(),
+            self.bindings.clone(),
+            self.step.clone(),
+            self.clips.clone(),
+            self.options.namespace.clone(),
+        );
+        let result = self
+            .finalize()
+            .and_then(|(open, close)| self.write_file(path, &open, &close))
+            .map(|_| ());
+        (
+            self.buf,
+            self.bindings,
+            self.step,
+            self.clips,
+            self.options.namespace,
+        ) = state;
+        result
+    }
+
+    /// Complete the chart and return the document instead of writing it to the
+    /// target, e.g. to send it in a response; the file of a backend created with
+    /// [`Self::new`] is left untouched
+    pub fn finish(self) -> Result<String, Error> {
+        self.finish_figure().map(|(document, _)| document)
+    }
+
+    /// Present the chart, see [`DrawingBackend::present`], and describe what was
+    /// written, to log with each generated chart
+    ///
+    /// With the `fontdb` feature, the font families missing from the installed
+    /// fonts are warned about, see [`Self::missing_fonts`].
+    pub fn present_report(&mut self) -> Result<WriteReport, Error> {
+        self.present().map_err(|error| match error {
+            DrawingErrorKind::DrawingError(error) => error,
+            DrawingErrorKind::FontError(error) => Error::other(error),
+        })?;
+        let mut warnings = Vec::new();
+        let (width, height) = self.size;
+        match self.bounds {
+            None => warnings.push("nothing was drawn".to_string()),
+            Some((left, top, right, bottom))
+                if left < 0 || top < 0 || right > width as i32 || bottom > height as i32 =>
+            {
+                warnings.push(format!(
+                    "content drawn from ({}, {}) to ({}, {}) is cut off by the canvas of {}x{}",
+                    left, top, right, bottom, width, height
+                ));
+            }
+            _ => {}
+        }
+        if let Some(palette) = self.options.palette {
+            let (colors, available) = (self.palette_colors.borrow().len(), palette.colors().len());
+            if colors > available {
+                warnings.push(format!(
+                    "{} colors share the {} colors of the colorblind palette",
+                    colors, available
+                ));
+            }
+        }
+        #[cfg(feature = "fontdb")]
+        for family in self.missing_fonts() {
+            warnings.push(format!("font not found: {}", family));
+        }
+        Ok(WriteReport {
+            path: match self.target {
+                Target::File(path) => Some(path.to_path_buf()),
+                _ => None,
+            },
+            bytes: self.written,
+            primitives: self.primitives,
+            elements: self.elements.clone(),
+            warnings,
+        })
+    }
+
+    /// Like [`Self::finish`], returning the UTF-8 bytes of the document, ready for an
+    /// HTTP response body or an archive entry
+    pub fn present_to_vec(self) -> Result<Vec<u8>, Error> {
+        self.finish().map(String::into_bytes)
+    }
+
+    /// The markup closing the canvas, written after all drawing commands
+    fn close_canvas(&self) -> String {
+        let (_, _, width, height) = self.crop_area();
+        // Close the window into the full canvas of a cropped one
+        let mut close = if (width, height) != self.size {
+            "  ])\n".to_string()
+        } else {
+            String::new()
+        };
+        // The overlays are drawn over everything, in the cropped canvas
+        let info = self.canvas_info();
+        for overlay in &self.options.overlays {
+            close.push_str(&overlay(&info));
+            if !close.ends_with('\n') {
+                close.push('\n');
+            }
+        }
+        close.push(']');
+        match self.options.scaling {
+            Some(Scaling::Factor(_)) => close.push_str("))"),
+            Some(Scaling::FitWidth) => close.push_str(")) })"),
+            None => {}
+        }
+        if !self.options.inline {
+            // Inline, a trailing newline would become a space in the surrounding paragraph
+            close.push('\n');
+        }
+        close
+    }
+
+    /// What the hooks writing onto the canvas know of the chart
+    fn canvas_info(&self) -> CanvasInfo {
+        let (left, top, width, height) = self.crop_area();
+        CanvasInfo {
+            size: (width, height),
+            coord_map: self.coord_map.map(|(x0, x_scale, y0, y_scale)| {
+                (x0 - f64::from(left), x_scale, y0 - f64::from(top), y_scale)
+            }),
+            em: self.options.em,
+            rounded: self.options.reproducible,
+            smart_punctuation: self.options.smart_punctuation,
+        }
+    }
+
+    fn with_target(target: Target<'a>, size: (u32, u32)) -> Self {
+        Self {
+            target,
+            buf: String::default(),
+            size,
+            saved: false,
+            background: None,
+            step: None,
+            next_step: 2,
+            bindings: String::default(),
+            series_sections: 0,
+            series: None,
+            anchors: Vec::new(),
+            coord_map: None,
+            clips: Vec::new(),
+            bounds: None,
+            palette_colors: RefCell::default(),
+            append_start: None,
+            text_styles: Marked::default(),
+            markers: Marked::default(),
+            bulk_shapes: HashMap::new(),
+            bars: None,
+            #[cfg(feature = "image")]
+            dense_groups: Vec::new(),
+            #[cfg(feature = "image")]
+            dense_group: None,
+            spill: None,
+            primitives: 0,
+            elements: BTreeMap::new(),
+            written: 0,
+            manifest: Manifest::default(),
+            #[cfg(feature = "fontdb")]
+            font_families: Default::default(),
+            command_log: None,
+            progress: None,
+            calls: Vec::new(),
+            open_call: None,
+            source_map: Vec::new(),
+            label: None,
+            options: Options::default(),
+        }
+    }
+
+    /// The bounding box `(left, top, right, bottom)` of everything drawn so far, in
+    /// backend pixels, or `None` before anything is drawn
+    ///
+    /// Strokes are included with their width and labels with an extent estimated
+    /// from their font size; a fill of the whole canvas is the background and not
+    /// counted. The box is not clamped to the canvas, so a box reaching past
+    /// `(0, 0)` or [`DrawingBackend::get_size`] tells that something was drawn
+    /// outside of it. This is the box [`Self::auto_crop`] shrinks the canvas to.
+    pub fn content_bbox(&self) -> Option<(i32, i32, i32, i32)> {
+        self.bounds
+    }
+
+    /// The markup including the chart into a document, ready to paste or insert
+    /// into it, or `None` unless the chart is written to a file at a relative path,
+    /// see [`Self::new`]
+    ///
+    /// The chart is included with `#include`, after importing the bindings it
+    /// exports, such as `chart_anchors` of [`Self::named_anchors`]. In
+    /// [`Self::split_layers`] mode, the layers are only imported, for the document to
+    /// stack them itself. The path is the one given to [`Self::new`], see
+    /// [`Self::include_snippet_for`] for a path relative to the including document.
+    /// An absolute path has no snippet, as Typst reads a path starting with `/`
+    /// from the root of the project rather than of the file system.
+    /// With a hashed [`Self::namespace`], the names are known once the chart is
+    /// presented.
+    pub fn include_snippet(&self) -> Option<String> {
+        let Target::File(path) = self.target else {
+            return None;
+        };
+        if path.has_root() {
+            return None;
+        }
+        Some(self.snippet(path))
+    }
+
+    /// Like [`Self::include_snippet`], with the path of the chart relative to the
+    /// directory of the including `document`, e.g. `figures/chart.typ` for the chart
+    /// `out/figures/chart.typ` and the document `out/main.typ`; `None` as well when
+    /// one path is absolute and the other is not
+    pub fn include_snippet_for<P: AsRef<Path>>(&self, document: P) -> Option<String> {
+        let Target::File(path) = self.target else {
+            return None;
+        };
+        let dir = document.as_ref().parent().unwrap_or(Path::new(""));
+        Some(self.snippet(&relative_path(path, dir)?))
+    }
+
+    fn snippet(&self, path: &Path) -> String {
+        let path = path
+            .to_string_lossy()
+            .replace('\\', "/")
+            .replace('"', "\\\"");
+        let mut names = Vec::new();
+        if self.options.layers {
+            names.extend(LAYER_BINDINGS);
+        }
+        if self.options.anchors {
+            names.push(ANCHORS_BINDING);
+        }
+        if self.coord_map.is_some() {
+            names.push(COORD_BINDING);
+        }
+        let mut snippet = String::new();
+        if !names.is_empty() {
+            let names: Vec<_> = names.iter().map(|name| self.binding_name(name)).collect();
+            writeln!(snippet, "#import \"{}\": {}", path, names.join(", ")).unwrap();
+        }
+        if !self.options.layers {
+            writeln!(snippet, "#include \"{}\"", path).unwrap();
+        }
+        snippet
+    }
+
+    /// The JSON manifest of the chart drawn so far, or `None` unless enabled with
+    /// [`Self::manifest`]
+    pub fn manifest_json(&self) -> Option<String> {
+        self.options
+            .manifest
+            .then(|| self.manifest.to_json(self.size, self.bounds))
+    }
+
+    /// In source map mode, the 1-based lines of the document written by each drawing
+    /// call, comment included, with the call, as of the last time the document was
+    /// presented or flushed; a line of a Typst error falls into one of the ranges
+    ///
+    /// Lines count from the start of the chart, which follows the earlier content
+    /// of the file in append mode.
+    pub fn source_map(&self) -> &[(Range<usize>, String)] {
+        &self.source_map
+    }
+
+    /// The drawing call that wrote `line` of the document, see [`Self::source_map`]
+    pub fn source_call(&self, line: usize) -> Option<&str> {
+        self.source_map
+            .iter()
+            .find(|(lines, _)| lines.contains(&line))
+            .map(|(_, call)| call.as_str())
+    }
+
+    /// Create a new Typst drawing backend
+    pub fn new<T: AsRef<Path> + ?Sized>(path: &'a T, size: (u32, u32)) -> Self {
+        Self::with_target(Target::File(path.as_ref()), size)
+    }
+
+    /// Create a new Typst drawing backend and store the document into a String buffer
+    pub fn with_string(buf: &'a mut String, size: (u32, u32)) -> Self {
+        Self::with_target(Target::Buffer(buf), size)
+    }
+
+    /// Create a new Typst drawing backend writing the document to `writer`, such as
+    /// a socket or a pipe, when the chart is presented
+    pub fn with_writer<W: Write + 'a>(writer: W, size: (u32, u32)) -> Self {
+        Self::with_target(Target::Writer(Box::new(writer)), size)
+    }
+
+    /// Create a new Typst drawing backend writing the document to the standard
+    /// output, e.g. to pipe it into `typst compile - chart.pdf`
+    pub fn stdout(size: (u32, u32)) -> Self {
+        Self::with_writer(std::io::stdout(), size)
+    }
+
+    /// Create a new Typst drawing backend owning its output, so it can be returned
+    /// from functions or stored without borrowing a buffer; read the document
+    /// through [`Self::output`]
+    pub fn new_buffered(size: (u32, u32)) -> TypstBackend<'static> {
+        TypstBackend::with_target(Target::Shared(TypstOutput::default()), size)
+    }
+
+    /// A handle to the document of a backend created with [`Self::new_buffered`],
+    /// which stays valid after the backend is moved into a drawing area
+    pub fn output(&self) -> Option<TypstOutput> {
+        match &self.target {
+            Target::Shared(output) => Some(output.clone()),
+            _ => None,
+        }
+    }
+
+    /// Start a new, empty canvas on the same target with the same settings, keeping
+    /// the allocated buffers, so a batch job drawing thousands of charts sets up a
+    /// single backend
+    ///
+    /// The chart drawn so far is discarded unless it was presented. A file target is
+    /// overwritten by the next chart, or appended to in [`Self::append`] mode, while
+    /// a string or [`TypstOutput`] receives the next chart after the earlier ones,
+    /// unless taken out in between.
+    pub fn reset(&mut self) {
+        self.buf.clear();
+        self.saved = false;
+        self.background = None;
+        self.step = None;
+        self.next_step = 2;
+        self.bindings.clear();
+        self.series_sections = 0;
+        self.series = None;
+        self.anchors.clear();
+        self.coord_map = None;
+        self.clips.clear();
+        self.bounds = None;
+        self.palette_colors.get_mut().clear();
+        self.append_start = None;
+        self.text_styles = Marked::default();
+        self.markers = Marked::default();
+        self.bulk_shapes.clear();
+        self.bars = None;
+        #[cfg(feature = "image")]
+        {
+            self.dense_groups.clear();
+            self.dense_group = None;
+        }
+        self.spill = None;
+        self.primitives = 0;
+        self.elements.clear();
+        self.written = 0;
+        self.manifest = Manifest::default();
+        #[cfg(feature = "fontdb")]
+        self.font_families.clear();
+        self.calls.clear();
+        self.open_call = None;
+        self.source_map.clear();
+        self.label = None;
+    }
+
+    /// Like [`Self::reset`], writing the next chart to the file at `path` instead
+    pub fn reset_to<T: AsRef<Path> + ?Sized>(&mut self, path: &'a T) {
+        self.reset();
+        self.target = Target::File(path.as_ref());
+    }
+
+    /// Like [`Self::reset`], with a canvas of `size` for the next chart
+    pub fn reset_size(&mut self, size: (u32, u32)) {
+        self.reset();
+        self.size = size;
+    }
+
+    /// Draw a circle of `radius` around each of `centers` in a single command
+    ///
+    /// The circle is bound once and placed in a loop over the packed coordinates,
+    /// e.g. `#for (x, y) in ((8, 18), (28, 38)) { place(dx: x * 1pt, dy: y * 1pt,
+    /// bulk-1) }`, instead of writing a styled shape per point as
+    /// [`DrawingBackend::draw_circle`] does, for scatter plots of many points.
+    pub fn draw_circles<S: BackendStyle, I: IntoIterator<Item = BackendCoord>>(
+        &mut self,
+        centers: I,
+        radius: u32,
+        style: &S,
+        fill: bool,
+    ) -> Result<(), DrawingErrorKind<Error>> {
+        let centers: Vec<_> = centers.into_iter().collect();
+        if style.color().alpha == 0.0 || centers.is_empty() {
+            return Ok(());
+        }
+
+        let attrs = self.shape_attrs(style, fill, true);
+        let shape = format!("circle(radius: {}, {})", self.len(radius), attrs);
+        let name = self.bulk_shape(shape);
+        let call = self.begin_call(|| format!("draw_circles({} circles)", centers.len()));
+        let r = radius as i32;
+        let offsets: Vec<_> = centers.iter().map(|&(x, y)| [x - r, y - r]).collect();
+        #[cfg(feature = "image")]
+        let start = self.buf.len();
+        self.write_bulk(&["x", "y"], &offsets, &name);
+        let margin = self.shape_margin(style, fill, true);
+        for &center in &centers {
+            #[cfg(feature = "image")]
+            self.rasterize_closed(start, style, fill, true, |stroke| {
+                raster::Shape::Circle(center, radius, stroke)
+            });
+            self.note_element("circle");
+            self.log_command(|| {
+                JsonObject::new("circle")
+                    .point("center", center)
+                    .number("radius", radius)
+                    .color("color", style.color())
+                    .number("stroke_width", style.stroke_width())
+                    .flag("filled", fill)
+            })?;
+            let (x, y) = center;
+            self.include_bounds((x - r, y - r), (x + r, y + r), margin);
+            self.note_series_point(center);
+        }
+        self.end_call(call)?;
+        Ok(())
+    }
+
+    /// Draw each of `rects`, given by their upper left and bottom right corners, in
+    /// a single command, like [`Self::draw_circles`], for bar charts and heat maps
+    /// of many cells
+    ///
+    /// Unlike [`DrawingBackend::draw_rect`], a rect covering the canvas does not
+    /// become its background.
+    pub fn draw_rects<S: BackendStyle, I: IntoIterator<Item = (BackendCoord, BackendCoord)>>(
+        &mut self,
+        rects: I,
+        style: &S,
+        fill: bool,
+    ) -> Result<(), DrawingErrorKind<Error>> {
+        let rects: Vec<_> = rects.into_iter().collect();
+        if style.color().alpha == 0.0 || rects.is_empty() {
+            return Ok(());
+        }
+
+        let attrs = self.shape_attrs(style, fill, true);
+        let unit = self.bulk_unit();
+        let shape = format!(
+            "(w, h) => rect(width: w * {u}, height: h * {u}, {})",
+            attrs,
+            u = unit
+        );
+        let name = self.bulk_shape(shape);
+        let call = self.begin_call(|| format!("draw_rects({} rects)", rects.len()));
+        let cells: Vec<_> = rects
+            .iter()
+            .map(|&(from, to)| [from.0, from.1, to.0 - from.0, to.1 - from.1])
+            .collect();
+        #[cfg(feature = "image")]
+        let start = self.buf.len();
+        self.write_bulk(&["x", "y", "w", "h"], &cells, &format!("{}(w, h)", name));
+        let margin = self.shape_margin(style, fill, true);
+        for &(upper_left, bottom_right) in &rects {
+            #[cfg(feature = "image")]
+            self.rasterize_closed(start, style, fill, true, |stroke| {
+                raster::Shape::Rect(upper_left, bottom_right, stroke)
+            });
+            self.note_element("rect");
+            self.log_command(|| {
+                JsonObject::new("rect")
+                    .point("from", upper_left)
+                    .point("to", bottom_right)
+                    .color("color", style.color())
+                    .number("stroke_width", style.stroke_width())
+                    .flag("filled", fill)
+            })?;
+            self.include_bounds(upper_left, bottom_right, margin);
+        }
+        self.end_call(call)?;
+        Ok(())
+    }
+
+    /// The length of a pixel, which the packed numbers of bulk drawing are multiplied
+    /// by; exact in em units, unlike [`Self::len`]
+    fn bulk_unit(&self) -> String {
+        match self.options.em {
+            Some(em) => format!("(1em / {})", em),
+            None => "1pt".to_string(),
+        }
+    }
+
+    /// The name `shape` is bound to for bulk drawing, binding it when first drawn
+    fn bulk_shape(&mut self, shape: String) -> String {
+        if let Some(name) = self.bulk_shapes.get(&shape) {
+            return name.clone();
+        }
+        let name = format!("bulk-{}", self.bulk_shapes.len() + 1);
+        self.write_binding(&name, &shape);
+        let name = self.binding_name(&name);
+        self.bulk_shapes.insert(shape, name.clone());
+        name
+    }
+
+    /// Write a loop placing `body` at each row of numbers in `rows`, destructured
+    /// into `fields` of which the first two are the offsets
+    fn write_bulk<const N: usize>(&mut self, fields: &[&str; N], rows: &[[i32; N]], body: &str) {
+        let unit = self.bulk_unit();
+        write!(self.buf, "  #for ({}) in (", fields.join(", ")).unwrap();
+        for (i, row) in rows.iter().enumerate() {
+            if i > 0 {
+                self.buf.push_str(", ");
+            }
+            self.buf.push('(');
+            for (j, value) in row.iter().enumerate() {
+                if j > 0 {
+                    self.buf.push_str(", ");
+                }
+                self.buf.push_str(itoa::Buffer::new().format(*value));
+            }
+            self.buf.push(')');
+        }
+        // A single row needs a trailing comma to be an array
+        if rows.len() == 1 {
+            self.buf.push(',');
+        }
+        self.write_command_args(format_args!(
+            ") {{ place(dx: {x} * {u}, dy: {y} * {u}, {}) }}",
+            body,
+            x = fields[0],
+            y = fields[1],
+            u = unit
+        ));
+    }
+
+    /// Outline every filled circle and rect with the color and stroke width of
+    /// `style`, so a marker gets its body and a contrasting border from a single
+    /// emitted shape. A rect covering the whole canvas is never outlined.
+    pub fn with_outline<S: BackendStyle>(mut self, style: &S) -> Self {
+        self.options.outline = Some((style.color(), style.stroke_width()));
+        self
+    }
+
+    /// Emit the chart as an unclipped inline box without a trailing newline, so a
+    /// small chart such as a sparkline can flow inside a paragraph or table cell
+    pub fn inline(mut self) -> Self {
+        self.options.inline = true;
+        self
+    }
+
+    /// Lay the commands out as blocks of no height stacked at the top of the canvas
+    /// and moved into position, instead of placing them with `place`, so the chart
+    /// works anywhere Typst content is allowed, such as in a floating figure or a
+    /// table cell where placed content is restricted
+    ///
+    /// The canvas shadows `place` with a function of its own, so the commands stay
+    /// the same, as do the overlays and underlays using `place`.
+    pub fn float_safe(mut self) -> Self {
+        self.options.float_safe = true;
+        self
+    }
+
+    /// Do not clip the canvas, so labels and rotated axis titles reaching over its
+    /// edges are shown in full, over the surrounding content
+    pub fn overflow_visible(mut self) -> Self {
+        self.options.overflow_visible = true;
+        self
+    }
+
+    /// Add `padding` pixels of room around the drawing, inside the canvas, so strokes
+    /// on the edges of a tightly fit chart are not cut off by its clipping
+    ///
+    /// The canvas grows by twice the padding in each direction.
+    pub fn padding(mut self, padding: u32) -> Self {
+        self.options.padding = padding;
+        self
+    }
+
+    /// Shrink the canvas to the bounding box of everything drawn, for a tight figure
+    /// without tuning its size by hand
+    ///
+    /// The extent of labels is estimated from their font size, as only Typst knows
+    /// their exact layout, so a label may reach beyond the cropped canvas: it is not
+    /// clipped there, only at the edges of the full canvas. Combined with
+    /// [`Self::padding`], the room is added around the cropped drawing.
+    pub fn auto_crop(mut self) -> Self {
+        self.options.auto_crop = true;
+        self
+    }
+
+    /// Describe the chart in a JSON manifest: its size, the elements drawn by kind,
+    /// the series sections with their elements and bounding boxes, and the labels
+    /// with their estimated boxes, for tooling and tests that inspect charts
+    /// without parsing Typst
+    ///
+    /// A backend created with [`Self::new`] writes the manifest next to the file
+    /// when presented, e.g. to `chart.json` for `chart.typ`; see also
+    /// [`Self::manifest_json`].
+    pub fn manifest(mut self) -> Self {
+        self.options.manifest = true;
+        self
+    }
+
+    /// Precede the commands of every drawing call with a `// source: ..` comment
+    /// naming the call, such as `draw_rect((10, 10), (90, 40), fill: true)`, so a
+    /// line reported by a Typst error can be traced back to the element that
+    /// produced it; see also [`Self::source_map`]
+    pub fn source_map_comments(mut self) -> Self {
+        self.options.source_map = true;
+        self
+    }
+
+    /// Bind [`TypstLegend`](element::TypstLegend)s to `chart_legend`, e.g.
+    /// `#let chart_legend = [#box(..)]`, instead of drawing them inside the canvas,
+    /// so the including document can place the legend beside or below the chart
+    /// or in a margin note
+    pub fn detach_legend(mut self) -> Self {
+        self.options.detached_legend = true;
+        self
+    }
+
+    /// Write the commands of each series, marked with
+    /// [`TypstSection::series`](element::TypstSection::series), to its own file
+    /// `<stem>-series-<n>.typ` next to the file given to [`Self::new`], which
+    /// includes them, so a document can include some series only or a series can be
+    /// regenerated alone
+    ///
+    /// A series ends at the next section mark. Each series file starts with the
+    /// [`Self::preamble`] and the bindings of the chart, such as repeated marker
+    /// shapes, so it compiles on its own. Only charts written to
+    /// a file are split, and not in [`Self::split_layers`] mode.
+    pub fn split_series(mut self) -> Self {
+        self.options.split_series = true;
+        self
+    }
+
+    /// Bind named points of the chart to `chart_anchors`, a dictionary of
+    /// `(dx, dy)` offsets from the upper left corner of the canvas, so drawings
+    /// around the chart, such as a CeTZ canvas, can attach arrows and annotations
+    /// to them, e.g. `chart_anchors.at("legend")`
+    ///
+    /// The points are the ones marked with [`TypstAnchor`](element::TypstAnchor),
+    /// such as the origin or the ends of the axes, the upper left corner of the
+    /// last [`TypstLegend`](element::TypstLegend) as `legend`, and the first and
+    /// last point of each series marked with
+    /// [`TypstSection::series`](element::TypstSection::series), as
+    /// `series-1-first` and `series-1-last`.
+    pub fn named_anchors(mut self) -> Self {
+        self.options.anchors = true;
+        self
+    }
+
+    /// Bind the axes, mesh and labels to `chart_frame` and the data series to
+    /// `chart_data`, the layers the canvas shows on top of each other, so the
+    /// including document can stack them itself, e.g. with annotations between the
+    /// grid and the data: `#box(width: 300pt, height: 200pt)[#chart_frame #notes
+    /// #chart_data]`
+    ///
+    /// Everything goes to the frame layer until a
+    /// [`TypstLayer::data`](element::TypstLayer::data) mark. The
+    /// [`Self::source_map`] is not available in this mode.
+    pub fn split_layers(mut self) -> Self {
+        self.options.layers = true;
+        self
+    }
+
+    /// Wrap the chart in a `#figure` captioned `caption`, escaped like the labels
+    ///
+    /// Give the caption here instead of to plotters' `ChartBuilder::caption`, which
+    /// draws it inside the canvas.
+    pub fn figure_caption(mut self, caption: &str) -> Self {
+        self.options.figure_caption = Some(caption.to_string());
+        self
+    }
+
+    /// The caption of the figure of [`Self::figure_caption`] mode, if any
+    pub fn caption(&self) -> Option<&str> {
+        self.options.figure_caption.as_deref()
+    }
+
+    /// Label the figure of [`Self::figure_caption`] mode `label`, such as
+    /// `"fig:sales"`
+    ///
+    /// A label holds letters, digits, and `_`, `-`, `:` and `.`; any other character
+    /// is replaced by `_`.
+    ///
+    /// Without it, the label is `fig:<prefix>` with the prefix of the
+    /// [`Self::namespace`], or else `fig:chart-<hash>` with a hash of the caption and
+    /// the chart, so it is the same on every run. Charts drawn alike with the same
+    /// caption then share their label.
+    pub fn figure_label(mut self, label: &str) -> Self {
+        self.options.figure_label = Some(typst_label(label));
+        self
+    }
+
+    /// The label of the figure the chart is wrapped in, such as `fig:sales`, to
+    /// reference it with `@fig:sales`; known once the chart is presented
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Like [`Self::finish`], also returning the label of the figure the chart is
+    /// wrapped in, if any
+    pub fn finish_figure(mut self) -> Result<(String, Option<String>), Error> {
+        let document = self.finish_document()?;
+        Ok((document, self.label.take()))
+    }
+
+    /// Complete the chart into a document of its own, leaving the drawing commands in
+    /// `buf` for their allocation to be reused
+    pub(crate) fn finish_document(&mut self) -> Result<String, Error> {
+        let (open, close) = self.finalize()?;
+        // Nothing is left to write when the backend is dropped
+        self.saved = true;
+        if self.options.split_series {
+            // No series file is written, the series stay inline; the series numbers
+            // alternate with the commands
+            self.buf = self.buf.split(SERIES_MARK).step_by(2).collect();
+        }
+        let mut document = open;
+        document.reserve(self.buf.len() + close.len());
+        push_spilled(&mut document, &self.spill)?;
+        document.push_str(&self.buf);
+        document.push_str(&close);
+        Ok(document)
+    }
+
+    /// Mirror every emitted command into `log` as a line of JSON (JSON Lines), with
+    /// the shape drawn, its coordinates and style, for linters, diff tools and
+    /// converters built on top of the backend
+    ///
+    /// Each line is an object such as
+    /// `{"shape": "rect", "from": [10, 10], "to": [90, 40], "color": [0, 0, 255, 1], "stroke_width": 1, "filled": true}`.
+    /// Coordinates are backend pixels, colors are `[red, green, blue, alpha]` as
+    /// drawn, before any palette or grayscale mapping. The log is flushed when the
+    /// chart is presented.
+    pub fn command_log<W: Write + 'a>(mut self, log: W) -> Self {
+        self.command_log = Some(Box::new(log));
+        self
+    }
+
+    /// Call `callback` with the numbers of primitives drawn and bytes written so far
+    /// every `every` primitives, e.g. to show a progress bar while drawing a chart
+    /// of millions of points
+    pub fn on_progress<F: FnMut(Progress) + 'a>(mut self, every: usize, callback: F) -> Self {
+        self.progress = Some((every.max(1), Box::new(callback)));
+        self
+    }
+
+    /// Guarantee the same output for the same drawing calls on every machine, for
+    /// reproducible builds
+    ///
+    /// Lengths and angles are rounded to four decimals, so the last digits of
+    /// floating point functions, which differ between platforms, do not show, and
+    /// [`Self::hashed_namespace`] uses a hash that does not change with the Rust
+    /// version. The backend never writes paths, timestamps or locale dependent
+    /// numbers; markup passed in, such as a page header showing
+    /// `datetime.today()`, is up to the caller.
+    pub fn reproducible(mut self) -> Self {
+        self.options.reproducible = true;
+        self
+    }
+
+    /// Leave out the attributes of the drawing commands that Typst defaults to the
+    /// same value, such as the `stroke: none` of a filled shape or a zero offset,
+    /// for a noticeably smaller file that compiles to the same chart
+    ///
+    /// Whole lengths are always written without a fraction, e.g. `12pt`.
+    pub fn compact(mut self) -> Self {
+        self.options.compact = true;
+        self
+    }
+
+    /// Merge filled rects drawn one after the other on a common bottom edge with the
+    /// same fill and without overlapping, such as the bars of a histogram, into a
+    /// single polygon tracing their outline, which Typst lays out much faster than
+    /// thousands of rects
+    ///
+    /// Rects are only merged without an outline, see [`Self::with_outline`], and
+    /// outside of [`Self::source_map_comments`] and [`Self::debug_overlay`] modes,
+    /// where each drawing call keeps a command of its own.
+    pub fn batch_histograms(mut self) -> Self {
+        self.options.batch_bars = true;
+        self
+    }
+
+    /// Reduce the points of a line series to at most four per column of backend
+    /// pixels: where the line enters and leaves the column, and its lowest and
+    /// highest point in between, so a signal of millions of samples is drawn as
+    /// it looks, with a few commands per column
+    ///
+    /// Only runs of consecutive points in the same column are reduced, so a path
+    /// going back and forth keeps its shape.
+    pub fn decimate_lines(mut self) -> Self {
+        self.options.decimate = true;
+        self
+    }
+
+    /// Fail with an error of kind [`FileTooLarge`](std::io::ErrorKind::FileTooLarge)
+    /// once the document grows beyond `bytes` bytes, instead of writing a document
+    /// too large for Typst to compile
+    ///
+    /// The drawing call taking the chart over its budget fails, or else presenting
+    /// it, as the markup completing the chart counts too.
+    pub fn size_budget(mut self, bytes: usize) -> Self {
+        self.options.max_bytes = Some(bytes);
+        self
+    }
+
+    /// Abort drawing the chart once `flag` is set, e.g. by the timeout of a web
+    /// request, with an error of kind [`Interrupted`](std::io::ErrorKind::Interrupted)
+    /// from the next drawing call or from presenting the chart, which then writes
+    /// nothing
+    pub fn cancel_on(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.options.cancel = Some(flag);
+        self
+    }
+
+    /// Like [`Self::size_budget`], failing once more than `count` primitives, such as
+    /// lines, shapes and labels, are drawn
+    pub fn primitive_budget(mut self, count: usize) -> Self {
+        self.options.max_primitives = Some(count);
+        self
+    }
+
+    /// Align the canvas row `y` with the baseline of the surrounding text, e.g. the
+    /// zero line of an inline chart, as found by `chart.backend_coord(..)`
+    pub fn baseline(mut self, y: i32) -> Self {
+        self.options.baseline = Some(y);
+        self
+    }
+
+    /// Scale the chart to fill the width of the including container while keeping
+    /// its aspect ratio, using a `layout` callback evaluated by Typst
+    pub fn responsive(mut self) -> Self {
+        self.options.scaling = Some(Scaling::FitWidth);
+        self
+    }
+
+    /// Display the chart scaled by `factor`, e.g. `0.5` to show a chart drawn at a
+    /// high logical resolution at half its size, without changing the drawing
+    ///
+    /// # Panics
+    /// If `factor` is not a finite number greater than zero.
+    pub fn scale(mut self, factor: f64) -> Self {
+        assert!(
+            factor.is_finite() && factor > 0.0,
+            "scale factor must be finite and positive, got {}",
+            factor
+        );
+        self.options.scaling = Some(Scaling::Factor(factor));
+        self
+    }
+
+    /// Emit all lengths as multiples of `1em` rather than `pt`, taking `1em` to be
+    /// `font_size` pixels, so the chart scales with the font size of the including
+    /// document; e.g. `11.0` renders at its drawn size in an 11pt document
+    ///
+    /// # Panics
+    /// If `font_size` is not a finite number greater than zero.
+    pub fn em_units(mut self, font_size: f64) -> Self {
+        assert!(
+            font_size.is_finite() && font_size > 0.0,
+            "em font size must be finite and positive, got {}",
+            font_size
+        );
+        self.options.em = Some(font_size);
+        self
+    }
+
+    /// Never emit a background: the fill covering the whole canvas before anything
+    /// else is drawn, as done by `root.fill(..)`, is dropped, so the chart can be
+    /// layered over slide backgrounds or colored pages; later full-canvas fills,
+    /// such as a translucent overlay, are kept
+    pub fn transparent_background(mut self) -> Self {
+        self.options.transparent = true;
+        self
+    }
+
+    /// Draw a frame with the color and stroke width of `style` around the canvas,
+    /// with corners rounded by `radius` pixels
+    pub fn frame<S: BackendStyle>(mut self, style: &S, radius: u32) -> Self {
+        self.options.frame = Some((style.color(), style.stroke_width(), radius));
+        self
+    }
+
+    /// Overlay every text element and shape drawn by the backend with a translucent
+    /// bounding box and a cross on its anchor point, to diagnose alignment and
+    /// clipping issues
+    pub fn debug_overlay(mut self) -> Self {
+        self.options.debug_overlay = true;
+        self
+    }
+
+    /// Emit `markup`, such as `#import "@preview/cetz:0.3.1"` or
+    /// `#set text(font: "Inter")`, before the chart, so the generated file is self
+    /// sufficient; successive calls append lines
+    pub fn preamble(mut self, markup: &str) -> Self {
+        self.options.preamble.push_str(markup);
+        if !markup.ends_with('\n') {
+            self.options.preamble.push('\n');
+        }
+        self
+    }
+
+    /// Render into `template`, replacing its `{{chart}}` placeholder with the chart,
+    /// so every chart gets the same wrapper, e.g. `#figure([{{chart}}], caption: ..)`.
+    /// The placeholder must stand where markup is expected.
+    pub fn template(mut self, template: &str) -> Self {
+        self.options.template = Some(Template::Markup(template.to_string()));
+        self
+    }
+
+    /// Like [`Self::template`], with the template read from the file at `path` when
+    /// the chart is presented
+    pub fn template_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.options.template = Some(Template::File(path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Emit a standalone document: the page is sized to the chart, so compiling the
+    /// file gives a PDF or image of just the chart
+    pub fn standalone(mut self) -> Self {
+        self.options.page.get_or_insert_with(Page::default);
+        self
+    }
+
+    /// Put `markup` in the page header of the standalone document, e.g.
+    /// `Sales report #h(1fr) #datetime.today().display()`; implies
+    /// [`Self::standalone`]
+    pub fn page_header(mut self, markup: &str) -> Self {
+        self.options.page.get_or_insert_with(Page::default).header = Some(markup.to_string());
+        self
+    }
+
+    /// Put `markup` in the page footer of the standalone document, e.g.
+    /// `#align(center, context counter(page).display())`; implies
+    /// [`Self::standalone`]
+    pub fn page_footer(mut self, markup: &str) -> Self {
+        self.options.page.get_or_insert_with(Page::default).footer = Some(markup.to_string());
+        self
+    }
+
+    /// Use `function` instead of `only` to guard the groups started by
+    /// [`TypstReveal`](crate::element::TypstReveal), e.g. `uncover` to keep the
+    /// space of hidden layers reserved
+    pub fn reveal_function(mut self, function: &str) -> Self {
+        self.options.reveal_function = Some(function.to_string());
+        self
+    }
+
+    /// Prefix the names of the `#let` bindings emitted by the chart, such as
+    /// [`TypstData`](crate::element::TypstData), with `prefix_`, so several charts
+    /// can be imported into one document without clashing
+    pub fn namespace(mut self, prefix: &str) -> Self {
+        self.options.namespace = Some(Namespace::Prefix(typst_identifier(prefix)));
+        self
+    }
+
+    /// Like [`Self::namespace`], with a prefix derived from a hash of the chart;
+    /// suited to bindings only the chart itself refers to
+    pub fn hashed_namespace(mut self) -> Self {
+        self.options.namespace = Some(Namespace::Hashed);
+        self
+    }
+
+    /// Give every stroke of the chart the line cap `cap`: `"butt"`, `"round"` or
+    /// `"square"`
+    ///
+    /// # Panics
+    /// If `cap` is none of these.
+    pub fn stroke_cap(mut self, cap: &str) -> Self {
+        assert!(
+            STROKE_CAPS.contains(&cap),
+            "stroke cap must be one of {}, got {:?}",
+            STROKE_CAPS.join(", "),
+            cap
+        );
+        self.options.stroke.get_or_insert_with(Default::default).cap = Some(cap.to_string());
+        self
+    }
+
+    /// Give every stroke of the chart the line join `join`: `"miter"`, `"round"` or
+    /// `"bevel"`
+    ///
+    /// # Panics
+    /// If `join` is none of these.
+    pub fn stroke_join(mut self, join: &str) -> Self {
+        assert!(
+            STROKE_JOINS.contains(&join),
+            "stroke join must be one of {}, got {:?}",
+            STROKE_JOINS.join(", "),
+            join
+        );
+        self.options
+            .stroke
+            .get_or_insert_with(Default::default)
+            .join = Some(join.to_string());
+        self
+    }
+
+    /// Give every stroke of the chart the miter limit `limit`
+    pub fn stroke_miter_limit(mut self, limit: f64) -> Self {
+        self.options
+            .stroke
+            .get_or_insert_with(Default::default)
+            .miter_limit = Some(limit);
+        self
+    }
+
+    /// Give every stroke of the chart the named dash pattern `dash`, such as
+    /// `"dashed"` or `"dotted"`
+    ///
+    /// The patterns are those Typst names: `"solid"`, `"dotted"`, `"dashed"` and
+    /// `"dash-dotted"`, each also prefixed with `"densely-"` or `"loosely-"`.
+    ///
+    /// # Panics
+    /// If `dash` is not one of these patterns.
+    pub fn stroke_dash(mut self, dash: &str) -> Self {
+        assert!(
+            STROKE_DASHES.contains(&dash),
+            "stroke dash must be a named Typst dash pattern, got {:?}",
+            dash
+        );
+        self.options
+            .stroke
+            .get_or_insert_with(Default::default)
+            .dash = Some(dash.to_string());
+        self
+    }
+
+    /// Multiply every stroke width by `factor`; as plotters widths are whole pixels,
+    /// a factor of `0.5` turns the default 1px lines into 0.5pt hairlines
+    ///
+    /// # Panics
+    /// If `factor` is not a finite number greater than zero.
+    pub fn stroke_scale(mut self, factor: f64) -> Self {
+        assert!(
+            factor.is_finite() && factor > 0.0,
+            "stroke scale must be finite and positive, got {}",
+            factor
+        );
+        self.options.stroke_scale = Some(factor);
+        self
+    }
+
+    /// Give all digits the same width, so numeric tick labels align in columns
+    pub fn tabular_figures(mut self) -> Self {
+        self.options.tabular_figures = true;
+        self
+    }
+
+    /// Disable the standard ligatures, e.g. to keep `fi` in a label two letters
+    pub fn disable_ligatures(mut self) -> Self {
+        self.options.disable_ligatures = true;
+        self
+    }
+
+    /// Draw all text in the requested fonts only, so a glyph missing from them shows
+    /// as a placeholder box instead of being taken from another font, e.g. for a
+    /// journal requiring a given typeface
+    pub fn strict_fonts(mut self) -> Self {
+        self.options.strict_fonts = true;
+        self
+    }
+
+    /// Let Typst turn quotes, `--`, `...` and `~` in labels into typographic
+    /// quotes, dashes, ellipses and non-breaking spaces
+    ///
+    /// By default labels are printed as typed, as data labels like `"A"-"B"` or
+    /// `--` would be mangled otherwise.
+    pub fn smart_punctuation(mut self) -> Self {
+        self.options.smart_punctuation = true;
+        self
+    }
+
+    /// Draw pure black text in the text color of the including document, so the
+    /// chart fits a dark themed document without being generated again
+    ///
+    /// Only the text is affected; black lines and shapes stay black.
+    pub fn inherit_text_color(mut self) -> Self {
+        self.options.inherit_text_color = true;
+        self
+    }
+
+    /// Draw every color as the gray of the same luminance, for a print friendly
+    /// monochrome variant of the chart; blitted images keep their colors
+    pub fn grayscale(mut self) -> Self {
+        self.options.grayscale = true;
+        self
+    }
+
+    /// Replace the colors of the chart by the colors of `palette`, in the order they
+    /// are first drawn, so the series stay distinct for readers with a color vision
+    /// deficiency; grays, black and white are kept
+    ///
+    /// A palette has 7 or 8 colors: past that many distinct colors, the palette
+    /// starts over and two colors of the chart share one, which
+    /// [`Self::present_report`] warns about.
+    pub fn colorblind_palette(mut self, palette: ColorblindPalette) -> Self {
+        self.options.palette = Some(palette);
+        self
+    }
+
+    /// Write every color with `serializer` instead of as `rgb(..)`, e.g. to use the
+    /// brand colors of a template such as `corp.blue`
+    ///
+    /// The serializer is called with the colors after the colorblind palette is
+    /// applied, and takes precedence over grayscale mode.
+    pub fn color_serializer<F: Fn(BackendColor) -> String + 'static>(
+        mut self,
+        serializer: F,
+    ) -> Self {
+        self.options.color_serializer = Some(Rc::new(serializer));
+        self
+    }
+
+    /// Write the markup returned by `hook` onto the canvas, over everything drawn,
+    /// e.g. a "DRAFT" stamp; successive calls add overlays
+    ///
+    /// The hook is called when the chart is presented, with the size of the canvas
+    /// and the mapping of data coordinates given by a
+    /// [`TypstCoordMap`](element::TypstCoordMap), to position the markup with
+    /// `#place(dx: .., dy: ..)`.
+    pub fn overlay<F: Fn(&CanvasInfo) -> String + 'static>(mut self, hook: F) -> Self {
+        self.options.overlays.push(Rc::new(hook));
+        self
+    }
+
+    /// Write the markup returned by `hook` onto the canvas under everything drawn,
+    /// such as a background picture; successive calls add underlays
+    ///
+    /// The hook is called like the ones of [`Self::overlay`].
+    pub fn underlay<F: Fn(&CanvasInfo) -> String + 'static>(mut self, hook: F) -> Self {
+        self.options.underlays.push(Rc::new(hook));
+        self
+    }
+
+    /// Write `text` across the middle of the canvas under the chart, rotated
+    /// clockwise by `angle` degrees and in gray of the given `opacity` from 0 to 1,
+    /// e.g. `watermark("CONFIDENTIAL", -30.0, 0.15)`
+    pub fn watermark(self, text: &str, angle: f64, opacity: f64) -> Self {
+        let text = text.to_string();
+        let alpha = (opacity.clamp(0.0, 1.0) * 100.0).round();
+        self.underlay(move |canvas| {
+            let (width, height) = canvas.size();
+            format!(
+                "  #place(center + horizon, rotate({}deg, text(size: {}, weight: \"bold\", fill: rgb(128, 128, 128, {}%))[{}]))",
+                angle,
+                canvas.len(f64::from(width.min(height)) / 5.0),
+                alpha,
+                canvas.escape(&text)
+            )
+        })
+    }
+
+    /// Let the hyphenation and justification settings of the including document
+    /// apply to the text of the chart, which by default is neither hyphenated nor
+    /// justified
+    pub fn inherit_paragraph_settings(mut self) -> Self {
+        self.options.inherit_paragraph = true;
+        self
+    }
+
+    /// Enable the OpenType features `features` on all text, such as `"smcp"` for
+    /// small capitals or `"zero"` for a slashed zero; features enabled before are
+    /// kept
+    pub fn font_features(mut self, features: &[&str]) -> Self {
+        for feature in features {
+            if !self.options.font_features.iter().any(|f| f == feature) {
+                self.options.font_features.push(feature.to_string());
+            }
+        }
+        self
+    }
+
+    /// The `set text` arguments of the font settings applying to all text, each
+    /// starting with `, `
+    pub(crate) fn text_features(&self) -> String {
+        let mut args = String::new();
+        if self.options.tabular_figures {
+            args.push_str(", number-width: \"tabular\"");
+        }
+        if self.options.disable_ligatures {
+            args.push_str(", ligatures: false");
+        }
+        if !self.options.font_features.is_empty() {
+            let tags: Vec<String> = self
+                .options
+                .font_features
+                .iter()
+                .map(|f| format!("\"{}\"", Self::escape_text(f)))
+                .collect();
+            // A single element array needs a trailing comma
+            let comma = if tags.len() == 1 { "," } else { "" };
+            write!(args, ", features: ({}{})", tags.join(", "), comma).unwrap();
+        }
+        if self.options.strict_fonts {
+            args.push_str(", fallback: false");
+        }
+        args
+    }
+
+    /// Append the chart to the file given to [`Self::new`] instead of overwriting
+    /// it, as a paragraph of its own, so a loop over datasets can accumulate its
+    /// figures in one document; a missing file is created
+    pub fn append(mut self) -> Self {
+        self.options.append = true;
+        self
+    }
+}
+
+/// Append the commands spilled to disk, if any, to `out`
+fn push_spilled(out: &mut String, spill: &Option<spill::Spill>) -> Result<(), Error> {
+    match spill {
+        Some(spill) => spill.read(|commands| {
+            out.push_str(commands);
+            Ok(())
+        }),
+        None => Ok(()),
+    }
+}
+
+/// `path` relative to the directory `dir`, or `None` when it cannot be told, such
+/// as when only one of them is absolute or they are on different drives
+fn relative_path(path: &Path, dir: &Path) -> Option<PathBuf> {
+    if path.has_root() != dir.has_root() {
+        return None;
+    }
+    let (path, dir): (Vec<_>, Vec<_>) = (path.components().collect(), dir.components().collect());
+    let common = path.iter().zip(&dir).take_while(|(a, b)| a == b).count();
+    let mut relative = PathBuf::new();
+    for component in &dir[common..] {
+        // Going up from `..` or from another drive is not known without the file
+        // system
+        match component {
+            Component::Normal(_) => relative.push(".."),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    for component in &path[common..] {
+        match component {
+            Component::Prefix(_) | Component::RootDir => return None,
+            _ => relative.push(component),
+        }
+    }
+    Some(relative)
+}
+
+/// The separation needed after the content of `path` to start a new paragraph
+fn append_separator(path: &Path) -> Result<&'static str, Error> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(""),
+        Err(e) => return Err(e),
+    };
+    let len = file.metadata()?.len();
+    let mut tail = Vec::with_capacity(2);
+    file.seek(SeekFrom::Start(len.saturating_sub(2)))?;
+    file.read_to_end(&mut tail)?;
+    Ok(match tail.as_slice() {
+        [] | [b'\n', b'\n'] => "",
+        [.., b'\n'] => "\n",
+        _ => "\n\n",
+    })
+}
+
+impl<'a> DrawingBackend for TypstBackend<'a> {
+    type ErrorType = Error;
+
+    fn get_size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    fn ensure_prepared(&mut self) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
+        Ok(())
+    }
+
+    fn present(&mut self) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
+        if !self.saved {
+            let (open, close) = self.finalize().map_err(DrawingErrorKind::DrawingError)?;
+
+            self.written = match self.target {
+                Target::File(path) => {
+                    let written = self
+                        .write_file(path, &open, &close)
+                        .map_err(DrawingErrorKind::DrawingError)?;
+                    if let Some(manifest) = self.manifest_json() {
+                        std::fs::write(path.with_extension("json"), manifest)
+                            .map_err(DrawingErrorKind::DrawingError)?;
+                    }
+                    written
+                }
+                Target::Buffer(ref mut out) => {
+                    let start = out.len();
+                    out.reserve(open.len() + self.buf.len() + close.len());
+                    out.push_str(&open);
+                    push_spilled(out, &self.spill).map_err(DrawingErrorKind::DrawingError)?;
+                    out.push_str(&self.buf);
+                    out.push_str(&close);
+                    out.len() - start
+                }
+                Target::Writer(ref mut writer) => {
+                    let mut written = 0;
+                    let mut write = |part: &str| {
+                        written += part.len();
+                        writer.write_all(part.as_bytes())
+                    };
+                    write(&open).map_err(DrawingErrorKind::DrawingError)?;
+                    if let Some(spill) = &self.spill {
+                        spill
+                            .read(&mut write)
+                            .map_err(DrawingErrorKind::DrawingError)?;
+                    }
+                    for part in [&self.buf, &close] {
+                        write(part).map_err(DrawingErrorKind::DrawingError)?;
+                    }
+                    writer.flush().map_err(DrawingErrorKind::DrawingError)?;
+                    written
+                }
+                Target::Shared(ref output) => {
+                    let mut out = output.0.borrow_mut();
+                    let start = out.len();
+                    out.reserve(open.len() + self.buf.len() + close.len());
+                    out.push_str(&open);
+                    push_spilled(&mut out, &self.spill).map_err(DrawingErrorKind::DrawingError)?;
+                    out.push_str(&self.buf);
+                    out.push_str(&close);
+                    out.len() - start
+                }
+            };
+            if let Some(log) = &mut self.command_log {
+                log.flush().map_err(DrawingErrorKind::DrawingError)?;
+            }
+            self.saved = true;
+        }
+        Ok(())
+    }
+
+    fn draw_pixel(
+        &mut self,
+        point: BackendCoord,
+        color: BackendColor,
+    ) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
+        if color.alpha == 0.0 {
+            return Ok(());
+        }
+
+        let color_str = self.typst_color(color);
+        let call = self.begin_call(|| format!("draw_pixel({:?})", point));
+        #[cfg(feature = "image")]
+        let start = self.buf.len();
+        let (x, y, one) = (self.len(point.0), self.len(point.1), self.len(1));
+        let stroke = if self.options.compact {
+            ""
+        } else {
+            ", stroke: none"
+        };
+        self.write_command_args(format_args!(
+            "  #place(dx: {}, dy: {}, rect(width: {}, height: {}, fill: {}{}))",
+            x, y, one, one, color_str, stroke
+        ));
+        #[cfg(feature = "image")]
+        self.rasterize(start, color, raster::Shape::Pixel(point));
+        self.note_element("pixel");
+        self.log_command(|| {
+            JsonObject::new("pixel")
+                .point("at", point)
+                .color("color", color)
+        })?;
+        self.include_bounds(point, (point.0 + 1, point.1 + 1), 0);
+        self.end_call(call)?;
+        Ok(())
+    }
+
+    fn draw_line<S: BackendStyle>(
+        &mut self,
+        from: BackendCoord,
+        to: BackendCoord,
+        style: &S,
+    ) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
+        if style.color().alpha == 0.0 {
+            return Ok(());
+        }
+
+        let color = self.typst_color(style.color());
+        let stroke_width = style.stroke_width();
+
+        let dx = (to.0 - from.0) as f64;
+        let dy = (to.1 - from.1) as f64;
+        let length = (dx * dx + dy * dy).sqrt();
+        let mut angle = dy.atan2(dx).to_degrees();
+        if self.options.reproducible {
+            // The last digits of trigonometric functions differ between platforms
+            angle = round_decimals(angle);
+        }
+
+        let stroke = self.stroke(stroke_width, &color);
+        let call = self.begin_call(|| format!("draw_line({:?}, {:?})", from, to));
+        #[cfg(feature = "image")]
+        let start = self.buf.len();
+        let (x, y, length) = (self.len(from.0), self.len(from.1), self.len(length));
+        self.buf.push_str("  #place(dx: ");
+        write!(self.buf, "{}, dy: {}, line(length: {}", x, y, length).unwrap();
+        if angle != 0.0 || !self.options.compact {
+            self.buf.push_str(", angle: ");
+            write_number(&mut self.buf, angle).unwrap();
+            self.buf.push_str("deg");
+        }
+        self.write_command_args(format_args!(", stroke: {}))", stroke));
+        #[cfg(feature = "image")]
+        {
+            let width = self.stroke_px(stroke_width);
+            self.rasterize(start, style.color(), raster::Shape::Line(from, to, width));
+        }
+        self.note_element("line");
+        self.log_command(|| {
+            JsonObject::new("line")
+                .point("from", from)
+                .point("to", to)
+                .color("color", style.color())
+                .number("stroke_width", stroke_width)
+        })?;
+        self.include_bounds(from, to, self.half_stroke(stroke_width));
+        self.note_series_point(from);
+        self.note_series_point(to);
+        self.write_debug_overlay(from, to, from);
+        self.end_call(call)?;
+        Ok(())
+    }
+
+    fn draw_rect<S: BackendStyle>(
+        &mut self,
+        upper_left: BackendCoord,
+        bottom_right: BackendCoord,
+        style: &S,
+        fill: bool,
+    ) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
+        if style.color().alpha == 0.0 {
+            return Ok(());
+        }
+
+        let width = bottom_right.0 - upper_left.0;
+        let height = bottom_right.1 - upper_left.1;
+
+        let covers_canvas = self.covers_canvas(upper_left, bottom_right);
+        // A full-canvas fill before anything else is drawn, as done by
+        // `root.fill(..)`, becomes the fill of the canvas box itself, left out in
+        // transparent mode. A translucent fill over an earlier one has to be
+        // composited, so it stays a rect.
+        if fill
+            && covers_canvas
+            && self.buf.is_empty()
+            && (self.background.is_none() || style.color().alpha == 1.0)
+        {
+            self.background = Some(style.color());
+            return Ok(());
+        }
+
+        let outlined = !covers_canvas;
+        let attrs = self.shape_attrs(style, fill, outlined);
+
+        let call = self.begin_call(|| {
+            format!(
+                "draw_rect({:?}, {:?}, fill: {})",
+                upper_left, bottom_right, fill
+            )
+        });
+        #[cfg(feature = "image")]
+        let start = self.buf.len();
+        if !(fill && !covers_canvas && self.batch_bar(upper_left, bottom_right, &attrs)) {
+            let (x, y) = (self.len(upper_left.0), self.len(upper_left.1));
+            let (width, height) = (self.len(width), self.len(height));
+            let begun = self.buf.len();
+            self.write_command_args(format_args!(
+                "  #place(dx: {}, dy: {}, rect(width: {}, height: {}, {}))",
+                x, y, width, height, attrs
+            ));
+            // Only the rect just starting new bars belongs to them, any other one
+            // ends them
+            match self.bars.as_mut() {
+                Some(bars) if bars.first.is_some() && bars.command == (begun..begun) => {
+                    bars.command.end = self.buf.len();
+                }
+                _ => self.bars = None,
+            }
+        }
+        #[cfg(feature = "image")]
+        self.rasterize_closed(start, style, fill, outlined, |stroke| {
+            raster::Shape::Rect(upper_left, bottom_right, stroke)
+        });
+        self.note_element("rect");
+        self.log_command(|| {
+            JsonObject::new("rect")
+                .point("from", upper_left)
+                .point("to", bottom_right)
+                .color("color", style.color())
+                .number("stroke_width", style.stroke_width())
+                .flag("filled", fill)
+        })?;
+        let margin = self.shape_margin(style, fill, outlined);
+        self.include_bounds(upper_left, bottom_right, margin);
+        self.write_debug_overlay(upper_left, bottom_right, upper_left);
+        self.end_call(call)?;
+        Ok(())
+    }
+
+    fn draw_path<S: BackendStyle, I: IntoIterator<Item = BackendCoord>>(
+        &mut self,
+        path: I,
+        style: &S,
+    ) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
+        if style.color().alpha == 0.0 {
+            return Ok(());
+        }
+
+        let mut points: Vec<_> = path.into_iter().collect();
+        if self.options.decimate {
+            points = decimate_min_max(points);
+        }
+        let points = merge_collinear(points);
+        if points.len() < 2 {
+            return Ok(());
+        }
+
+        let call = self.begin_call(|| format!("draw_path({} points)", points.len()));
+        // Draw as individual line segments to avoid auto-closing
+        for window in points.windows(2) {
+            let from = window[0];
+            let to = window[1];
+            self.draw_line(from, to, style)?;
+        }
+        self.end_call(call)?;
+
+        Ok(())
+    }
+
+    fn fill_polygon<S: BackendStyle, I: IntoIterator<Item = BackendCoord>>(
+        &mut self,
+        path: I,
+        style: &S,
+    ) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
+        if style.color().alpha == 0.0 {
+            return Ok(());
+        }
+
+        let points: Vec<_> = path.into_iter().collect();
+        if points.is_empty() {
+            return Ok(());
+        }
+
+        let color = self.typst_color(style.color());
+
+        let call = self.begin_call(|| format!("fill_polygon({} points)", points.len()));
+        #[cfg(feature = "image")]
+        let start = self.buf.len();
+        write!(self.buf, "  #place(polygon(fill: {}", color).unwrap();
+        if !self.options.compact {
+            self.buf.push_str(", stroke: none");
+        }
+        for &(x, y) in &points {
+            let (x, y) = (self.len(x), self.len(y));
+            write!(self.buf, ", ({}, {})", x, y).unwrap();
+        }
+        self.write_command("))");
+        #[cfg(feature = "image")]
+        self.rasterize(
+            start,
+            style.color(),
+            raster::Shape::Polygon((&points[..]).into()),
+        );
+        self.note_element("polygon");
+        self.log_command(|| {
+            JsonObject::new("polygon")
+                .points("points", &points)
+                .color("color", style.color())
+        })?;
+        self.include_points(&points, 0);
+        if self.options.debug_overlay {
+            let upper_left = points
+                .iter()
+                .fold(points[0], |a, p| (a.0.min(p.0), a.1.min(p.1)));
+            let bottom_right = points
+                .iter()
+                .fold(points[0], |a, p| (a.0.max(p.0), a.1.max(p.1)));
+            self.write_debug_overlay(upper_left, bottom_right, points[0]);
+        }
+        self.end_call(call)?;
+        Ok(())
+    }
+
+    fn draw_circle<S: BackendStyle>(
+        &mut self,
+        center: BackendCoord,
+        radius: u32,
+        style: &S,
+        fill: bool,
+    ) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
+        if style.color().alpha == 0.0 {
+            return Ok(());
+        }
+
+        let attrs = self.shape_attrs(style, fill, true);
+
+        // Typst circle is positioned by center minus radius to get top-left
+        let shape = format!("circle(radius: {}, {})", self.len(radius), attrs);
+        let shape = self.marker_mark(shape);
+        let call =
+            self.begin_call(|| format!("draw_circle({:?}, {}, fill: {})", center, radius, fill));
+        #[cfg(feature = "image")]
+        let start = self.buf.len();
+        let x = self.len(center.0 - radius as i32);
+        let y = self.len(center.1 - radius as i32);
+        self.write_command_args(format_args!("  #place(dx: {}, dy: {}, {})", x, y, shape));
+        #[cfg(feature = "image")]
+        self.rasterize_closed(start, style, fill, true, |stroke| {
+            raster::Shape::Circle(center, radius, stroke)
+        });
+        self.note_element("circle");
+        self.log_command(|| {
+            JsonObject::new("circle")
+                .point("center", center)
+                .number("radius", radius)
+                .color("color", style.color())
+                .number("stroke_width", style.stroke_width())
+                .flag("filled", fill)
+        })?;
+        let r = radius as i32;
+        let margin = self.shape_margin(style, fill, true);
+        self.include_bounds(
+            (center.0 - r, center.1 - r),
+            (center.0 + r, center.1 + r),
+            margin,
+        );
+        self.note_series_point(center);
+        self.write_debug_overlay(
+            (center.0 - r, center.1 - r),
+            (center.0 + r, center.1 + r),
+            center,
+        );
+        self.end_call(call)?;
+        Ok(())
+    }
+
+    fn draw_text<S: BackendTextStyle>(
+        &mut self,
+        text: &str,
+        style: &S,
+        pos: BackendCoord,
+    ) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
+        let angle = match style.transform() {
+            FontTransform::Rotate90 => 90.0,
+            FontTransform::Rotate180 => 180.0,
+            FontTransform::Rotate270 => 270.0,
+            _ => 0.0,
+        };
+        let call = self.begin_call(|| format!("draw_text({:?}, {:?})", text, pos));
+        self.write_text(text, style, pos, angle, Decoration::default());
+        self.log_text(text, style, pos, angle)?;
+        self.end_call(call)?;
+        Ok(())
+    }
+
+    #[cfg(all(not(target_arch = "wasm32"), feature = "image"))]
+    fn blit_bitmap(
+        &mut self,
+        pos: BackendCoord,
+        (w, h): (u32, u32),
+        src: &[u8],
+    ) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
+        use image::codecs::png::PngEncoder;
+        use image::ImageEncoder;
+        use std::io::Cursor;
+
+        let mut data = vec![];
+
+        {
+            let cursor = Cursor::new(&mut data);
+            let encoder = PngEncoder::new(cursor);
+            let color = image::ExtendedColorType::Rgb8;
+
+            encoder.write_image(src, w, h, color).map_err(|e| {
+                DrawingErrorKind::DrawingError(Error::other(format!("Image error: {}", e)))
+            })?;
+        }
+
+        // Convert to base64
+        let base64_data = base64_encode(&data);
+
+        let cmd = format!(
+            "  #place(dx: {}, dy: {}, image.decode(\"data:image/png;base64,{}\", width: {}, height: {}))",
+            self.len(pos.0), self.len(pos.1), base64_data, self.len(w), self.len(h)
+        );
+        let call = self.begin_call(|| format!("blit_bitmap({:?}, {:?})", pos, (w, h)));
+        self.write_command(&cmd);
+        self.note_element("image");
+        self.log_command(|| {
+            JsonObject::new("image")
+                .point("at", pos)
+                .number("width", w)
+                .number("height", h)
+        })?;
+        self.include_bounds(pos, (pos.0 + w as i32, pos.1 + h as i32), 0);
+        self.write_debug_overlay(pos, (pos.0 + w as i32, pos.1 + h as i32), pos);
+        self.end_call(call)?;
+        Ok(())
+    }
+}
+
+/// Keep the first, lowest, highest and last point of each run of points in the
+/// same column, in the order of the path, see [`TypstBackend::decimate_lines`]
+fn decimate_min_max(points: Vec<BackendCoord>) -> Vec<BackendCoord> {
+    let mut decimated = Vec::with_capacity(points.len().min(4096));
+    for run in points.chunk_by(|a, b| a.0 == b.0) {
+        if run.len() <= 4 {
+            decimated.extend_from_slice(run);
+            continue;
+        }
+        let (mut low, mut high) = (1, 1);
+        for (i, point) in run.iter().enumerate().take(run.len() - 1).skip(1) {
+            if point.1 < run[low].1 {
+                low = i;
+            }
+            if point.1 > run[high].1 {
+                high = i;
+            }
+        }
+        decimated.push(run[0]);
+        decimated.push(run[low.min(high)]);
+        decimated.push(run[low.max(high)]);
+        decimated.push(run[run.len() - 1]);
+    }
+    decimated
+}
+
+/// The placed `commands` without the offsets of `zero` length, see
+/// [`TypstBackend::compact`]
+fn compact_lines(commands: &str, zero: &str) -> String {
+    let (dx, dy) = (format!("dx: {}, ", zero), format!("dy: {}, ", zero));
+    let mut compacted = String::with_capacity(commands.len());
+    for line in commands.split_inclusive('\n') {
+        let command = line.trim_start_matches(' ');
+        let Some(args) = command.strip_prefix("#place(") else {
+            compacted.push_str(line);
+            continue;
+        };
+        compacted.push_str(&line[..line.len() - args.len()]);
+        let args = args.strip_prefix(&dx).unwrap_or(args);
+        compacted.push_str(args.strip_prefix(&dy).unwrap_or(args));
+    }
+    compacted
+}
+
+/// Drop repeated points and interior points of collinear runs, so a straight
+/// run of segments is emitted as a single line.
+fn merge_collinear(points: Vec<BackendCoord>) -> Vec<BackendCoord> {
+    let mut merged: Vec<BackendCoord> = Vec::with_capacity(points.len());
+
+    for point in points {
+        if merged.last() == Some(&point) {
+            continue;
+        }
+
+        if let [.., a, b] = merged[..] {
+            let (d1x, d1y) = (i64::from(b.0 - a.0), i64::from(b.1 - a.1));
+            let (d2x, d2y) = (i64::from(point.0 - b.0), i64::from(point.1 - b.1));
+            // Same direction only: a path doubling back on itself must keep its turn point
+            if d1x * d2y == d1y * d2x && d1x * d2x + d1y * d2y > 0 {
+                merged.pop();
+            }
+        }
+
+        merged.push(point);
+    }
+
+    merged
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "image"))]
+fn base64_encode(data: &[u8]) -> String {
+    const BASE64_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut result = String::new();
+    let mut i = 0;
+
+    while i + 2 < data.len() {
+        let b1 = data[i];
+        let b2 = data[i + 1];
+        let b3 = data[i + 2];
+
+        result.push(BASE64_CHARS[(b1 >> 2) as usize] as char);
+        result.push(BASE64_CHARS[(((b1 & 0x03) << 4) | (b2 >> 4)) as usize] as char);
+        result.push(BASE64_CHARS[(((b2 & 0x0F) << 2) | (b3 >> 6)) as usize] as char);
+        result.push(BASE64_CHARS[(b3 & 0x3F) as usize] as char);
+
+        i += 3;
+    }
+
+    // Handle remaining bytes
+    if i < data.len() {
+        let b1 = data[i];
+        result.push(BASE64_CHARS[(b1 >> 2) as usize] as char);
+
+        if i + 1 < data.len() {
+            let b2 = data[i + 1];
+            result.push(BASE64_CHARS[(((b1 & 0x03) << 4) | (b2 >> 4)) as usize] as char);
+            result.push(BASE64_CHARS[((b2 & 0x0F) << 2) as usize] as char);
+            result.push('=');
+        } else {
+            result.push(BASE64_CHARS[((b1 & 0x03) << 4) as usize] as char);
+            result.push_str("==");
+        }
+    }
+
+    result
+}
+
+impl Drop for TypstBackend<'_> {
+    fn drop(&mut self) {
+        if !self.saved {
+            // drop should not panic, so we ignore a failed present
+            let _ = self.present();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use plotters::coord::Shift;
+    use plotters::prelude::*;
+    use plotters::style::text_anchor::{HPos, Pos, VPos};
+    use std::fs;
+
+    static DST_DIR: &str = "target/test/typst";
+
+    pub(crate) fn checked_save_file(name: &str, content: &str) {
+        /*
+          Please use the Typst file to manually verify the results.
+        */
+        assert!(!content.is_empty());
+        fs::create_dir_all(DST_DIR).unwrap();
+        let file_name = format!("{}.typ", name);
+        let file_path = std::path::Path::new(DST_DIR).join(file_name);
+        println!("{:?} created", file_path);
+        fs::write(file_path, content).unwrap();
+        #[cfg(feature = "test-util")]
+        crate::test_util::assert_snapshot(name, content);
+    }
+
+    /// Check that the reveal groups and clip regions of `content` are each closed
+    /// by their own bracket, innermost first
+    #[cfg(feature = "plotters")]
+    pub(crate) fn assert_nested_groups(content: &str) {
+        let mut open = Vec::new();
+        for line in content.lines() {
+            if line.starts_with("  #only(") && line.ends_with('[') {
+                open.push("  ]");
+            } else if line.contains("box(width: ") && line.contains(", clip: true, place(") {
+                open.push("  ])))");
+            } else if line == "  ]" || line == "  ])))" {
+                assert_eq!(
+                    open.pop(),
+                    Some(line),
+                    "mismatched group end in {}",
+                    content
+                );
+            }
+        }
+        assert!(open.is_empty(), "unclosed groups in {}", content);
+    }
+
+    fn draw_mesh_with_custom_ticks(tick_size: i32, test_name: &str) {
+        let mut content: String = Default::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (500, 500)).into_drawing_area();
+
+            let mut chart = ChartBuilder::on(&root)
+                .caption("This is a test", ("sans-serif", 20u32))
+                .set_all_label_area_size(40u32)
+                .build_cartesian_2d(0..10, 0..10)
+                .unwrap();
+
+            chart
+                .configure_mesh()
+                .set_all_tick_mark_size(tick_size)
+                .draw()
+                .unwrap();
+        }
+
+        checked_save_file(test_name, &content);
+
+        assert!(content.contains("This is a test"));
+    }
+
+    #[test]
+    fn test_draw_mesh_no_ticks() {
+        draw_mesh_with_custom_ticks(0, "test_draw_mesh_no_ticks");
+    }
+
+    #[test]
+    fn test_draw_mesh_negative_ticks() {
+        draw_mesh_with_custom_ticks(-10, "test_draw_mesh_negative_ticks");
+    }
+
+    #[test]
+    fn test_text_alignments() {
+        let mut content: String = Default::default();
+        {
+            let mut root = TypstBackend::with_string(&mut content, (500, 500));
+
+            let style = TextStyle::from(("sans-serif", 20).into_font())
+                .pos(Pos::new(HPos::Right, VPos::Top));
+            root.draw_text("right-align", &style, (150, 50)).unwrap();
+
+            let style = style.pos(Pos::new(HPos::Center, VPos::Top));
+            root.draw_text("center-align", &style, (150, 150)).unwrap();
+
+            let style = style.pos(Pos::new(HPos::Left, VPos::Top));
+            root.draw_text("left-align", &style, (150, 200)).unwrap();
+        }
+
+        checked_save_file("test_text_alignments", &content);
+
+        assert!(content.contains("right-align"));
+        assert!(content.contains("center-align"));
+        assert!(content.contains("left-align"));
+        // Right and center aligned text is placed from the right edge and the center
+        assert!(content.contains("#place(top + right, dx: -350pt, dy: 50pt, box["));
+        assert!(content.contains("#place(top + center, dx: -100pt, dy: 150pt, box["));
+        assert!(!content.contains("measure("));
+    }
+
+    #[test]
+    fn test_text_draw() {
+        let mut content: String = Default::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (1500, 800)).into_drawing_area();
+            let root = root
+                .titled("Image Title", ("sans-serif", 60).into_font())
+                .unwrap();
+
+            let mut chart = ChartBuilder::on(&root)
+                .caption("All anchor point positions", ("sans-serif", 20u32))
+                .set_all_label_area_size(40u32)
+                .build_cartesian_2d(0..100i32, 0..50i32)
+                .unwrap();
+
+            chart
+                .configure_mesh()
+                .disable_x_mesh()
+                .disable_y_mesh()
+                .x_desc("X Axis")
+                .y_desc("Y Axis")
+                .draw()
+                .unwrap();
+
+            let ((x1, y1), (x2, y2), (x3, y3)) = ((-30, 30), (0, -30), (30, 30));
+
+            for (dy, trans) in [
+                FontTransform::None,
+                FontTransform::Rotate90,
+                FontTransform::Rotate180,
+                FontTransform::Rotate270,
+            ]
+            .iter()
+            .enumerate()
+            {
+                for (dx1, h_pos) in [HPos::Left, HPos::Right, HPos::Center].iter().enumerate() {
+                    for (dx2, v_pos) in [VPos::Top, VPos::Center, VPos::Bottom].iter().enumerate() {
+                        let x = 150_i32 + (dx1 as i32 * 3 + dx2 as i32) * 150;
+                        let y = 120 + dy as i32 * 150;
+                        let draw = |x, y, text| {
+                            root.draw(&Circle::new((x, y), 3, BLACK.mix(0.5))).unwrap();
+                            let style = TextStyle::from(("sans-serif", 20).into_font())
+                                .pos(Pos::new(*h_pos, *v_pos))
+                                .transform(trans.clone());
+                            root.draw_text(text, &style, (x, y)).unwrap();
+                        };
+                        draw(x + x1, y + y1, "dood");
+                        draw(x + x2, y + y2, "dog");
+                        draw(x + x3, y + y3, "goog");
+                    }
+                }
+            }
+        }
+
+        checked_save_file("test_text_draw", &content);
+
+        // Each label is written once, whatever its alignment
+        assert_eq!(content.matches("dog").count(), 36);
+        assert_eq!(content.matches("dood").count(), 36);
+        assert_eq!(content.matches("goog").count(), 36);
+    }
+
+    #[test]
+    fn test_text_clipping() {
+        let mut content: String = Default::default();
+        {
+            let (width, height) = (500_i32, 500_i32);
+            let root = TypstBackend::with_string(&mut content, (width as u32, height as u32))
+                .into_drawing_area();
+
+            let style = TextStyle::from(("sans-serif", 20).into_font())
+                .pos(Pos::new(HPos::Center, VPos::Center));
+            root.draw_text("TOP LEFT", &style, (0, 0)).unwrap();
+            root.draw_text("TOP CENTER", &style, (width / 2, 0))
+                .unwrap();
+            root.draw_text("TOP RIGHT", &style, (width, 0)).unwrap();
+
+            root.draw_text("MIDDLE LEFT", &style, (0, height / 2))
+                .unwrap();
+            root.draw_text("MIDDLE RIGHT", &style, (width, height / 2))
+                .unwrap();
+
+            root.draw_text("BOTTOM LEFT", &style, (0, height)).unwrap();
+            root.draw_text("BOTTOM CENTER", &style, (width / 2, height))
+                .unwrap();
+            root.draw_text("BOTTOM RIGHT", &style, (width, height))
+                .unwrap();
+        }
+
+        checked_save_file("test_text_clipping", &content);
+    }
+
+    #[test]
+    fn test_series_labels() {
+        let mut content = String::default();
+        {
+            let (width, height) = (500, 500);
+            let root = TypstBackend::with_string(&mut content, (width, height)).into_drawing_area();
+
+            let mut chart = ChartBuilder::on(&root)
+                .caption("All series label positions", ("sans-serif", 20u32))
+                .set_all_label_area_size(40u32)
+                .build_cartesian_2d(0..50i32, 0..50i32)
+                .unwrap();
+
+            chart
+                .configure_mesh()
+                .disable_x_mesh()
+                .disable_y_mesh()
+                .draw()
+                .unwrap();
+
+            chart
+                .draw_series(std::iter::once(Circle::new((5, 15), 5u32, RED)))
+                .expect("Drawing error")
+                .label("Series 1")
+                .legend(|(x, y)| Circle::new((x, y), 3u32, RED.filled()));
+
+            chart
+                .draw_series(std::iter::once(Circle::new((5, 15), 10u32, BLUE)))
+                .expect("Drawing error")
+                .label("Series 2")
+                .legend(|(x, y)| Circle::new((x, y), 3u32, BLUE.filled()));
+
+            for pos in vec![
+                SeriesLabelPosition::UpperLeft,
+                SeriesLabelPosition::MiddleLeft,
+                SeriesLabelPosition::LowerLeft,
+                SeriesLabelPosition::UpperMiddle,
+                SeriesLabelPosition::MiddleMiddle,
+                SeriesLabelPosition::LowerMiddle,
+                SeriesLabelPosition::UpperRight,
+                SeriesLabelPosition::MiddleRight,
+                SeriesLabelPosition::LowerRight,
+                SeriesLabelPosition::Coordinate(70, 70),
+            ]
+            .into_iter()
+            {
+                chart
+                    .configure_series_labels()
+                    .border_style(BLACK.mix(0.5))
+                    .position(pos)
+                    .draw()
+                    .expect("Drawing error");
+            }
+        }
+
+        checked_save_file("test_series_labels", &content);
+    }
+
+    #[test]
+    fn test_draw_pixel_alphas() {
+        let mut content = String::default();
+        {
+            let (width, height) = (100_i32, 100_i32);
+            let root = TypstBackend::with_string(&mut content, (width as u32, height as u32))
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+
+            for i in -20..20 {
+                let alpha = i as f64 * 0.1;
+                root.draw_pixel((50 + i, 50 + i), &BLACK.mix(alpha))
+                    .unwrap();
+            }
+        }
+
+        checked_save_file("test_draw_pixel_alphas", &content);
+    }
+
+    #[test]
+    fn test_simple_drawing() {
+        let mut content: String = Default::default();
+        {
+            let mut backend = TypstBackend::with_string(&mut content, (500, 500));
+
+            // Draw a simple rectangle
+            backend
+                .draw_rect((10, 10), (100, 100), &RGBColor(255, 0, 0), true)
+                .unwrap();
+
+            backend.present().unwrap();
+        }
+
+        checked_save_file("test_simple_drawing", &content);
+        assert!(content.contains("rect"));
+        assert!(content.contains("rgb(255, 0, 0)"));
+    }
+
+    #[test]
+    fn test_draw_line() {
+        let mut content = String::default();
+        {
+            let mut backend = TypstBackend::with_string(&mut content, (300, 300));
+
+            backend
+                .draw_line((10, 10), (100, 100), &RGBColor(0, 255, 0))
+                .unwrap();
+
+            backend.present().unwrap();
+        }
+
+        checked_save_file("test_draw_line", &content);
+        assert!(content.contains("line"));
+        assert!(content.contains("rgb(0, 255, 0)"));
+    }
+
+    #[test]
+    fn test_draw_circle() {
+        let mut content = String::default();
+        {
+            let mut backend = TypstBackend::with_string(&mut content, (300, 300));
+
+            // Filled circle
+            backend
+                .draw_circle((150, 150), 50, &RGBColor(0, 0, 255), true)
+                .unwrap();
+
+            backend.present().unwrap();
+        }
+
+        checked_save_file("test_draw_circle", &content);
+        assert!(content.contains("circle"));
+        assert!(content.contains("rgb(0, 0, 255)"));
+    }
+
+    #[test]
+    fn test_draw_polygon() {
+        let mut content = String::default();
+        {
+            let mut backend = TypstBackend::with_string(&mut content, (300, 300));
+
+            let points = vec![(50, 50), (100, 50), (75, 100)];
+            backend
+                .fill_polygon(points, &RGBColor(255, 128, 0))
+                .unwrap();
+
+            backend.present().unwrap();
+        }
+
+        checked_save_file("test_draw_polygon", &content);
+        assert!(content.contains("polygon"));
+        assert!(content.contains("rgb(255, 128, 0)"));
+    }
+
+    #[test]
+    fn test_outlined_markers() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (300, 300))
+                .with_outline(&ShapeStyle::from(BLACK).stroke_width(2))
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+
+            root.draw(&Circle::new((150, 150), 10, RED.filled()))
+                .unwrap();
+            root.draw(&Rectangle::new([(10, 10), (50, 50)], BLUE.filled()))
+                .unwrap();
+            root.draw(&Circle::new((100, 100), 10, GREEN)).unwrap();
+        }
+
+        checked_save_file("test_outlined_markers", &content);
+        assert!(content.contains("fill: rgb(255, 0, 0), stroke: 2pt + rgb(0, 0, 0)"));
+        assert!(content.contains("fill: rgb(0, 0, 255), stroke: 2pt + rgb(0, 0, 0)"));
+        // The background is not a shape, unfilled shapes keep their own stroke
+        assert!(content.contains("clip: true, fill: rgb(255, 255, 255))["));
+        assert!(content.contains("fill: none, stroke: 1pt + rgb(0, 255, 0)"));
+    }
+
+    #[test]
+    fn test_inline_mode() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (60, 12))
+                .inline()
+                .into_drawing_area();
+            root.draw(&PathElement::new(
+                vec![(0, 10), (20, 2), (40, 8), (60, 0)],
+                BLUE,
+            ))
+            .unwrap();
+        }
+
+        checked_save_file("test_inline_mode", &content);
+        assert!(content.starts_with("#box(width: 60pt, height: 12pt, clip: false)["));
+        assert!(content.ends_with("]"));
+    }
+
+    #[test]
+    fn test_baseline_alignment() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (60, 20))
+                .inline()
+                .baseline(15)
+                .into_drawing_area();
+            let chart = ChartBuilder::on(&root)
+                .build_cartesian_2d(0..10, -1..3)
+                .unwrap();
+            assert_eq!(chart.backend_coord(&(0, 0)).1, 15);
+        }
+
+        assert!(content.starts_with("#box(width: 60pt, height: 20pt, clip: false, baseline: 5pt)["));
+    }
+
+    #[test]
+    fn test_responsive_width() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (400, 300))
+                .responsive()
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            root.draw(&Circle::new((200, 150), 100, RED)).unwrap();
+        }
+
+        // The chart takes the width of its container, which must be definite.
+        checked_save_file(
+            "test_responsive_width",
+            &format!("#block(width: 200pt)[\n{}]\n", content),
+        );
+        assert!(content.starts_with(
+            "#layout(size => { let s = size.width / 400pt; box(width: s * 400pt, height: s * 300pt, scale(x: s * 100%, y: s * 100%, origin: top + left, box(width: 400pt, height: 300pt, clip: true, fill: rgb(255, 255, 255))["
+        ));
+        assert!(content.ends_with("])) })\n"));
+    }
+
+    #[test]
+    fn test_scaled_output() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (800, 600))
+                .scale(0.5)
+                .into_drawing_area();
+            root.draw(&Circle::new((400, 300), 200, RED)).unwrap();
+        }
+
+        checked_save_file("test_scaled_output", &content);
+        assert!(content.starts_with(
+            "#box(width: 0.5 * 800pt, height: 0.5 * 600pt, scale(x: 0.5 * 100%, y: 0.5 * 100%, origin: top + left, box(width: 800pt, height: 600pt, clip: true)["
+        ));
+        assert!(content.ends_with("]))\n"));
+    }
+
+    #[test]
+    #[should_panic(expected = "scale factor must be finite and positive, got NaN")]
+    fn test_scale_rejects_nan() {
+        let _ = TypstBackend::new_buffered((800, 600)).scale(f64::NAN);
+    }
+
+    #[test]
+    #[should_panic(expected = "em font size must be finite and positive, got 0")]
+    fn test_em_units_rejects_zero() {
+        let _ = TypstBackend::new_buffered((800, 600)).em_units(0.0);
+    }
+
+    #[test]
+    fn test_em_units() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (220, 110))
+                .em_units(11.0)
+                .responsive()
+                .into_drawing_area();
+            root.draw(&Rectangle::new([(11, 22), (33, 55)], RED))
+                .unwrap();
+            root.draw(&Text::new("label", (0, 0), ("sans-serif", 13.64)))
+                .unwrap();
+        }
+
+        checked_save_file("test_em_units", &content);
+        assert!(!content.contains("pt"));
+        assert!(content.contains("let s = size.width / (20em).to-absolute();"));
+        assert!(content.contains("box(width: 20em, height: 10em, clip: true)"));
+        assert!(content.contains(
+            "#place(dx: 1em, dy: 2em, rect(width: 2em, height: 3em, fill: none, stroke: 0.0909em + rgb(255, 0, 0)))"
+        ));
+        assert!(content.contains("size: 1em"));
+    }
+
+    #[test]
+    fn test_background_fill() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (200, 100)).into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            root.fill(&RED.mix(0.5)).unwrap();
+            root.draw(&Circle::new((100, 50), 20, BLUE)).unwrap();
+            root.fill(&BLACK.mix(0.1)).unwrap();
+        }
+
+        checked_save_file("test_background_fill", &content);
+        assert!(content.starts_with(
+            "#box(width: 200pt, height: 100pt, clip: true, fill: rgb(255, 255, 255))["
+        ));
+        // Translucent fills are composited over what is below them
+        assert!(content.contains("rect(width: 200pt, height: 100pt, fill: rgb(255, 0, 0, 50%)"));
+        assert!(content.contains("rect(width: 200pt, height: 100pt, fill: rgb(0, 0, 0, 10%)"));
+    }
+
+    #[test]
+    fn test_transparent_background() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (200, 100))
+                .transparent_background()
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            root.draw(&Circle::new((100, 50), 20, BLUE)).unwrap();
+            root.fill(&BLACK.mix(0.1)).unwrap();
+            root.draw(&Rectangle::new([(10, 10), (50, 50)], RED.filled()))
+                .unwrap();
+        }
+
+        checked_save_file("test_transparent_background", &content);
+        assert!(content.starts_with("#box(width: 200pt, height: 100pt, clip: true)["));
+        // The translucent overlay drawn over the circle is not a background
+        assert_eq!(content.matches("rect(").count(), 2);
+        assert!(content.contains("rect(width: 200pt, height: 100pt, fill: rgb(0, 0, 0, 10%)"));
+    }
+
+    #[test]
+    fn test_canvas_frame() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (200, 100))
+                .frame(&ShapeStyle::from(BLACK).stroke_width(2), 5)
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+        }
+
+        checked_save_file("test_canvas_frame", &content);
+        assert!(content.starts_with(
+            "#box(width: 200pt, height: 100pt, clip: true, fill: rgb(255, 255, 255), stroke: 2pt + rgb(0, 0, 0), radius: 5pt)["
+        ));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_assert_compiles() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (100, 50))
+                .inline()
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            root.draw(&Text::new("a \"quoted\" #hash", (5, 5), ("sans-serif", 10)))
+                .unwrap();
+        }
+
+        crate::test_util::assert_compiles(&content);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    #[should_panic(expected = "Typst compilation failed")]
+    fn test_assert_compiles_rejects_invalid() {
+        crate::test_util::assert_compiles("#box(width: 10pt, height: [");
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_missing_snapshot_fails() {
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            return;
+        }
+        let name = "test_missing_snapshot_fails";
+        let result = std::panic::catch_unwind(|| {
+            crate::test_util::assert_snapshot(name, "#box(width: 10pt, height: 10pt)")
+        });
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots");
+        assert!(!dir.join(format!("{}.png", name)).exists());
+        fs::remove_file(dir.join(format!("{}.actual.png", name))).unwrap();
+        let message = result.unwrap_err();
+        assert!(message
+            .downcast_ref::<String>()
+            .unwrap()
+            .contains("has no reference"));
+    }
+
+    #[test]
+    fn test_debug_overlay() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (300, 200))
+                .debug_overlay()
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            let mut chart = ChartBuilder::on(&root)
+                .caption("Debug", ("sans-serif", 20))
+                .margin(10)
+                .x_label_area_size(25)
+                .y_label_area_size(30)
+                .build_cartesian_2d(0..10, 0..10)
+                .unwrap();
+            chart.configure_mesh().disable_mesh().draw().unwrap();
+            chart
+                .draw_series(
+                    (1..10)
+                        .step_by(3)
+                        .map(|x| Circle::new((x, x), 5, RED.filled())),
+                )
+                .unwrap();
+        }
+
+        checked_save_file("test_debug_overlay", &content);
+        // The canvas background is not overlaid, drawn shapes and text are
+        assert!(!content.contains("rect(width: 300pt, height: 200pt"));
+        assert!(content
+            .contains("circle(radius: 5pt, fill: rgb(255, 0, 0), stroke: none))\n  #place(dx: "));
+        assert!(content.contains(
+            "#box(fill: rgb(255, 0, 255, 8%), stroke: 0.5pt + rgb(255, 0, 255, 60%))[Debug]"
+        ));
+        assert!(
+            content.contains("line(length: 6pt, angle: 90deg, stroke: 0.5pt + rgb(0, 160, 255))")
+        );
+    }
+
+    #[test]
+    fn test_preamble() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (200, 100))
+                .preamble("#set text(font: \"DejaVu Sans Mono\")")
+                .preamble("#let accent = rgb(0, 128, 255)\n")
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            root.draw(&Text::new("preamble", (10, 10), ("monospace", 20)))
+                .unwrap();
+        }
+
+        checked_save_file("test_preamble", &content);
+        assert!(content.starts_with(concat!(
+            "#set text(font: \"DejaVu Sans Mono\")\n",
+            "#let accent = rgb(0, 128, 255)\n",
+            "#box("
+        )));
+    }
+
+    #[test]
+    fn test_template() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (200, 100))
+                .template("#figure([{{chart}}], caption: [A red disc]) <disc>\n")
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            root.draw(&Circle::new((100, 50), 40, RED.filled()))
+                .unwrap();
+        }
+
+        checked_save_file("test_template", &content);
+        assert!(content.starts_with("#figure([#box(width: 200pt"));
+        assert!(content.ends_with("]\n], caption: [A red disc]) <disc>\n"));
+    }
+
+    #[test]
+    fn test_template_file() {
+        fs::create_dir_all(DST_DIR).unwrap();
+        let path = std::path::Path::new(DST_DIR).join("test_template_file.tmpl");
+        fs::write(&path, "= Report\n{{chart}}Done.\n").unwrap();
+
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (100, 50))
+                .template_file(&path)
+                .into_drawing_area();
+            root.fill(&BLUE).unwrap();
+        }
+        assert!(content.starts_with("= Report\n#box("));
+        assert!(content.ends_with("]\nDone.\n"));
+
+        let mut content = String::default();
+        let mut backend =
+            TypstBackend::with_string(&mut content, (100, 50)).template("no placeholder");
+        assert!(backend.present().is_err());
+    }
+
+    #[test]
+    fn test_standalone_page() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (200, 100))
+                .standalone()
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+        }
+        assert!(content.starts_with(
+            "#set page(width: auto, height: auto, margin: (x: 20pt, top: 20pt, bottom: 20pt))\n#box("
+        ));
+
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (300, 200))
+                .page_header("*Quarterly report* #h(1fr) Sales")
+                .page_footer("#align(center, context counter(page).display(\"1 / 1\", both: true))")
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            root.draw(&Rectangle::new(
+                [(20, 20), (280, 180)],
+                BLUE.mix(0.3).filled(),
+            ))
+            .unwrap();
+        }
+
+        checked_save_file("test_standalone_page", &content);
+        assert!(content.starts_with(concat!(
+            "#set page(width: auto, height: auto, margin: (x: 20pt, top: 48pt, bottom: 48pt), ",
+            "header: [*Quarterly report* #h(1fr) Sales], ",
+            "footer: [#align(center, context counter(page).display(\"1 / 1\", both: true))])\n",
+            "#box("
+        )));
+    }
+
+    #[test]
+    fn test_finish() {
+        let path = std::path::Path::new(DST_DIR).join("test_finish_unwritten.typ");
+        let _ = fs::remove_file(&path);
+        let mut backend = TypstBackend::new(&path, (100, 50)).preamble("// report");
+        backend
+            .draw_rect((10, 10), (90, 40), &RGBColor(0, 0, 255), true)
+            .unwrap();
+        let document = backend.finish().unwrap();
+        assert!(!path.exists());
+
+        let mut content = String::default();
+        {
+            let mut backend =
+                TypstBackend::with_string(&mut content, (100, 50)).preamble("// report");
+            backend
+                .draw_rect((10, 10), (90, 40), &RGBColor(0, 0, 255), true)
+                .unwrap();
+            assert_eq!(backend.finish().unwrap(), document);
+        }
+        assert!(content.is_empty());
+        checked_save_file("test_finish", &document);
+
+        let mut backend = TypstBackend::new(&path, (100, 50)).preamble("// report");
+        backend
+            .draw_rect((10, 10), (90, 40), &RGBColor(0, 0, 255), true)
+            .unwrap();
+        assert_eq!(backend.present_to_vec().unwrap(), document.as_bytes());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_new_buffered() {
+        fn chart() -> (DrawingArea<TypstBackend<'static>, Shift>, TypstOutput) {
+            let backend = TypstBackend::new_buffered((100, 50));
+            let output = backend.output().unwrap();
+            let root = backend.into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            root.draw(&Circle::new((50, 25), 20, RED.filled())).unwrap();
+            (root, output)
+        }
+
+        let (root, output) = chart();
+        assert!(output.contents().is_empty());
+        root.present().unwrap();
+        let content = output.take();
+        assert!(output.contents().is_empty());
+
+        let mut expected = String::default();
+        {
+            let root = TypstBackend::with_string(&mut expected, (100, 50)).into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            root.draw(&Circle::new((50, 25), 20, RED.filled())).unwrap();
+        }
+        assert_eq!(content, expected);
+        checked_save_file("test_new_buffered", &content);
+
+        let mut unused = String::default();
+        assert!(TypstBackend::with_string(&mut unused, (1, 1))
+            .output()
+            .is_none());
+    }
+
+    #[test]
+    fn test_writer_target() {
+        let mut bytes = Vec::new();
+        {
+            let root = TypstBackend::with_writer(&mut bytes, (100, 50)).into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            root.draw(&Rectangle::new([(10, 10), (90, 40)], GREEN.filled()))
+                .unwrap();
+        }
+
+        let mut expected = String::default();
+        {
+            let root = TypstBackend::with_string(&mut expected, (100, 50)).into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            root.draw(&Rectangle::new([(10, 10), (90, 40)], GREEN.filled()))
+                .unwrap();
+        }
+        assert_eq!(String::from_utf8(bytes).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_append_mode() {
+        fs::create_dir_all(DST_DIR).unwrap();
+        let path = std::path::Path::new(DST_DIR).join("test_append_mode.typ");
+        fs::write(&path, "= Figures").unwrap();
+
+        for color in [RED, GREEN, BLUE] {
+            let root = TypstBackend::new(&path, (60, 30))
+                .append()
+                .into_drawing_area();
+            root.fill(&color).unwrap();
+        }
+
+        let content = fs::read_to_string(&path).unwrap();
+        checked_save_file("test_append_mode", &content);
+        assert!(content.starts_with("= Figures\n\n#box("));
+        assert_eq!(content.matches("]\n\n#box(").count(), 2);
+        assert!(content.ends_with("]\n"));
+    }
+
+    #[cfg(feature = "plotters")]
+    #[test]
+    fn test_flush() {
+        fs::create_dir_all(DST_DIR).unwrap();
+        let path = std::path::Path::new(DST_DIR).join("test_flush.typ");
+        let _ = fs::remove_file(&path);
+
+        let mut backend =
+            TypstBackend::new(&path, (100, 50)).preamble("#let only(step, body) = body");
+        backend
+            .draw_rect((0, 0), (50, 50), &RGBColor(255, 0, 0), true)
+            .unwrap();
+        backend.begin_step("2-");
+        backend.flush().unwrap();
+        let partial = fs::read_to_string(&path).unwrap();
+        assert_eq!(partial.matches("rect(").count(), 1);
+        assert!(partial.ends_with("  ]\n]\n"));
+
+        backend
+            .draw_rect((50, 0), (100, 50), &RGBColor(0, 0, 255), true)
+            .unwrap();
+        backend.present().unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        checked_save_file("test_flush", &content);
+        assert_eq!(content.matches("rect(").count(), 2);
+        assert_eq!(content.matches("#only(\"2-\")[").count(), 1);
+
+        // Appending, a flush is replaced by the next write instead of repeated
+        fs::write(&path, "= Flushed\n").unwrap();
+        let mut backend = TypstBackend::new(&path, (100, 50)).append();
+        backend
+            .draw_rect((0, 0), (50, 50), &RGBColor(255, 0, 0), true)
+            .unwrap();
+        backend.flush().unwrap();
+        backend.flush().unwrap();
+        backend.present().unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("= Flushed\n\n#box("));
+        assert_eq!(content.matches("#box(").count(), 1);
+    }
+
+    #[test]
+    fn test_stroke_defaults() {
+        let draw = |backend: TypstBackend| {
+            let root = backend
+                .frame(&ShapeStyle::from(BLACK).stroke_width(2), 0)
+                .into_drawing_area();
+            root.draw(&PathElement::new(
+                vec![(10, 80), (50, 20), (90, 80)],
+                ShapeStyle::from(BLUE).stroke_width(6),
+            ))
+            .unwrap();
+            root.draw(&Rectangle::new(
+                [(110, 20), (190, 80)],
+                ShapeStyle::from(RED).stroke_width(3),
+            ))
+            .unwrap();
+        };
+
+        let mut content = String::default();
+        draw(
+            TypstBackend::with_string(&mut content, (200, 100))
+                .stroke_cap("round")
+                .stroke_join("round")
+                .stroke_miter_limit(2.0),
+        );
+        checked_save_file("test_stroke_defaults", &content);
+        assert!(content.starts_with(
+            "#let chart-stroke = (cap: \"round\", join: \"round\", miter-limit: 2)\n#box("
+        ));
+        assert!(content.contains("stroke: (..chart-stroke, thickness: 2pt, paint: rgb(0, 0, 0))"));
+        assert!(content.contains("stroke: (..chart-stroke, thickness: 6pt, paint: rgb(0, 0, 255))"));
+        assert!(content.contains("stroke: (..chart-stroke, thickness: 3pt, paint: rgb(255, 0, 0))"));
+
+        // The hashed namespace reaches the frame around the canvas as well
+        let mut content = String::default();
+        draw(
+            TypstBackend::with_string(&mut content, (200, 100))
+                .stroke_dash("dashed")
+                .hashed_namespace(),
+        );
+        checked_save_file("test_stroke_defaults_hashed", &content);
+        let name = &content[5..content.find(" = ").unwrap()];
+        assert!(name.ends_with("_chart-stroke"));
+        assert_eq!(content.matches(&format!("..{}, ", name)).count(), 4);
+        assert!(!content.contains(NAMESPACE_PLACEHOLDER));
+    }
+
+    #[test]
+    #[should_panic(expected = "stroke cap must be one of butt, round, square, got \"flat\"")]
+    fn test_stroke_cap_rejects_unknown() {
+        let _ = TypstBackend::new_buffered((100, 100)).stroke_cap("flat");
+    }
+
+    #[test]
+    #[should_panic(expected = "stroke dash must be a named Typst dash pattern, got \"dashed\\\"\"")]
+    fn test_stroke_dash_rejects_unknown() {
+        let _ = TypstBackend::new_buffered((100, 100)).stroke_dash("dashed\"");
+    }
+
+    #[test]
+    fn test_stroke_defaults_bulk() {
+        let mut content = String::default();
+        {
+            let mut backend =
+                TypstBackend::with_string(&mut content, (100, 100)).stroke_cap("round");
+            backend
+                .draw_circles([(20, 20), (50, 50), (80, 80)], 5, &RED, false)
+                .unwrap();
+            backend.present().unwrap();
+        }
+
+        checked_save_file("test_stroke_defaults_bulk", &content);
+        let stroke = content.find("#let chart-stroke = ").unwrap();
+        assert!(stroke < content.find("#let bulk-1 = ").unwrap());
+        #[cfg(feature = "test-util")]
+        crate::test_util::assert_compiles(&content);
+    }
+
+    #[test]
+    #[should_panic(expected = "stroke scale must be finite and positive, got inf")]
+    fn test_stroke_scale_rejects_infinity() {
+        let _ = TypstBackend::new_buffered((800, 600)).stroke_scale(f64::INFINITY);
+    }
+
+    #[test]
+    fn test_stroke_scale() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (200, 200))
+                .stroke_scale(0.25)
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            let mut chart = ChartBuilder::on(&root)
+                .margin(10)
+                .x_label_area_size(20)
+                .y_label_area_size(20)
+                .build_cartesian_2d(0..10, 0..10)
+                .unwrap();
+            chart.configure_mesh().draw().unwrap();
+            chart
+                .draw_series(LineSeries::new(
+                    (0..10).map(|x| (x, x)),
+                    ShapeStyle::from(BLUE).stroke_width(4),
+                ))
+                .unwrap();
+        }
+
+        checked_save_file("test_stroke_scale", &content);
+        assert!(content.contains("stroke: 0.25pt + rgb(0, 0, 0)"));
+        assert!(content.contains("stroke: 1pt + rgb(0, 0, 255)"));
+        assert!(!content.contains("stroke: 4pt"));
+    }
+
+    /// Center of the bounding box of the dark pixels of the rendered `content`
+    #[cfg(feature = "test-util")]
+    fn ink_center(content: &str) -> (f64, f64) {
+        let pixmap = crate::test_util::render(content);
+        let (l, t, r, b) = crate::test_util::ink_bounds(&pixmap).expect("nothing rendered");
+        ((l + r) as f64 / 2.0, (t + b) as f64 / 2.0)
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_rotation_origin() {
+        let anchor = (100, 100);
+        for (name, h_pos) in [
+            ("left", HPos::Left),
+            ("center", HPos::Center),
+            ("right", HPos::Right),
+        ] {
+            let mut centers = Vec::new();
+            for transform in [
+                FontTransform::None,
+                FontTransform::Rotate90,
+                FontTransform::Rotate180,
+                FontTransform::Rotate270,
+            ] {
+                let mut content = String::default();
+                {
+                    let mut backend = TypstBackend::with_string(&mut content, (200, 200));
+                    let style = TextStyle::from(("sans-serif", 30).into_font())
+                        .pos(Pos::new(h_pos, VPos::Top))
+                        .transform(transform);
+                    backend.draw_text("Wide label", &style, anchor).unwrap();
+                }
+                centers.push(ink_center(&content));
+            }
+
+            // Each quarter turn moves the ink around the anchor by a quarter turn
+            let (ax, ay) = (anchor.0 as f64, anchor.1 as f64);
+            let (dx, dy) = (centers[0].0 - ax, centers[0].1 - ay);
+            let expected = [(dx, dy), (-dy, dx), (-dx, -dy), (dy, -dx)];
+            for (center, (ex, ey)) in centers.iter().zip(expected) {
+                assert!(
+                    (center.0 - ax - ex).abs() <= 2.0 && (center.1 - ay - ey).abs() <= 2.0,
+                    "{}: ink centered at {:?}, expected {:?}",
+                    name,
+                    center,
+                    (ax + ex, ay + ey)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_vertical_centering() {
+        let draw = |h_pos, text: &str| {
+            let mut content = String::default();
+            {
+                let mut backend = TypstBackend::with_string(&mut content, (200, 100));
+                let style = TextStyle::from(("sans-serif", 30).into_font())
+                    .pos(Pos::new(h_pos, VPos::Center));
+                backend.draw_text(text, &style, (100, 50)).unwrap();
+            }
+            content
+        };
+
+        let content = draw(HPos::Right, "Ag");
+        checked_save_file("test_vertical_centering", &content);
+        assert!(content.contains("top-edge: \"bounds\", bottom-edge: \"bounds\""));
+        assert!(content.contains("#place(horizon + right, dx: -100pt, dy: 0pt, box["));
+
+        // The ink is centered on the anchor whatever the glyphs reach above and below
+        #[cfg(feature = "test-util")]
+        for text in ["Ag", "xo", "ÅÉ", "Typst\u{2082}"] {
+            let (x, y) = ink_center(&draw(HPos::Center, text));
+            assert!((x - 100.0).abs() <= 1.5, "{} centered at x = {}", text, x);
+            assert!((y - 50.0).abs() <= 1.5, "{} centered at y = {}", text, y);
+        }
+    }
+
+    /// Text anchors agree with the bitmap backend: every anchor and rotation of a
+    /// label is rendered by both backends and the bounding boxes of the ink compared.
+    /// Needs the `DejaVu Sans Mono` font installed, which Typst also bundles.
+    #[cfg(feature = "bitmap-parity")]
+    #[test]
+    fn test_bitmap_alignment_parity() {
+        const SIZE: (u32, u32) = (240, 240);
+        const ANCHOR: (i32, i32) = (120, 120);
+        const TOLERANCE: i64 = 4;
+
+        let mut failures = Vec::new();
+        for (h_name, h_pos) in [
+            ("left", HPos::Left),
+            ("center", HPos::Center),
+            ("right", HPos::Right),
+        ] {
+            for (v_name, v_pos) in [
+                ("top", VPos::Top),
+                ("center", VPos::Center),
+                ("bottom", VPos::Bottom),
+            ] {
+                for (angle, transform) in [
+                    (0, FontTransform::None),
+                    (90, FontTransform::Rotate90),
+                    (180, FontTransform::Rotate180),
+                    (270, FontTransform::Rotate270),
+                ] {
+                    let style = TextStyle::from(("DejaVu Sans Mono", 30).into_font())
+                        .pos(Pos::new(h_pos, v_pos))
+                        .transform(transform);
+
+                    let mut rgb = vec![255; (SIZE.0 * SIZE.1 * 3) as usize];
+                    {
+                        let mut backend = BitMapBackend::with_buffer(&mut rgb, SIZE);
+                        backend.draw_text("Typ", &style, ANCHOR).unwrap();
+                        backend.present().unwrap();
+                    }
+                    let bitmap = rgb
+                        .chunks_exact(3)
+                        .enumerate()
+                        .filter(|(_, p)| p.iter().map(|c| *c as u32).sum::<u32>() < 3 * 128)
+                        .map(|(i, _)| ((i as u32 % SIZE.0) as i64, (i as u32 / SIZE.0) as i64))
+                        .fold(None, |bounds: Option<[i64; 4]>, (x, y)| {
+                            let [l, t, r, b] = bounds.unwrap_or([x, y, x, y]);
+                            Some([l.min(x), t.min(y), r.max(x), b.max(y)])
+                        })
+                        .expect("the bitmap backend drew nothing");
+
+                    let mut content = String::default();
+                    {
+                        let mut backend = TypstBackend::with_string(&mut content, SIZE);
+                        backend.draw_text("Typ", &style, ANCHOR).unwrap();
+                    }
+                    let (l, t, r, b) =
+                        crate::test_util::ink_bounds(&crate::test_util::render(&content))
+                            .expect("Typst drew nothing");
+                    let typst = [l as i64, t as i64, r as i64, b as i64];
+
+                    if bitmap
+                        .iter()
+                        .zip(typst.iter())
+                        .any(|(a, b)| (a - b).abs() > TOLERANCE)
+                    {
+                        failures.push(format!(
+                            "{}/{} at {}deg: bitmap {:?}, typst {:?}",
+                            h_name, v_name, angle, bitmap, typst
+                        ));
+                    }
+                }
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn test_font_features() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (200, 200))
+                .tabular_figures()
+                .disable_ligatures()
+                .font_features(&["zero"])
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            let mut chart = ChartBuilder::on(&root)
+                .margin(10)
+                .y_label_area_size(40)
+                .x_label_area_size(20)
+                .caption("final figures", ("sans-serif", 14))
+                .build_cartesian_2d(0.0..1.0, 0.0..1000.0)
+                .unwrap();
+            chart.configure_mesh().draw().unwrap();
+        }
+
+        checked_save_file("test_font_features", &content);
+        assert!(content
+            .contains(", number-width: \"tabular\", ligatures: false, features: (\"zero\",))\n"));
+
+        let mut content = String::default();
+        {
+            let mut backend =
+                TypstBackend::with_string(&mut content, (100, 50)).font_features(&["smcp", "onum"]);
+            let style = TextStyle::from(("serif", 12).into_font());
+            backend.draw_text("Small caps", &style, (5, 5)).unwrap();
+        }
+        assert!(content.contains(", features: (\"smcp\", \"onum\"))\n"));
+
+        // Setting an option again does not repeat its argument, which Typst rejects
+        let mut content = String::default();
+        {
+            let mut backend = TypstBackend::with_string(&mut content, (100, 50))
+                .tabular_figures()
+                .disable_ligatures()
+                .font_features(&["smcp"])
+                .tabular_figures()
+                .disable_ligatures()
+                .font_features(&["onum", "smcp"]);
+            let style = TextStyle::from(("serif", 12).into_font());
+            backend.draw_text("Small caps", &style, (5, 5)).unwrap();
+        }
+        assert!(content.contains(
+            ", number-width: \"tabular\", ligatures: false, features: (\"smcp\", \"onum\"))\n"
+        ));
+        assert_eq!(content.matches("number-width").count(), 1);
+    }
+
+    #[test]
+    fn test_smart_punctuation() {
+        let draw = |backend: TypstBackend| {
+            let mut backend = backend;
+            let style = TextStyle::from(("serif", 12).into_font());
+            backend
+                .draw_text("\"A\"--'B' ... ~1 -2 #$", &style, (5, 5))
+                .unwrap();
+        };
+
+        let mut content = String::default();
+        draw(TypstBackend::with_string(&mut content, (200, 50)));
+        checked_save_file("test_smart_punctuation", &content);
+        assert!(content.contains(r#"\"A\"\--\'B\' \.\.. \~1 -2 \#\$]"#));
+
+        let mut content = String::default();
+        draw(TypstBackend::with_string(&mut content, (200, 50)).smart_punctuation());
+        checked_save_file("test_smart_punctuation_enabled", &content);
+        assert!(content.contains(r#""A"--'B' ... ~1 -2 \#\$]"#));
+    }
+
+    #[test]
+    fn test_paragraph_guard() {
+        let draw = |backend: TypstBackend| {
+            let root = backend.into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            root.draw(&Text::new(
+                "Incomprehensibilities of an extraordinarily long axis description",
+                (5, 20),
+                ("serif", 14).into_font(),
+            ))
+            .unwrap();
+        };
+
+        let mut content = String::default();
+        draw(TypstBackend::with_string(&mut content, (300, 50)));
+        checked_save_file(
+            "test_paragraph_guard",
+            &format!("#set par(justify: true)\n#set text(hyphenate: true)\n{content}"),
+        );
+        assert!(content.contains(")[\n  #set text(hyphenate: false)\n  #set par(justify: false)\n"));
+
+        let mut content = String::default();
+        draw(TypstBackend::with_string(&mut content, (300, 50)).inherit_paragraph_settings());
+        assert!(!content.contains("hyphenate"));
+        assert!(!content.contains("justify"));
+    }
+
+    #[test]
+    fn test_inherit_text_color() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (200, 100))
+                .inherit_text_color()
+                .into_drawing_area();
+            let mut chart = ChartBuilder::on(&root)
+                .margin(10)
+                .x_label_area_size(20)
+                .y_label_area_size(30)
+                .caption("dark theme", ("sans-serif", 14))
+                .build_cartesian_2d(0.0..1.0, 0.0..1.0)
+                .unwrap();
+            chart
+                .configure_mesh()
+                .axis_style(WHITE)
+                .bold_line_style(WHITE.mix(0.3))
+                .light_line_style(TRANSPARENT)
+                .draw()
+                .unwrap();
+            root.draw(&Text::new(
+                "red",
+                (150, 80),
+                ("sans-serif", 12).into_font().color(&RED),
+            ))
+            .unwrap();
+        }
+
+        checked_save_file(
+            "test_inherit_text_color",
+            &format!("#set page(fill: rgb(30, 30, 40))\n#set text(fill: white)\n{content}"),
+        );
+        assert!(!content.contains("fill: rgb(0, 0, 0)"));
+        assert!(content.contains("fill: rgb(255, 0, 0)"));
+    }
+
+    #[test]
+    fn test_hoisted_text_style() {
+        let mut content = String::default();
+        {
+            let mut backend = TypstBackend::with_string(&mut content, (200, 100));
+            let style = TextStyle::from(("serif", 12).into_font());
+            for (i, label) in ["one", "two", "three"].iter().enumerate() {
+                backend
+                    .draw_text(label, &style, (10, 10 + 20 * i as i32))
+                    .unwrap();
+            }
+            let bold =
+                TextStyle::from(("serif", 12).into_font().style(FontStyle::Bold)).color(&RED);
+            backend.draw_text("four", &bold, (100, 10)).unwrap();
+        }
+
+        checked_save_file("test_hoisted_text_style", &content);
+        assert_eq!(content.matches("#set text(size:").count(), 1);
+        assert!(content.contains(
+            "  #set text(size: 9.67741935483871pt, fill: rgb(0, 0, 0), weight: \"regular\", style: \"normal\", font: \"Liberation Serif\", top-edge: \"bounds\", bottom-edge: \"bounds\")\n"
+        ));
+        assert!(content.contains("box[two]"));
+        // Only the settings that differ are repeated
+        assert!(content.contains("box[#set text(fill: rgb(255, 0, 0), weight: \"bold\"); four]"));
+    }
+
+    #[test]
+    fn test_marker_instances() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (200, 200))
+                .namespace("scatter")
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            let mut chart = ChartBuilder::on(&root)
+                .margin(10)
+                .build_cartesian_2d(0.0..1.0, 0.0..1.0)
+                .unwrap();
+            chart
+                .draw_series((0..100).map(|i| {
+                    let x = (i as f64 * 0.618_034).fract();
+                    let y = (i as f64 * 0.754_878).fract();
+                    Circle::new((x, y), 2, BLUE.filled())
+                }))
+                .unwrap();
+            chart
+                .draw_series([(0.5, 0.5)].map(|p| Circle::new(p, 4, RED.filled())))
+                .unwrap();
+        }
+
+        checked_save_file("test_marker_instances", &content);
+        assert_eq!(
+            content.matches("#let scatter_marker-1 = circle(").count(),
+            1
+        );
+        assert_eq!(content.matches(", scatter_marker-1)").count(), 100);
+        // A shape placed a few times only is written inline
+        assert!(content.contains(", circle(radius: 4pt, fill: rgb(255, 0, 0)"));
+        assert!(!content.contains("marker-2"));
     }
 
-    #[cfg(all(not(target_arch = "wasm32"), feature = "image"))]
-    fn blit_bitmap(
-        &mut self,
-        pos: BackendCoord,
-        (w, h): (u32, u32),
-        src: &[u8],
-    ) -> Result<(), DrawingErrorKind<Self::ErrorType>> {
-        use image::codecs::png::PngEncoder;
-        use image::ImageEncoder;
-        use std::io::Cursor;
+    #[test]
+    fn test_push_clip() {
+        use plotters::element::{Drawable, PointCollection};
 
-        let mut data = vec![];
+        /// A disc of which only the upper half is drawn
+        struct HalfDisc((i32, i32));
+
+        impl<'b> PointCollection<'b, (i32, i32)> for &'b HalfDisc {
+            type Point = &'b (i32, i32);
+            type IntoIter = std::iter::Once<&'b (i32, i32)>;
+            fn point_iter(self) -> Self::IntoIter {
+                std::iter::once(&self.0)
+            }
+        }
+
+        impl Drawable<TypstBackend<'_>> for HalfDisc {
+            fn draw<I: Iterator<Item = BackendCoord>>(
+                &self,
+                mut points: I,
+                backend: &mut TypstBackend,
+                _: (u32, u32),
+            ) -> Result<(), DrawingErrorKind<Error>> {
+                let (x, y) = points.next().unwrap();
+                backend.push_clip((x - 20, y - 20), (x + 20, y));
+                backend.draw_circle((x, y), 20, &BLUE, true)?;
+                backend.pop_clip();
+                // Unbalanced pops are ignored
+                backend.pop_clip();
+                Ok(())
+            }
+        }
 
+        let mut content = String::default();
         {
-            let cursor = Cursor::new(&mut data);
-            let encoder = PngEncoder::new(cursor);
-            let color = image::ColorType::Rgb8;
+            let root = TypstBackend::with_string(&mut content, (100, 60)).into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            root.draw(&HalfDisc((50, 40))).unwrap();
+        }
 
-            encoder.write_image(src, w, h, color).map_err(|e| {
-                DrawingErrorKind::DrawingError(Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("Image error: {}", e),
-                ))
-            })?;
+        checked_save_file("test_push_clip", &content);
+        assert!(content.contains(
+            "  #place(dx: 30pt, dy: 20pt, box(width: 40pt, height: 20pt, clip: true, place(dx: -30pt, dy: -20pt, box(width: 100pt, height: 60pt)[\n  #place(dx: 30pt, dy: 20pt, circle("
+        ));
+        assert_eq!(content.matches("  ])))\n").count(), 1);
+
+        // A region left open is closed when presenting
+        let mut content = String::default();
+        {
+            let mut backend = TypstBackend::with_string(&mut content, (10, 10));
+            backend.push_clip((0, 0), (5, 5));
         }
+        assert!(content.ends_with("  ])))\n]\n"));
+    }
 
-        // Convert to base64
-        let base64_data = base64_encode(&data);
+    #[cfg(feature = "plotters")]
+    #[test]
+    fn test_push_clip_across_next_steps() {
+        let mut content = String::default();
+        {
+            let mut backend = TypstBackend::with_string(&mut content, (100, 60));
+            backend.push_clip((10, 10), (90, 50));
+            for x in [20, 50] {
+                backend.begin_next_step();
+                backend
+                    .draw_circle((x, 30), 25, &RGBColor(0, 0, 255), true)
+                    .unwrap();
+            }
+            backend.pop_clip();
+            backend
+                .draw_circle((80, 30), 25, &RGBColor(255, 0, 0), true)
+                .unwrap();
+        }
 
-        let cmd = format!(
-            "  #place(dx: {}pt, dy: {}pt, image.decode(\"data:image/png;base64,{}\", width: {}pt, height: {}pt))",
-            pos.0, pos.1, base64_data, w, h
-        );
-        self.write_command(&cmd);
-        Ok(())
+        assert_nested_groups(&content);
+        #[cfg(feature = "test-util")]
+        crate::test_util::assert_compiles(&format!("#let only(step, body) = body\n{}", content));
+        // Each automatic step reopens the region it was begun in
+        let clip = "  #place(dx: 10pt, dy: 10pt, box(width: 80pt, height: 40pt, clip: true";
+        assert_eq!(content.matches(clip).count(), 3);
+        assert!(content.contains(&format!("  ])))\n  ]\n  #only(\"3-\")[\n{}", clip)));
+        // The red circle drawn after the region is still in the last step
+        let red = content.find("rgb(255, 0, 0)").unwrap();
+        assert!(content[red..].starts_with("rgb(255, 0, 0), stroke: none))\n  ]\n"));
     }
-}
 
-#[cfg(all(not(target_arch = "wasm32"), feature = "image"))]
-fn base64_encode(data: &[u8]) -> String {
-    const BASE64_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    #[test]
+    fn test_grayscale() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (200, 100))
+                .grayscale()
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            for (i, color) in [RED, GREEN, BLUE].iter().enumerate() {
+                let x = 10 + 60 * i as i32;
+                root.draw(&Rectangle::new([(x, 10), (x + 50, 60)], color.filled()))
+                    .unwrap();
+            }
+            root.draw(&Text::new("gray", (10, 70), ("sans-serif", 14)))
+                .unwrap();
+            root.draw(&Circle::new((180, 80), 8, BLUE.mix(0.5).filled()))
+                .unwrap();
+        }
 
-    let mut result = String::new();
-    let mut i = 0;
+        checked_save_file("test_grayscale", &content);
+        assert!(!content.contains("rgb("));
+        assert!(content.contains("fill: luma(255)"));
+        assert!(content.contains("fill: luma(127)"));
+        assert!(content.contains("fill: luma(76, 50%)"));
+        assert!(content.contains("fill: luma(0)"));
+    }
 
-    while i + 2 < data.len() {
-        let b1 = data[i];
-        let b2 = data[i + 1];
-        let b3 = data[i + 2];
+    #[test]
+    fn test_colorblind_palette() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (300, 200))
+                .colorblind_palette(ColorblindPalette::OkabeIto)
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            let mut chart = ChartBuilder::on(&root)
+                .margin(10)
+                .x_label_area_size(20)
+                .y_label_area_size(30)
+                .build_cartesian_2d(0.0..3.0, 0.0..3.0)
+                .unwrap();
+            chart.configure_mesh().draw().unwrap();
+            for (i, color) in [RED, GREEN, BLUE].iter().enumerate() {
+                let offset = i as f64 * 0.5;
+                chart
+                    .draw_series(
+                        AreaSeries::new(
+                            (0..=3).map(|x| (x as f64, x as f64 / 3.0 + offset)),
+                            0.0,
+                            color.mix(0.2),
+                        )
+                        .border_style(ShapeStyle::from(color).stroke_width(2)),
+                    )
+                    .unwrap();
+            }
+        }
 
-        result.push(BASE64_CHARS[(b1 >> 2) as usize] as char);
-        result.push(BASE64_CHARS[(((b1 & 0x03) << 4) | (b2 >> 4)) as usize] as char);
-        result.push(BASE64_CHARS[(((b2 & 0x0F) << 2) | (b3 >> 6)) as usize] as char);
-        result.push(BASE64_CHARS[(b3 & 0x3F) as usize] as char);
+        checked_save_file("test_colorblind_palette", &content);
+        // Each color and its translucent fill take the same palette color
+        assert!(content.contains("fill: rgb(230, 159, 0, 20%)"));
+        assert!(content.contains("stroke: 2pt + rgb(230, 159, 0)"));
+        assert!(content.contains("stroke: 2pt + rgb(86, 180, 233)"));
+        assert!(content.contains("stroke: 2pt + rgb(0, 158, 115)"));
+        assert!(!content.contains("rgb(255, 0, 0"));
+        // The mesh stays gray
+        assert!(content.contains("rgb(0, 0, 0, 10%)"));
 
-        i += 3;
+        // Past the colors of the palette, they are reused with a warning
+        let mut backend =
+            TypstBackend::new_buffered((100, 50)).colorblind_palette(ColorblindPalette::OkabeIto);
+        for i in 0..9 {
+            let color = RGBColor(255, i * 20, 0);
+            backend
+                .draw_pixel((i as i32, 0), color.to_backend_color())
+                .unwrap();
+        }
+        let report = backend.present_report().unwrap();
+        assert_eq!(
+            report.warnings,
+            ["9 colors share the 7 colors of the colorblind palette"]
+        );
     }
 
-    // Handle remaining bytes
-    if i < data.len() {
-        let b1 = data[i];
-        result.push(BASE64_CHARS[(b1 >> 2) as usize] as char);
+    #[test]
+    fn test_color_serializer() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (200, 100))
+                .preamble("#let corp = (blue: rgb(\"#0055aa\"), ink: rgb(\"#222233\"))\n")
+                .color_serializer(|color| match color.rgb {
+                    (0, 0, 255) => "corp.blue".to_string(),
+                    (0, 0, 0) if color.alpha >= 1.0 => "corp.ink".to_string(),
+                    (r, g, b) => format!(
+                        "rgb({}, {}, {}).transparentize({}%)",
+                        r,
+                        g,
+                        b,
+                        (100.0 - color.alpha * 100.0).round()
+                    ),
+                })
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            root.draw(&Rectangle::new([(10, 10), (90, 60)], BLUE.filled()))
+                .unwrap();
+            root.draw(&Rectangle::new(
+                [(110, 10), (190, 60)],
+                RED.mix(0.5).filled(),
+            ))
+            .unwrap();
+            root.draw(&Text::new("brand", (10, 70), ("sans-serif", 14)))
+                .unwrap();
+        }
 
-        if i + 1 < data.len() {
-            let b2 = data[i + 1];
-            result.push(BASE64_CHARS[(((b1 & 0x03) << 4) | (b2 >> 4)) as usize] as char);
-            result.push(BASE64_CHARS[((b2 & 0x0F) << 2) as usize] as char);
-            result.push('=');
-        } else {
-            result.push(BASE64_CHARS[((b1 & 0x03) << 4) as usize] as char);
-            result.push_str("==");
+        checked_save_file("test_color_serializer", &content);
+        assert!(content.contains("fill: corp.blue"));
+        assert!(content.contains("fill: corp.ink"));
+        assert!(content.contains("fill: rgb(255, 0, 0).transparentize(50%)"));
+    }
+
+    /// The markup of `text` drawn as a label
+    #[cfg(feature = "test-util")]
+    fn label_markup(text: &str, smart_punctuation: bool) -> String {
+        let mut content = String::default();
+        {
+            let mut backend = TypstBackend::with_string(&mut content, (400, 50));
+            if smart_punctuation {
+                backend = backend.smart_punctuation();
+            }
+            let style = TextStyle::from(("serif", 12).into_font());
+            backend.draw_text(text, &style, (5, 5)).unwrap();
         }
+        content
     }
 
-    result
-}
+    #[cfg(feature = "test-util")]
+    proptest::proptest! {
+        /// Whatever a label says, the chart parses
+        #[test]
+        fn prop_label_syntax(text in proptest::prelude::any::<String>(), smart in proptest::prelude::any::<bool>()) {
+            let content = label_markup(&text, smart);
+            let root = typst::syntax::parse(&content);
+            proptest::prop_assert!(root.errors().is_empty(), "{:?} in {}", root.errors(), content);
+        }
+    }
 
-impl Drop for TypstBackend<'_> {
-    fn drop(&mut self) {
-        if !self.saved {
-            // drop should not panic, so we ignore a failed present
-            let _ = self.present();
+    #[cfg(feature = "test-util")]
+    proptest::proptest! {
+        #![proptest_config(proptest::prelude::ProptestConfig::with_cases(64))]
+
+        /// A label reads as typed, but for white space collapsing into single spaces
+        /// and hyphens before numbers that may turn into minus signs
+        #[test]
+        fn prop_label_round_trip(text in "[ -~äöüß€—–“”‘’…°±µ²\n\t]{0,24}") {
+            let expected = text.split_whitespace().collect::<Vec<_>>().join(" ");
+            let content = label_markup(&text, false);
+            let rendered = crate::test_util::rendered_text(&content);
+            let rendered = rendered.split_whitespace().collect::<Vec<_>>().join(" ");
+            proptest::prop_assert_eq!(rendered.replace('\u{2212}', "-"), expected, "markup: {}", content);
         }
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use plotters::prelude::*;
-    use plotters::style::text_anchor::{HPos, Pos, VPos};
-    use std::fs;
+    #[test]
+    fn test_overflow_visible() {
+        let draw = |backend: TypstBackend| {
+            let root = backend.into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            // Half of the title hangs over the left edge
+            let style = TextStyle::from(("sans-serif", 14).into_font())
+                .transform(FontTransform::Rotate270)
+                .pos(Pos::new(HPos::Center, VPos::Center));
+            root.draw_text("axis title", &style, (0, 50)).unwrap();
+        };
 
-    static DST_DIR: &str = "target/test/typst";
+        let mut content = String::default();
+        draw(TypstBackend::with_string(&mut content, (100, 100)));
+        assert!(content.starts_with("#box(width: 100pt, height: 100pt, clip: true"));
 
-    fn checked_save_file(name: &str, content: &str) {
-        /*
-          Please use the Typst file to manually verify the results.
-        */
-        assert!(!content.is_empty());
-        fs::create_dir_all(DST_DIR).unwrap();
-        let file_name = format!("{}.typ", name);
-        let file_path = std::path::Path::new(DST_DIR).join(file_name);
-        println!("{:?} created", file_path);
-        fs::write(file_path, &content).unwrap();
+        let mut content = String::default();
+        draw(TypstBackend::with_string(&mut content, (100, 100)).overflow_visible());
+        checked_save_file(
+            "test_overflow_visible",
+            &format!("#pad(left: 20pt)[\n{content}]\n"),
+        );
+        assert!(content.starts_with("#box(width: 100pt, height: 100pt, clip: false"));
     }
 
-    fn draw_mesh_with_custom_ticks(tick_size: i32, test_name: &str) {
-        let mut content: String = Default::default();
+    #[test]
+    fn test_canvas_padding() {
+        let mut content = String::default();
         {
-            let root = TypstBackend::with_string(&mut content, (500, 500)).into_drawing_area();
+            let root = TypstBackend::with_string(&mut content, (100, 60))
+                .padding(5)
+                .baseline(60)
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            // A frame right on the edges of the canvas, half of its stroke outside
+            root.draw(&Rectangle::new(
+                [(0, 0), (99, 59)],
+                ShapeStyle::from(BLUE).stroke_width(4),
+            ))
+            .unwrap();
+            root.draw(&Text::new("padded", (50, 30), ("sans-serif", 14)))
+                .unwrap();
+        }
 
-            let mut chart = ChartBuilder::on(&root)
-                .caption("This is a test", ("sans-serif", 20u32))
-                .set_all_label_area_size(40u32)
-                .build_cartesian_2d(0..10, 0..10)
+        checked_save_file("test_canvas_padding", &content);
+        assert!(content.starts_with(
+            "#box(width: 110pt, height: 70pt, clip: true, baseline: 5pt, inset: 5pt, fill: rgb(255, 255, 255)"
+        ));
+        // The drawing keeps its coordinates
+        assert!(content.contains("#place(dx: 0pt, dy: 0pt, rect(width: 99pt, height: 59pt"));
+    }
+
+    #[test]
+    fn test_auto_crop() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (200, 200))
+                .auto_crop()
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            root.draw(&Rectangle::new(
+                [(40, 50), (120, 90)],
+                ShapeStyle::from(BLUE).stroke_width(2),
+            ))
+            .unwrap();
+            root.draw(&Circle::new((120, 90), 10, RED.filled()))
                 .unwrap();
+        }
 
-            chart
-                .configure_mesh()
-                .set_all_tick_mark_size(tick_size)
-                .draw()
+        checked_save_file("test_auto_crop", &content);
+        // The background is not content, the stroke of the frame is
+        // The labels may overflow the cropped canvas, the full one clips
+        assert!(content.starts_with("#box(width: 91pt, height: 51pt, clip: false"));
+        assert!(content.contains(
+            "  #place(dx: -39pt, dy: -49pt, box(width: 200pt, height: 200pt, clip: true)[\n"
+        ));
+        assert!(content.ends_with("  ])\n]\n"));
+    }
+
+    #[test]
+    fn test_content_bbox() {
+        let mut content = String::default();
+        let mut backend = TypstBackend::with_string(&mut content, (100, 100));
+        assert_eq!(backend.content_bbox(), None);
+
+        // The background does not count
+        backend
+            .draw_rect((0, 0), (99, 99), &WHITE.filled(), true)
+            .unwrap();
+        assert_eq!(backend.content_bbox(), None);
+
+        backend
+            .draw_line((10, 20), (50, 20), &ShapeStyle::from(BLACK).stroke_width(4))
+            .unwrap();
+        assert_eq!(backend.content_bbox(), Some((8, 18, 52, 22)));
+
+        // Content past the edges is reported as is
+        backend
+            .draw_circle((95, 60), 10, &RED.filled(), true)
+            .unwrap();
+        assert_eq!(backend.content_bbox(), Some((8, 18, 105, 70)));
+    }
+
+    #[cfg(feature = "plotters")]
+    #[test]
+    fn test_manifest() {
+        let path = std::path::Path::new(DST_DIR).join("test_manifest.typ");
+        fs::create_dir_all(DST_DIR).unwrap();
+        {
+            let root = TypstBackend::new(&path, (100, 80))
+                .manifest()
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            root.draw(&Text::new("Rain \"mm\"", (10, 5), ("sans-serif", 12)))
+                .unwrap();
+            root.draw(&crate::element::TypstSection::series()).unwrap();
+            root.draw(&Rectangle::new([(20, 30), (40, 70)], BLUE.filled()))
                 .unwrap();
+            root.draw(&Rectangle::new([(50, 40), (70, 70)], BLUE.filled()))
+                .unwrap();
+            root.present().unwrap();
         }
 
-        checked_save_file(test_name, &content);
+        let manifest = fs::read_to_string(path.with_extension("json")).unwrap();
+        assert_eq!(
+            manifest,
+            concat!(
+                "{\n",
+                "  \"size\": [100, 80],\n",
+                "  \"bbox\": [9, 4, 70, 70],\n",
+                "  \"elements\": {\"rect\": 2, \"text\": 1},\n",
+                "  \"series\": [\n",
+                "    {\"name\": \"series 1\", \"elements\": {\"rect\": 2}, \"bbox\": [20, 30, 70, 70]}\n",
+                "  ],\n",
+                "  \"labels\": [\n",
+                "    {\"text\": \"Rain \\\"mm\\\"\", \"bbox\": [10, 5, 62, 15]}\n",
+                "  ]\n",
+                "}\n",
+            )
+        );
+        assert_eq!(TypstBackend::new_buffered((10, 10)).manifest_json(), None);
+    }
 
-        assert!(content.contains("This is a test"));
+    #[test]
+    fn test_source_map() {
+        let mut content = String::default();
+        let mut backend = TypstBackend::with_string(&mut content, (100, 60)).source_map_comments();
+        backend
+            .draw_rect((10, 10), (90, 50), &BLUE.filled(), true)
+            .unwrap();
+        backend
+            .draw_path([(10, 10), (50, 50), (90, 10)], &BLACK)
+            .unwrap();
+        backend.present().unwrap();
+
+        let map = backend.source_map().to_vec();
+        assert_eq!(
+            map,
+            [
+                (
+                    4..6,
+                    "draw_rect((10, 10), (90, 50), fill: true)".to_string()
+                ),
+                (6..9, "draw_path(3 points)".to_string()),
+            ]
+        );
+        assert_eq!(backend.source_call(8), Some("draw_path(3 points)"));
+        assert_eq!(backend.source_call(1), None);
+        drop(backend);
+
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(
+            lines[3],
+            "  // source: draw_rect((10, 10), (90, 50), fill: true)"
+        );
+        assert!(lines[4].starts_with("  #place(dx: 10pt, dy: 10pt, rect("));
+        assert_eq!(lines[5], "  // source: draw_path(3 points)");
+        assert!(lines[7].starts_with("  #place(dx: 50pt, dy: 50pt, line("));
     }
 
     #[test]
-    fn test_draw_mesh_no_ticks() {
-        draw_mesh_with_custom_ticks(0, "test_draw_mesh_no_ticks");
+    fn test_command_log() {
+        let mut content = String::default();
+        let mut log = Vec::new();
+        {
+            let root = TypstBackend::with_string(&mut content, (100, 60))
+                .command_log(&mut log)
+                .into_drawing_area();
+            root.draw(&Rectangle::new([(10, 10), (90, 40)], BLUE.filled()))
+                .unwrap();
+            root.draw(&Text::new("a \"b\"", (5, 50), ("sans-serif", 12)))
+                .unwrap();
+            root.draw(&PathElement::new(
+                [(0, 0), (20, 20)],
+                ShapeStyle::from(RED).stroke_width(2),
+            ))
+            .unwrap();
+            root.present().unwrap();
+        }
+
+        assert_eq!(
+            String::from_utf8(log).unwrap(),
+            concat!(
+                "{\"shape\": \"rect\", \"from\": [10, 10], \"to\": [90, 40], \"color\": [0, 0, 255, 1], \"stroke_width\": 1, \"filled\": true}\n",
+                "{\"shape\": \"text\", \"text\": \"a \\\"b\\\"\", \"at\": [5, 50], \"color\": [0, 0, 0, 1], \"font\": \"sans-serif\", \"size\": 12, \"angle\": 0}\n",
+                "{\"shape\": \"line\", \"from\": [0, 0], \"to\": [20, 20], \"color\": [255, 0, 0, 1], \"stroke_width\": 2}\n",
+            )
+        );
     }
 
     #[test]
-    fn test_draw_mesh_negative_ticks() {
-        draw_mesh_with_custom_ticks(-10, "test_draw_mesh_negative_ticks");
+    fn test_reproducible() {
+        let draw = |backend: TypstBackend| {
+            let mut backend = backend.hashed_namespace().stroke_cap("round");
+            backend
+                .draw_line((0, 0), (30, 70), &ShapeStyle::from(BLACK).stroke_width(1))
+                .unwrap();
+            backend
+                .draw_rect((10, 10), (20, 20), &BLUE.filled(), true)
+                .unwrap();
+            backend.finish().unwrap()
+        };
+
+        let document = draw(TypstBackend::new_buffered((100, 100)).reproducible());
+        assert!(document.contains("line(length: 76.1577pt, angle: 66.8014deg"));
+        // The hash is fixed, unlike the one of the standard library
+        assert_eq!(
+            document.lines().next(),
+            Some("#let c12ef1d42_chart-stroke = (cap: \"round\")")
+        );
+        assert_eq!(
+            document,
+            draw(TypstBackend::new_buffered((100, 100)).reproducible())
+        );
+        assert_eq!(stable_hash(&["plotters"]), 0x04d9_7913_e996_9372);
+
+        let document = draw(TypstBackend::new_buffered((100, 100)));
+        assert!(document.contains("line(length: 76.15773105863909pt"));
     }
 
     #[test]
-    fn test_text_alignments() {
-        let mut content: String = Default::default();
+    fn test_strict_fonts() {
+        let mut content = String::default();
         {
-            let mut root = TypstBackend::with_string(&mut content, (500, 500));
+            // Setting the option twice, e.g. in a config and in code, sets it once
+            let mut backend = TypstBackend::with_string(&mut content, (100, 50))
+                .strict_fonts()
+                .strict_fonts();
+            let style = TextStyle::from(("serif", 12).into_font());
+            backend.draw_text("Ünïcode", &style, (10, 10)).unwrap();
+            backend.present().unwrap();
+        }
+
+        checked_save_file("test_strict_fonts", &content);
+        assert!(content.contains(", fallback: false)"));
+        assert_eq!(content.matches("fallback").count(), 1);
+    }
+
+    #[test]
+    fn test_figure_caption() {
+        let mut content = String::default();
+        {
+            let root = TypstBackend::with_string(&mut content, (200, 150))
+                .figure_caption("Sales *2024*")
+                .figure_label("fig:sales")
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            let mut chart = ChartBuilder::on(&root)
+                .margin(10)
+                .build_cartesian_2d(0.0..1.0, 0.0..1.0)
+                .unwrap();
+            chart.configure_mesh().draw().unwrap();
+        }
+
+        checked_save_file("test_figure_caption", &content);
+        assert!(content.starts_with("#figure([\n#box(width: 200pt"));
+        assert!(content.ends_with("], caption: [Sales \\*2024\\*]) <fig:sales>\n"));
+        assert_eq!(content.matches("Sales").count(), 1);
+    }
 
+    #[test]
+    fn test_figure_label() {
+        let draw = |backend: TypstBackend, caption: &str| {
+            let mut backend = backend.figure_caption(caption);
+            // A title is drawn like any other text
             let style = TextStyle::from(("sans-serif", 20).into_font())
-                .pos(Pos::new(HPos::Right, VPos::Top));
-            root.draw_text("right-align", &style, (150, 50)).unwrap();
+                .pos(Pos::new(HPos::Center, VPos::Top));
+            backend.draw_text("Title", &style, (50, 5)).unwrap();
+            backend.finish_figure().unwrap()
+        };
 
-            let style = style.pos(Pos::new(HPos::Center, VPos::Top));
-            root.draw_text("center-align", &style, (150, 150)).unwrap();
+        let (first, first_label) = draw(TypstBackend::new_buffered((100, 50)), "Costs");
+        let first_label = first_label.unwrap();
+        assert_eq!(first_label, "fig:chart-23b72582");
+        assert!(first.ends_with(&format!("], caption: [Costs]) <{}>\n", first_label)));
+        assert!(first.contains("[Title]"));
+        // The label depends on the chart only, not on the charts drawn before
+        let (_, second_label) = draw(TypstBackend::new_buffered((100, 50)), "Costs");
+        assert_eq!(second_label.as_ref(), Some(&first_label));
+        let (_, other_label) = draw(TypstBackend::new_buffered((100, 50)), "Prices");
+        assert_ne!(other_label.as_ref(), Some(&first_label));
+        let (_, named_label) = draw(
+            TypstBackend::new_buffered((100, 50)).namespace("costs"),
+            "Costs",
+        );
+        assert_eq!(named_label.as_deref(), Some("fig:costs"));
+        // A given label keeps only the characters Typst allows in one
+        let (named, given_label) = draw(
+            TypstBackend::new_buffered((100, 50)).figure_label("fig:q1 costs>"),
+            "Costs",
+        );
+        assert_eq!(given_label.as_deref(), Some("fig:q1_costs_"));
+        assert!(named.ends_with("]) <fig:q1_costs_>\n"));
+        #[cfg(feature = "test-util")]
+        crate::test_util::assert_compiles(&named);
+
+        let mut backend = TypstBackend::new_buffered((100, 50));
+        backend.present().unwrap();
+        assert_eq!(backend.label(), None);
+    }
+
+    #[cfg(feature = "plotters")]
+    #[test]
+    fn test_split_series() {
+        use crate::element::{TypstReveal, TypstSection};
 
-            let style = style.pos(Pos::new(HPos::Left, VPos::Top));
-            root.draw_text("left-align", &style, (150, 200)).unwrap();
+        let dir = std::path::Path::new(DST_DIR).join("split_series");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("chart.typ");
+        {
+            let root = TypstBackend::new(&path, (200, 100))
+                .preamble("#let only(step, body) = body")
+                .split_series()
+                .into_drawing_area();
+            root.fill(&WHITE).unwrap();
+            for (i, color) in [RED, BLUE].into_iter().enumerate() {
+                root.draw(&TypstSection::series()).unwrap();
+                root.draw(&TypstReveal::begin(format!("{}-", i + 2)))
+                    .unwrap();
+                root.draw(&Circle::new((50 + 50 * i as i32, 50), 10, color.filled()))
+                    .unwrap();
+                root.draw(&TypstReveal::end()).unwrap();
+            }
+            root.draw(&TypstSection::new("notes")).unwrap();
+            root.draw(&Text::new("note", (10, 10), ("sans-serif", 12)))
+                .unwrap();
+            root.present().unwrap();
         }
 
-        checked_save_file("test_text_alignments", &content);
-
-        assert!(content.contains("right-align"));
-        assert!(content.contains("center-align"));
-        assert!(content.contains("left-align"));
-        // Right and center aligned text will have measure() calls
-        assert!(content.contains("measure("));
+        let main = std::fs::read_to_string(&path).unwrap();
+        let first = std::fs::read_to_string(dir.join("chart-series-1.typ")).unwrap();
+        let second = std::fs::read_to_string(dir.join("chart-series-2.typ")).unwrap();
+        assert!(main.contains("  // section: series 1\n  #include \"chart-series-1.typ\"\n"));
+        assert!(main.contains("  #include \"chart-series-2.typ\"\n  // section: notes\n"));
+        assert!(main.contains("[note]"));
+        assert!(first.contains("rgb(255, 0, 0)") && !main.contains("rgb(255, 0, 0)"));
+        assert!(second.contains("rgb(0, 0, 255)"));
+        // Each series file defines what its commands use
+        assert!(second.starts_with("#let only(step, body) = body\n"));
+        #[cfg(feature = "test-util")]
+        crate::test_util::assert_compiles(&second);
+        // Included files are resolved relative to the working directory
+        let include = format!("#include \"{}/", dir.display());
+        checked_save_file("test_split_series", &main.replace("#include \"", &include));
     }
 
+    #[cfg(feature = "plotters")]
     #[test]
-    fn test_text_draw() {
-        let mut content: String = Default::default();
+    fn test_overlay() {
+        let mut content = String::default();
         {
-            let root = TypstBackend::with_string(&mut content, (1500, 800)).into_drawing_area();
-            let root = root
-                .titled("Image Title", ("sans-serif", 60).into_font())
+            let root = TypstBackend::with_string(&mut content, (200, 100))
+                .overlay(|canvas| {
+                    let (width, height) = canvas.size();
+                    format!(
+                        "  #place(dx: {}, dy: {}, text(red)[DRAFT])",
+                        canvas.len(width as f64 / 2.0),
+                        canvas.len(height as f64 / 2.0)
+                    )
+                })
+                .overlay(|canvas| match canvas.coord(5.0, 50.0) {
+                    Some((dx, dy)) => format!("  #place(dx: {}, dy: {})[+]\n", dx, dy),
+                    None => String::new(),
+                })
+                .into_drawing_area();
+            let chart = ChartBuilder::on(&root)
+                .build_cartesian_2d(0.0..10.0, 0.0..100.0)
                 .unwrap();
-
-            let mut chart = ChartBuilder::on(&root)
-                .caption("All anchor point positions", ("sans-serif", 20u32))
-                .set_all_label_area_size(40u32)
-                .build_cartesian_2d(0..100i32, 0..50i32)
+            chart
+                .plotting_area()
+                .draw(&crate::element::TypstCoordMap::new(
+                    (0.0, 0.0),
+                    (10.0, 100.0),
+                ))
                 .unwrap();
-
             chart
-                .configure_mesh()
-                .disable_x_mesh()
-                .disable_y_mesh()
-                .x_desc("X Axis")
-                .y_desc("Y Axis")
-                .draw()
+                .plotting_area()
+                .draw(&Rectangle::new([(0.0, 0.0), (10.0, 100.0)], BLUE.filled()))
+                .unwrap();
+            root.draw(&Text::new("label", (10, 10), ("serif", 20)))
                 .unwrap();
-
-            let ((x1, y1), (x2, y2), (x3, y3)) = ((-30, 30), (0, -30), (30, 30));
-
-            for (dy, trans) in [
-                FontTransform::None,
-                FontTransform::Rotate90,
-                FontTransform::Rotate180,
-                FontTransform::Rotate270,
-            ]
-            .iter()
-            .enumerate()
-            {
-                for (dx1, h_pos) in [HPos::Left, HPos::Right, HPos::Center].iter().enumerate() {
-                    for (dx2, v_pos) in [VPos::Top, VPos::Center, VPos::Bottom].iter().enumerate() {
-                        let x = 150_i32 + (dx1 as i32 * 3 + dx2 as i32) * 150;
-                        let y = 120 + dy as i32 * 150;
-                        let draw = |x, y, text| {
-                            root.draw(&Circle::new((x, y), 3, &BLACK.mix(0.5))).unwrap();
-                            let style = TextStyle::from(("sans-serif", 20).into_font())
-                                .pos(Pos::new(*h_pos, *v_pos))
-                                .transform(trans.clone());
-                            root.draw_text(text, &style, (x, y)).unwrap();
-                        };
-                        draw(x + x1, y + y1, "dood");
-                        draw(x + x2, y + y2, "dog");
-                        draw(x + x3, y + y3, "goog");
-                    }
-                }
-            }
         }
 
-        checked_save_file("test_text_draw", &content);
-
-        // Text appears twice for center/right aligned text (once in measure, once displayed)
-        // So we expect more than 36 occurrences
-        assert!(content.matches("dog").count() >= 36);
-        assert!(content.matches("dood").count() >= 36);
-        assert!(content.matches("goog").count() >= 36);
+        checked_save_file("test_overlay", &content);
+        // The text settings of the labels do not apply to the overlays
+        assert!(content.ends_with(
+            "  ]\n  #place(dx: 100pt, dy: 50pt, text(red)[DRAFT])\n  #place(dx: 99.5pt, dy: 49.5pt)[+]\n]\n"
+        ));
+        let labels = content.find("  #[\n  #set text(size: ").unwrap();
+        assert!(labels < content.find("[label]").unwrap());
     }
 
     #[test]
-    fn test_text_clipping() {
-        let mut content: String = Default::default();
+    fn test_watermark() {
+        let mut content = String::default();
         {
-            let (width, height) = (500_i32, 500_i32);
-            let root = TypstBackend::with_string(&mut content, (width as u32, height as u32))
+            let root = TypstBackend::with_string(&mut content, (200, 100))
+                .underlay(|canvas| {
+                    format!("  #place(rect(width: {}, fill: yellow))", canvas.len(50.0))
+                })
+                .watermark("DRAFT #2", -30.0, 0.15)
                 .into_drawing_area();
-
-            let style = TextStyle::from(("sans-serif", 20).into_font())
-                .pos(Pos::new(HPos::Center, VPos::Center));
-            root.draw_text("TOP LEFT", &style, (0, 0)).unwrap();
-            root.draw_text("TOP CENTER", &style, (width / 2, 0))
+            root.draw(&Circle::new((100, 50), 20, BLUE.filled()))
                 .unwrap();
-            root.draw_text("TOP RIGHT", &style, (width, 0)).unwrap();
+        }
 
-            root.draw_text("MIDDLE LEFT", &style, (0, height / 2))
+        checked_save_file("test_watermark", &content);
+        let underlay = content
+            .find("  #place(rect(width: 50pt, fill: yellow))\n")
+            .unwrap();
+        let watermark = content
+            .find("  #place(center + horizon, rotate(-30deg, text(size: 20pt, weight: \"bold\", fill: rgb(128, 128, 128, 15%))[DRAFT \\#2]))\n")
+            .unwrap();
+        let circle = content.find("circle(").unwrap();
+        assert!(underlay < watermark && watermark < circle);
+    }
+
+    #[test]
+    fn test_write_number() {
+        let mut values = vec![
+            0.0,
+            1.0,
+            -12.0,
+            0.5,
+            1.0 / 3.0,
+            2e-5,
+            1e-7,
+            123456.789,
+            1e15,
+            1e20,
+        ];
+        values.extend((1..2000).map(|i| f64::from(i).sqrt() * 0.37 - 8.0));
+        values.extend((1..2000).map(|i| f64::from(i).atan2(7.0).to_degrees()));
+        for value in values {
+            let mut written = String::new();
+            write_number(&mut written, value).unwrap();
+            assert_eq!(written, value.to_string());
+        }
+    }
+
+    #[test]
+    fn test_compact() {
+        let draw = |backend: TypstBackend| {
+            let mut backend = backend;
+            backend
+                .draw_rect((0, 10), (40, 30), &RED.filled(), true)
                 .unwrap();
-            root.draw_text("MIDDLE RIGHT", &style, (width, height / 2))
+            backend.draw_rect((0, 0), (40, 30), &BLUE, false).unwrap();
+            backend.draw_line((0, 0), (50, 0), &BLACK).unwrap();
+            backend
+                .fill_polygon(vec![(0, 0), (10, 0), (5, 8)], &GREEN)
                 .unwrap();
+            backend.finish().unwrap()
+        };
 
-            root.draw_text("BOTTOM LEFT", &style, (0, height)).unwrap();
-            root.draw_text("BOTTOM CENTER", &style, (width / 2, height))
+        let full = draw(TypstBackend::new_buffered((60, 40)));
+        let compact = draw(TypstBackend::new_buffered((60, 40)).compact());
+        checked_save_file("test_compact", &compact);
+        assert!(compact.len() < full.len());
+        assert!(compact.contains(
+            "  #place(dy: 10pt, rect(width: 40pt, height: 20pt, fill: rgb(255, 0, 0)))\n"
+        ));
+        assert!(compact
+            .contains("  #place(rect(width: 40pt, height: 30pt, stroke: 1pt + rgb(0, 0, 255)))\n"));
+        assert!(compact.contains("  #place(line(length: 50pt, stroke: 1pt + rgb(0, 0, 0)))\n"));
+        assert!(compact.contains("  #place(polygon(fill: rgb(0, 255, 0), (0pt, 0pt),"));
+        #[cfg(feature = "test-util")]
+        assert_eq!(
+            crate::test_util::render(&compact),
+            crate::test_util::render(&full)
+        );
+    }
+
+    #[test]
+    fn test_reset() {
+        fs::create_dir_all("target/test/typst").unwrap();
+        let mut backend = TypstBackend::new_buffered((100, 50));
+        let output = backend.output().unwrap();
+        let draw = |backend: &mut TypstBackend, color: &RGBColor| {
+            backend
+                .draw_text("a", &("serif", 12).into_font().color(color), (10, 10))
                 .unwrap();
-            root.draw_text("BOTTOM RIGHT", &style, (width, height))
+            backend
+                .draw_rect((10, 10), (40, 40), &color.filled(), true)
                 .unwrap();
-        }
+            backend.present().unwrap();
+        };
 
-        checked_save_file("test_text_clipping", &content);
+        draw(&mut backend, &RED);
+        let first = output.take();
+        backend.reset();
+        draw(&mut backend, &BLUE);
+        let second = output.take();
+        assert_eq!(second, first.replace("rgb(255, 0, 0)", "rgb(0, 0, 255)"));
+
+        // A discarded chart is not written, the next one goes to the new file
+        let path = "target/test/typst/test_reset.typ";
+        backend
+            .draw_rect((0, 0), (5, 5), &RED.filled(), true)
+            .unwrap();
+        backend.reset_to(path);
+        backend.reset_size((200, 100));
+        drop(backend);
+        assert!(output.take().is_empty());
+        assert!(fs::read_to_string(path)
+            .unwrap()
+            .starts_with("#box(width: 200pt, height: 100pt"));
     }
 
     #[test]
-    fn test_series_labels() {
-        let mut content = String::default();
-        {
-            let (width, height) = (500, 500);
-            let root = TypstBackend::with_string(&mut content, (width, height)).into_drawing_area();
-
+    fn test_batch_histograms() {
+        let draw = |backend: TypstBackend| {
+            let output = backend.output().unwrap();
+            let root = backend.into_drawing_area();
             let mut chart = ChartBuilder::on(&root)
-                .caption("All series label positions", ("sans-serif", 20u32))
-                .set_all_label_area_size(40u32)
-                .build_cartesian_2d(0..50i32, 0..50i32)
+                .margin(10)
+                .build_cartesian_2d((0u32..40u32).into_segmented(), 0u32..12u32)
+                .unwrap();
+            for (color, step) in [(BLUE.mix(1.0), 7), (RED.mix(0.5), 3)] {
+                chart
+                    .draw_series(
+                        Histogram::vertical(&chart)
+                            .style(color.filled())
+                            .margin(1)
+                            .data((0..200u32).map(|i| ((i * step) % 40, 1))),
+                    )
+                    .unwrap();
+            }
+            root.draw(&Rectangle::new([(10, 150), (30, 170)], GREEN.filled()))
                 .unwrap();
+            root.present().unwrap();
+            output.take()
+        };
 
-            chart
-                .configure_mesh()
-                .disable_x_mesh()
-                .disable_y_mesh()
-                .draw()
+        let batched = draw(TypstBackend::new_buffered((300, 200)).batch_histograms());
+        checked_save_file("test_batch_histograms", &batched);
+        assert_eq!(batched.matches("#place(polygon(fill: ").count(), 2);
+        assert_eq!(batched.matches("rect(").count(), 1);
+        // The 40 bars of a series, in any order, are traced by four vertices each
+        let blue = batched
+            .lines()
+            .find(|line| line.starts_with("  #place(polygon(fill: rgb(0, 0, 255), stroke: none, ("))
+            .unwrap();
+        assert_eq!(blue.matches("pt), (").count() + 1, 160);
+
+        let single = draw(TypstBackend::new_buffered((300, 200)));
+        assert_eq!(single.matches("rect(").count(), 81);
+        #[cfg(feature = "test-util")]
+        assert_eq!(
+            crate::test_util::render(&batched),
+            crate::test_util::render(&single)
+        );
+    }
+
+    #[test]
+    fn test_batch_histograms_interleaved() {
+        let draw = |backend: TypstBackend| {
+            let output = backend.output().unwrap();
+            let root = backend.into_drawing_area();
+            root.draw(&Rectangle::new([(10, 20), (30, 80)], RED.filled()))
                 .unwrap();
+            root.draw(&Rectangle::new(
+                [(0, 0), (60, 60)],
+                ShapeStyle::from(&BLACK).stroke_width(2),
+            ))
+            .unwrap();
+            root.draw(&Rectangle::new([(40, 40), (70, 80)], RED.filled()))
+                .unwrap();
+            root.present().unwrap();
+            output.take()
+        };
 
-            chart
-                .draw_series(std::iter::once(Circle::new((5, 15), 5u32, &RED)))
-                .expect("Drawing error")
-                .label("Series 1")
-                .legend(|(x, y)| Circle::new((x, y), 3u32, RED.filled()));
+        let batched = draw(TypstBackend::new_buffered((100, 100)).batch_histograms());
+        checked_save_file("test_batch_histograms_interleaved", &batched);
+        // The outlined rect in between keeps the bars apart
+        assert_eq!(batched.matches("rect(").count(), 3);
+        assert!(!batched.contains("polygon("));
+        let single = draw(TypstBackend::new_buffered((100, 100)));
+        assert_eq!(batched, single);
+    }
 
-            chart
-                .draw_series(std::iter::once(Circle::new((5, 15), 10u32, &BLUE)))
-                .expect("Drawing error")
-                .label("Series 2")
-                .legend(|(x, y)| Circle::new((x, y), 3u32, BLUE.filled()));
+    #[test]
+    fn test_decimate_lines() {
+        assert_eq!(
+            decimate_min_max(vec![
+                (0, 5),
+                (1, 5),
+                (1, 9),
+                (1, 2),
+                (1, 7),
+                (1, 3),
+                (1, 6),
+                (2, 0)
+            ]),
+            [(0, 5), (1, 5), (1, 9), (1, 2), (1, 6), (2, 0)]
+        );
 
-            for pos in vec![
-                SeriesLabelPosition::UpperLeft,
-                SeriesLabelPosition::MiddleLeft,
-                SeriesLabelPosition::LowerLeft,
-                SeriesLabelPosition::UpperMiddle,
-                SeriesLabelPosition::MiddleMiddle,
-                SeriesLabelPosition::LowerMiddle,
-                SeriesLabelPosition::UpperRight,
-                SeriesLabelPosition::MiddleRight,
-                SeriesLabelPosition::LowerRight,
-                SeriesLabelPosition::Coordinate(70, 70),
-            ]
-            .into_iter()
-            {
-                chart
-                    .configure_series_labels()
-                    .border_style(&BLACK.mix(0.5))
-                    .position(pos)
-                    .draw()
-                    .expect("Drawing error");
-            }
-        }
+        // A noisy signal of 100 samples per column
+        let signal: Vec<_> = (0..18_000)
+            .map(|i| {
+                let t = f64::from(i) / 100.0;
+                let noise = ((i * 7919) % 23) as f64 - 11.0;
+                (
+                    10 + i / 100,
+                    (60.0 + 30.0 * (t / 20.0).sin() + noise) as i32,
+                )
+            })
+            .collect();
+        let draw = |backend: TypstBackend| {
+            let output = backend.output().unwrap();
+            let root = backend.into_drawing_area();
+            root.draw(&PathElement::new(signal.clone(), BLUE)).unwrap();
+            root.present().unwrap();
+            output.take()
+        };
+        let decimated = draw(TypstBackend::new_buffered((200, 120)).decimate_lines());
+        checked_save_file("test_decimate_lines", &decimated);
+        let lines = decimated.matches("line(").count();
+        assert!(lines > 180 && lines <= 180 * 4, "{} lines", lines);
 
-        checked_save_file("test_series_labels", &content);
+        let full = draw(TypstBackend::new_buffered((200, 120)));
+        assert!(full.matches("line(").count() > 10_000);
+        #[cfg(feature = "test-util")]
+        {
+            use crate::test_util::{ink_bounds, render};
+            let (decimated, full) = (render(&decimated), render(&full));
+            assert_eq!(ink_bounds(&decimated), ink_bounds(&full));
+            let differing = decimated
+                .pixels()
+                .iter()
+                .zip(full.pixels())
+                .filter(|(a, b)| a.alpha().abs_diff(b.alpha()) > 64)
+                .count();
+            assert!(differing < 50, "{} pixels differ", differing);
+        }
     }
 
     #[test]
-    fn test_draw_pixel_alphas() {
-        let mut content = String::default();
+    fn test_size_budget() {
+        let mut backend = TypstBackend::new_buffered((100, 100)).primitive_budget(3);
+        for x in 0..3 {
+            backend
+                .draw_pixel((x, 0), BLACK.to_backend_color())
+                .unwrap();
+        }
+        let error = backend
+            .draw_line((0, 0), (10, 10), &ShapeStyle::from(BLACK))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Drawing backend error: the chart exceeds its size budget with 4 primitives of at most 3; decimate or rasterize its dense series"
+        );
+
+        let mut backend = TypstBackend::new_buffered((100, 100)).size_budget(2000);
+        let mut drawn = 0;
+        while backend
+            .draw_pixel((drawn, 0), BLACK.to_backend_color())
+            .is_ok()
         {
-            let (width, height) = (100_i32, 100_i32);
-            let root = TypstBackend::with_string(&mut content, (width as u32, height as u32))
-                .into_drawing_area();
-            root.fill(&WHITE).unwrap();
+            drawn += 1;
+        }
+        assert!(drawn > 10 && drawn < 30);
+        let DrawingErrorKind::DrawingError(error) = backend
+            .draw_pixel((0, 1), BLACK.to_backend_color())
+            .unwrap_err()
+        else {
+            unreachable!()
+        };
+        assert_eq!(error.kind(), std::io::ErrorKind::FileTooLarge);
 
-            for i in -20..20 {
-                let alpha = i as f64 * 0.1;
-                root.draw_pixel((50 + i, 50 + i), &BLACK.mix(alpha))
+        // The markup around the commands counts when presenting
+        let mut backend = TypstBackend::new_buffered((100, 100)).size_budget(150);
+        backend
+            .draw_pixel((0, 0), BLACK.to_backend_color())
+            .unwrap();
+        assert!(backend.present().is_err());
+        assert!(TypstBackend::new_buffered((100, 100))
+            .size_budget(150)
+            .finish()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_on_progress() {
+        let mut reports = Vec::new();
+        {
+            let mut backend = TypstBackend::new_buffered((100, 100))
+                .on_progress(4, |progress| reports.push(progress));
+            for x in 0..10 {
+                backend
+                    .draw_pixel((x, 0), BLACK.to_backend_color())
                     .unwrap();
             }
         }
-
-        checked_save_file("test_draw_pixel_alphas", &content);
+        assert_eq!(
+            reports.iter().map(|p| p.primitives).collect::<Vec<_>>(),
+            [4, 8]
+        );
+        assert!(reports[0].bytes > 0 && reports[1].bytes > reports[0].bytes);
     }
 
     #[test]
-    fn test_simple_drawing() {
-        let mut content: String = Default::default();
+    fn test_cancel_on() {
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut content = String::new();
         {
-            let mut backend = TypstBackend::with_string(&mut content, (500, 500));
-
-            // Draw a simple rectangle
+            let mut backend =
+                TypstBackend::with_string(&mut content, (100, 100)).cancel_on(flag.clone());
             backend
-                .draw_rect((10, 10), (100, 100), &RGBColor(255, 0, 0), true)
+                .draw_pixel((0, 0), BLACK.to_backend_color())
                 .unwrap();
+            flag.store(true, Ordering::Relaxed);
+            let error = backend
+                .draw_pixel((1, 0), BLACK.to_backend_color())
+                .unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "Drawing backend error: the chart was cancelled after 2 primitives, its output is incomplete"
+            );
+            let DrawingErrorKind::DrawingError(error) = backend.present().unwrap_err() else {
+                unreachable!()
+            };
+            assert_eq!(error.kind(), std::io::ErrorKind::Interrupted);
+        }
+        assert!(content.is_empty());
+    }
 
-            backend.present().unwrap();
+    #[test]
+    fn test_present_report() {
+        let path = std::path::Path::new(DST_DIR).join("test_present_report.typ");
+        fs::create_dir_all(DST_DIR).unwrap();
+        let mut backend = TypstBackend::new(&path, (100, 80));
+        backend
+            .draw_rect((10, 10), (120, 40), &ShapeStyle::from(BLUE), true)
+            .unwrap();
+        for x in [10, 20] {
+            backend
+                .draw_line((x, 50), (x, 70), &ShapeStyle::from(BLACK))
+                .unwrap();
         }
+        let report = backend.present_report().unwrap();
+        assert_eq!(report.path.as_deref(), Some(path.as_path()));
+        assert_eq!(report.bytes, fs::read(&path).unwrap().len());
+        assert_eq!(report.primitives, 3);
+        assert_eq!(report.elements, BTreeMap::from([("line", 2), ("rect", 1)]));
+        assert_eq!(
+            report.warnings,
+            ["content drawn from (9, 10) to (120, 71) is cut off by the canvas of 100x80"]
+        );
 
-        checked_save_file("test_simple_drawing", &content);
-        assert!(content.contains("rect"));
-        assert!(content.contains("rgb(255, 0, 0)"));
+        let mut content = String::new();
+        let report = TypstBackend::with_string(&mut content, (10, 10))
+            .present_report()
+            .unwrap();
+        assert_eq!(
+            (report.path, report.warnings),
+            (None, vec!["nothing was drawn".to_string()])
+        );
+        assert_eq!(report.bytes, content.len());
     }
 
     #[test]
-    fn test_draw_line() {
-        let mut content = String::default();
-        {
-            let mut backend = TypstBackend::with_string(&mut content, (300, 300));
+    fn test_include_snippet() {
+        let path = std::path::Path::new("out/figures/chart.typ");
+        let backend = TypstBackend::new(path, (100, 80));
+        assert_eq!(
+            backend.include_snippet().unwrap(),
+            "#include \"out/figures/chart.typ\"\n"
+        );
+        assert_eq!(
+            backend.include_snippet_for("out/main.typ").unwrap(),
+            "#include \"figures/chart.typ\"\n"
+        );
+        assert_eq!(
+            backend.include_snippet_for("out/slides/main.typ").unwrap(),
+            "#include \"../figures/chart.typ\"\n"
+        );
+
+        let backend = TypstBackend::new(path, (100, 80))
+            .named_anchors()
+            .namespace("sales");
+        assert_eq!(
+            backend.include_snippet().unwrap(),
+            "#import \"out/figures/chart.typ\": sales_chart_anchors\n#include \"out/figures/chart.typ\"\n"
+        );
+        let backend = TypstBackend::new(path, (100, 80)).split_layers();
+        assert_eq!(
+            backend.include_snippet().unwrap(),
+            "#import \"out/figures/chart.typ\": chart_frame, chart_data\n"
+        );
+
+        assert_eq!(TypstBackend::new_buffered((10, 10)).include_snippet(), None);
+
+        // A leading `/` stands for the root of the Typst project, not of the disk
+        let backend = TypstBackend::new(std::path::Path::new("/tmp/chart.typ"), (100, 80));
+        assert_eq!(backend.include_snippet(), None);
+        assert_eq!(backend.include_snippet_for("out/main.typ"), None);
+        assert_eq!(
+            backend.include_snippet_for("/tmp/report/main.typ").unwrap(),
+            "#include \"../chart.typ\"\n"
+        );
+        assert_eq!(
+            TypstBackend::new(path, (100, 80)).include_snippet_for("../main.typ"),
+            None
+        );
+    }
 
+    #[test]
+    fn test_float_safe() {
+        let draw = |backend: TypstBackend| {
+            let output = backend.output().unwrap();
+            // Aligned in the cropped canvas
+            let mut backend = backend.auto_crop().underlay(|_| {
+                "  #place(center + horizon, circle(radius: 5pt, fill: green))".to_string()
+            });
             backend
-                .draw_line((10, 10), (100, 100), &RGBColor(0, 255, 0))
+                .draw_rect((20, 20), (180, 120), &ShapeStyle::from(BLUE), false)
                 .unwrap();
-
+            backend.push_clip((40, 40), (100, 100));
+            backend
+                .draw_circle((100, 100), 30, &ShapeStyle::from(RED), true)
+                .unwrap();
+            backend.pop_clip();
+            for (x, pos) in [
+                (100, Pos::new(HPos::Left, VPos::Top)),
+                (150, Pos::new(HPos::Center, VPos::Center)),
+                (180, Pos::new(HPos::Right, VPos::Bottom)),
+            ] {
+                let style = TextStyle::from(("sans-serif", 12).into_font()).pos(pos);
+                backend.draw_text("label", &style, (x, 60)).unwrap();
+            }
             backend.present().unwrap();
-        }
+            output.take()
+        };
+        let placed = draw(TypstBackend::new_buffered((200, 150)));
+        let moved = draw(TypstBackend::new_buffered((200, 150)).float_safe());
+        checked_save_file("test_float_safe", &moved);
+        // The same commands, with `place` shadowed for the cropped canvas and
+        // rebound in the full canvas within and in the clip region
+        let start = moved
+            .find("  #let place(size: (162pt, 111pt), ..args) = {\n")
+            .unwrap();
+        let end = start + moved[start..].find("\n  }\n").unwrap() + 5;
+        let rebound = "  #let place = place.with(size: (200pt, 150pt))\n";
+        assert_eq!(moved.matches(rebound).count(), 2);
+        assert_eq!(
+            format!("{}{}", &moved[..start], &moved[end..]).replace(rebound, ""),
+            placed
+        );
 
-        checked_save_file("test_draw_line", &content);
-        assert!(content.contains("line"));
-        assert!(content.contains("rgb(0, 255, 0)"));
+        #[cfg(feature = "test-util")]
+        {
+            use crate::test_util::{assert_compiles, render};
+            assert_eq!(render(&moved), render(&placed));
+            assert_compiles(&format!(
+                "#figure(placement: top, [\n{}])\n#table(columns: 2, [a], [\n{}])",
+                moved, moved
+            ));
+        }
     }
 
     #[test]
-    fn test_draw_circle() {
+    fn test_draw_path_merges_collinear() {
         let mut content = String::default();
         {
             let mut backend = TypstBackend::with_string(&mut content, (300, 300));
 
-            // Filled circle
-            backend
-                .draw_circle((150, 150), 50, &RGBColor(0, 0, 255), true)
-                .unwrap();
+            // A step: three collinear points, a turn, then a repeated point
+            let points = vec![(10, 10), (20, 10), (30, 10), (30, 40), (30, 40), (30, 50)];
+            backend.draw_path(points, &RGBColor(0, 0, 0)).unwrap();
 
             backend.present().unwrap();
         }
 
-        checked_save_file("test_draw_circle", &content);
-        assert!(content.contains("circle"));
-        assert!(content.contains("rgb(0, 0, 255)"));
+        checked_save_file("test_draw_path_merges_collinear", &content);
+        assert_eq!(content.matches("line(").count(), 2);
+        assert!(content.contains("line(length: 20pt, angle: 0deg"));
+        assert!(content.contains("line(length: 40pt, angle: 90deg"));
     }
 
     #[test]
-    fn test_draw_polygon() {
+    fn test_draw_path_keeps_reversal() {
         let mut content = String::default();
         {
             let mut backend = TypstBackend::with_string(&mut content, (300, 300));
 
-            let points = vec![(50, 50), (100, 50), (75, 100)];
-            backend
-                .fill_polygon(points, &RGBColor(255, 128, 0))
-                .unwrap();
-
-            backend.present().unwrap();
+            let points = vec![(10, 10), (50, 10), (30, 10)];
+            backend.draw_path(points, &RGBColor(0, 0, 0)).unwrap();
         }
 
-        checked_save_file("test_draw_polygon", &content);
-        assert!(content.contains("polygon"));
-        assert!(content.contains("rgb(255, 128, 0)"));
+        assert_eq!(content.matches("line(").count(), 2);
     }
 }
-
@@ -0,0 +1,231 @@
+//! The machine-readable descriptions of a chart: the manifest written by
+//! [`TypstBackend::manifest`](crate::TypstBackend::manifest) and the entries of
+//! [`TypstBackend::command_log`](crate::TypstBackend::command_log)
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+
+use plotters_backend::{BackendColor, BackendCoord};
+
+/// A box `(left, top, right, bottom)` in backend pixels
+pub(crate) type Bounds = (i32, i32, i32, i32);
+
+/// The smallest box covering `bounds` and `other`
+pub(crate) fn grow(bounds: Option<Bounds>, other: Bounds) -> Bounds {
+    match bounds {
+        Some((l, t, r, b)) => (
+            l.min(other.0),
+            t.min(other.1),
+            r.max(other.2),
+            b.max(other.3),
+        ),
+        None => other,
+    }
+}
+
+/// The elements drawn after a section mark, e.g. one series
+#[derive(Default)]
+struct Group {
+    name: String,
+    elements: BTreeMap<&'static str, usize>,
+    bounds: Option<Bounds>,
+}
+
+/// What was drawn, collected while drawing
+#[derive(Default)]
+pub(crate) struct Manifest {
+    elements: BTreeMap<&'static str, usize>,
+    groups: Vec<Group>,
+    labels: Vec<(String, Bounds)>,
+    /// The series that may be rasterized, in raster fallback mode
+    rasterized: Option<Vec<String>>,
+}
+
+impl Manifest {
+    /// Start the group of the elements drawn after the section mark `name`
+    #[cfg(feature = "plotters")]
+    pub(crate) fn begin_group(&mut self, name: &str) {
+        self.groups.push(Group {
+            name: name.to_string(),
+            ..Group::default()
+        });
+    }
+
+    /// Count an element of `kind`, such as `"line"`
+    pub(crate) fn element(&mut self, kind: &'static str) {
+        *self.elements.entry(kind).or_default() += 1;
+        if let Some(group) = self.groups.last_mut() {
+            *group.elements.entry(kind).or_default() += 1;
+        }
+    }
+
+    /// Grow the box of the current group to cover `bounds`
+    pub(crate) fn include(&mut self, bounds: Bounds) {
+        if let Some(group) = self.groups.last_mut() {
+            group.bounds = Some(grow(group.bounds, bounds));
+        }
+    }
+
+    /// Record the label `text`, estimated to cover `bounds`
+    pub(crate) fn label(&mut self, text: &str, bounds: Bounds) {
+        self.labels.push((text.to_string(), bounds));
+    }
+
+    /// Record which series were rasterized, see
+    /// [`TypstBackend::raster_report`](crate::TypstBackend::raster_report)
+    #[cfg(feature = "image")]
+    pub(crate) fn rasterized(&mut self, report: &[crate::raster::RasterReport]) {
+        let series = report
+            .iter()
+            .map(|series| {
+                format!(
+                    "    {{\"name\": {}, \"primitives\": {}, \"rasterized\": {}, \"pixels\": [{}, {}], \"dpi\": {}, \"bytes\": {}}}",
+                    json_string(&series.name),
+                    series.primitives,
+                    series.rasterized,
+                    series.pixels.0,
+                    series.pixels.1,
+                    series.dpi,
+                    series.bytes
+                )
+            })
+            .collect();
+        self.rasterized = Some(series);
+    }
+
+    /// The manifest as a JSON object, for a canvas of `size` with everything drawn
+    /// inside `bounds`
+    pub(crate) fn to_json(&self, size: (u32, u32), bounds: Option<Bounds>) -> String {
+        let mut json = String::new();
+        writeln!(json, "{{").unwrap();
+        writeln!(json, "  \"size\": [{}, {}],", size.0, size.1).unwrap();
+        writeln!(json, "  \"bbox\": {},", json_bounds(bounds)).unwrap();
+        writeln!(json, "  \"elements\": {},", json_counts(&self.elements)).unwrap();
+        let groups = self
+            .groups
+            .iter()
+            .map(|group| {
+                format!(
+                    "    {{\"name\": {}, \"elements\": {}, \"bbox\": {}}}",
+                    json_string(&group.name),
+                    json_counts(&group.elements),
+                    json_bounds(group.bounds)
+                )
+            })
+            .collect::<Vec<_>>();
+        writeln!(json, "  \"series\": {},", json_array(&groups)).unwrap();
+        if let Some(rasterized) = &self.rasterized {
+            writeln!(json, "  \"rasterized\": {},", json_array(rasterized)).unwrap();
+        }
+        let labels = self
+            .labels
+            .iter()
+            .map(|(text, bounds)| {
+                format!(
+                    "    {{\"text\": {}, \"bbox\": {}}}",
+                    json_string(text),
+                    json_bounds(Some(*bounds))
+                )
+            })
+            .collect::<Vec<_>>();
+        writeln!(json, "  \"labels\": {}", json_array(&labels)).unwrap();
+        json.push_str("}\n");
+        json
+    }
+}
+
+/// A JSON object describing an emitted command, written field by field
+pub(crate) struct JsonObject(String);
+
+impl JsonObject {
+    /// Start the object of a command drawing a `shape`, such as `"rect"`
+    pub(crate) fn new(shape: &str) -> Self {
+        Self(format!("{{\"shape\": {}", json_string(shape)))
+    }
+
+    fn field(mut self, key: &str, value: fmt::Arguments) -> Self {
+        write!(self.0, ", \"{}\": {}", key, value).unwrap();
+        self
+    }
+
+    pub(crate) fn point(self, key: &str, (x, y): BackendCoord) -> Self {
+        self.field(key, format_args!("[{}, {}]", x, y))
+    }
+
+    pub(crate) fn points(self, key: &str, points: &[BackendCoord]) -> Self {
+        let points = points
+            .iter()
+            .map(|(x, y)| format!("[{}, {}]", x, y))
+            .collect::<Vec<_>>();
+        self.field(key, format_args!("[{}]", points.join(", ")))
+    }
+
+    pub(crate) fn number<T: fmt::Display>(self, key: &str, value: T) -> Self {
+        self.field(key, format_args!("{}", value))
+    }
+
+    pub(crate) fn flag(self, key: &str, value: bool) -> Self {
+        self.field(key, format_args!("{}", value))
+    }
+
+    pub(crate) fn string(self, key: &str, value: &str) -> Self {
+        self.field(key, format_args!("{}", json_string(value)))
+    }
+
+    #[cfg(feature = "plotters")]
+    pub(crate) fn strings<'s, I: IntoIterator<Item = &'s str>>(self, key: &str, values: I) -> Self {
+        let values = values.into_iter().map(json_string).collect::<Vec<_>>();
+        self.field(key, format_args!("[{}]", values.join(", ")))
+    }
+
+    /// A color as `[red, green, blue, alpha]`
+    pub(crate) fn color(self, key: &str, color: BackendColor) -> Self {
+        let (r, g, b) = color.rgb;
+        self.field(key, format_args!("[{}, {}, {}, {}]", r, g, b, color.alpha))
+    }
+}
+
+impl fmt::Display for JsonObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}}}", self.0)
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn json_bounds(bounds: Option<Bounds>) -> String {
+    match bounds {
+        Some((l, t, r, b)) => format!("[{}, {}, {}, {}]", l, t, r, b),
+        None => "null".to_string(),
+    }
+}
+
+fn json_counts(counts: &BTreeMap<&'static str, usize>) -> String {
+    let entries = counts
+        .iter()
+        .map(|(kind, count)| format!("\"{}\": {}", kind, count))
+        .collect::<Vec<_>>();
+    format!("{{{}}}", entries.join(", "))
+}
+
+fn json_array(items: &[String]) -> String {
+    if items.is_empty() {
+        "[]".to_string()
+    } else {
+        format!("[\n{}\n  ]", items.join(",\n"))
+    }
+}
@@ -0,0 +1,196 @@
+/*!
+Local Typst packages of generated charts
+
+A [`LocalPackage`] turns a directory of generated `.typ` charts into a Typst
+package: it writes a `typst.toml` manifest and a `lib.typ` entrypoint with one
+function per chart. Written into [`local_package_dir`], the charts can be used
+from any document:
+
+```typst
+#import "@local/mycharts:0.1.0": sales
+#figure(sales(), caption: [Sales])
+```
+*/
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::typst_identifier;
+
+/// Name of the entrypoint written next to the manifest
+pub const ENTRYPOINT: &str = "lib.typ";
+
+/// A Typst package exposing generated charts as functions
+#[derive(Clone, Debug)]
+pub struct LocalPackage {
+    name: String,
+    version: String,
+    charts: Vec<(String, String)>,
+}
+
+impl LocalPackage {
+    /// Create a package without charts; `version` must be a `major.minor.patch` triple
+    pub fn new(name: &str, version: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            version: version.to_string(),
+            charts: Vec::new(),
+        }
+    }
+
+    /// Expose the chart in `file`, relative to the package root, as the function `name`
+    pub fn chart(mut self, name: &str, file: &str) -> Self {
+        self.charts
+            .push((typst_identifier(name), file.replace('\\', "/")));
+        self
+    }
+
+    /// Expose every `.typ` file in `dir` as a function named after the file
+    ///
+    /// The files are sorted by name; `lib.typ` itself is skipped.
+    pub fn charts_from_dir<P: AsRef<Path>>(mut self, dir: P) -> io::Result<Self> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "typ") {
+                if let Some(file) = path.file_name().and_then(|f| f.to_str()) {
+                    if file != ENTRYPOINT {
+                        files.push(file.to_string());
+                    }
+                }
+            }
+        }
+        files.sort();
+        for file in files {
+            let name = file.trim_end_matches(".typ").to_string();
+            self = self.chart(&name, &file);
+        }
+        Ok(self)
+    }
+
+    /// The `typst.toml` manifest
+    pub fn manifest(&self) -> String {
+        format!(
+            "[package]\nname = \"{}\"\nversion = \"{}\"\nentrypoint = \"{}\"\n",
+            escape_string(&self.name),
+            escape_string(&self.version),
+            ENTRYPOINT
+        )
+    }
+
+    /// The `lib.typ` entrypoint, one function per chart
+    pub fn entrypoint(&self) -> String {
+        let mut lib = String::new();
+        for (name, file) in &self.charts {
+            lib.push_str(&format!(
+                "#let {}() = include \"{}\"\n",
+                name,
+                escape_string(file)
+            ));
+        }
+        lib
+    }
+
+    /// Write the manifest and the entrypoint into `dir`, creating it if needed
+    ///
+    /// The chart files themselves are expected to be in `dir` already.
+    pub fn write<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join("typst.toml"), self.manifest())?;
+        fs::write(dir.join(ENTRYPOINT), self.entrypoint())
+    }
+
+    /// Write the package into the local package directory, see [`local_package_dir`]
+    ///
+    /// Returns the directory the package was written to.
+    pub fn install(&self) -> io::Result<PathBuf> {
+        let dir = local_package_dir(&self.name, &self.version)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no local package directory"))?;
+        self.write(&dir)?;
+        Ok(dir)
+    }
+}
+
+/// Escape `text` for a double quoted string in TOML or Typst
+fn escape_string(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Directory Typst resolves `@local/<name>:<version>` to
+///
+/// This is `typst/packages/local/<name>/<version>` in the platform data
+/// directory, or `None` if the directory cannot be determined.
+pub fn local_package_dir(name: &str, version: &str) -> Option<PathBuf> {
+    data_dir().map(|dir| {
+        dir.join("typst")
+            .join("packages")
+            .join("local")
+            .join(name)
+            .join(version)
+    })
+}
+
+fn data_dir() -> Option<PathBuf> {
+    let env = |name| {
+        std::env::var_os(name)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    };
+    if cfg!(windows) {
+        env("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        env("HOME").map(|home| home.join("Library").join("Application Support"))
+    } else {
+        env("XDG_DATA_HOME").or_else(|| env("HOME").map(|home| home.join(".local").join("share")))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LocalPackage;
+    use crate::test::checked_save_file;
+    use std::fs;
+
+    const PACKAGE_DIR: &str = "target/test/typst/package";
+
+    #[test]
+    fn test_local_package() {
+        fs::create_dir_all(PACKAGE_DIR).unwrap();
+        fs::write(
+            format!("{PACKAGE_DIR}/bars.typ"),
+            "#box(width: 40pt, height: 20pt, fill: blue)\n",
+        )
+        .unwrap();
+        fs::write(
+            format!("{PACKAGE_DIR}/2024 sales.typ"),
+            "#box(width: 20pt, height: 20pt, fill: red)\n",
+        )
+        .unwrap();
+
+        let package = LocalPackage::new("mycharts", "0.1.0")
+            .charts_from_dir(PACKAGE_DIR)
+            .unwrap();
+        package.write(PACKAGE_DIR).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(format!("{PACKAGE_DIR}/typst.toml")).unwrap(),
+            "[package]\nname = \"mycharts\"\nversion = \"0.1.0\"\nentrypoint = \"lib.typ\"\n"
+        );
+        assert_eq!(
+            fs::read_to_string(format!("{PACKAGE_DIR}/lib.typ")).unwrap(),
+            concat!(
+                "#let _2024_sales() = include \"2024 sales.typ\"\n",
+                "#let bars() = include \"bars.typ\"\n",
+            )
+        );
+
+        checked_save_file(
+            "test_local_package",
+            &format!(
+                "#import \"/{PACKAGE_DIR}/lib.typ\": bars, _2024_sales\n#bars() #_2024_sales()\n"
+            ),
+        );
+    }
+}
@@ -0,0 +1,169 @@
+/*!
+Pooled backends for services rendering many charts
+
+A [`TypstPool`] is shared by the worker threads of a service. Each worker takes a
+backend set up the same way, draws a chart, and hands the backend back: the
+buffer of its drawing commands returns to the pool for the next chart, and the
+finished document is returned or collected under a name:
+
+```
+use plotters_typst::pool::TypstPool;
+
+let pool = TypstPool::new().setup(|backend| backend.reproducible().compact());
+std::thread::scope(|scope| {
+    for worker in 0..4 {
+        let pool = &pool;
+        scope.spawn(move || {
+            let backend = pool.backend((320, 200));
+            pool.collect(&format!("chart-{}", worker), backend).unwrap();
+        });
+    }
+});
+assert_eq!(pool.take_outputs().len(), 4);
+```
+
+Backends are not `Send`: they are created on the worker thread, and only their
+buffers and outputs cross threads.
+*/
+
+use std::io::Error;
+use std::sync::Mutex;
+
+use crate::TypstBackend;
+
+/// Sets up every backend handed out, see [`TypstPool::setup`]
+type Setup = dyn Fn(TypstBackend<'static>) -> TypstBackend<'static> + Send + Sync;
+
+/// Hands out backends with reused buffers to worker threads and collects their
+/// documents
+pub struct TypstPool {
+    setup: Option<Box<Setup>>,
+    capacity: usize,
+    buffers: Mutex<Vec<String>>,
+    outputs: Mutex<Vec<(String, String)>>,
+}
+
+impl Default for TypstPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TypstPool {
+    /// Create a pool handing out backends with the default settings
+    pub fn new() -> Self {
+        Self {
+            setup: None,
+            capacity: 0,
+            buffers: Mutex::default(),
+            outputs: Mutex::default(),
+        }
+    }
+
+    /// Apply `setup`, a chain of builder methods, to every backend handed out
+    pub fn setup<F>(mut self, setup: F) -> Self
+    where
+        F: Fn(TypstBackend<'static>) -> TypstBackend<'static> + Send + Sync + 'static,
+    {
+        self.setup = Some(Box::new(setup));
+        self
+    }
+
+    /// Allocate room for `bytes` bytes of drawing commands in a backend that does
+    /// not reuse the buffer of an earlier one
+    pub fn capacity(mut self, bytes: usize) -> Self {
+        self.capacity = bytes;
+        self
+    }
+
+    /// A backend of `size` writing into a buffer of its own, see
+    /// [`TypstBackend::new_buffered`], set up and drawing into a reused buffer
+    pub fn backend(&self, size: (u32, u32)) -> TypstBackend<'static> {
+        let mut backend = TypstBackend::new_buffered(size);
+        backend.buf = self
+            .buffers
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| String::with_capacity(self.capacity));
+        match &self.setup {
+            Some(setup) => setup(backend),
+            None => backend,
+        }
+    }
+
+    /// Complete the chart of a backend handed out by the pool, see
+    /// [`TypstBackend::finish`], and take its buffer back
+    pub fn finish(&self, mut backend: TypstBackend<'static>) -> Result<String, Error> {
+        let document = backend.finish_document();
+        let mut buf = std::mem::take(&mut backend.buf);
+        buf.clear();
+        self.buffers.lock().unwrap().push(buf);
+        document
+    }
+
+    /// Like [`Self::finish`], keeping the document under `name` until
+    /// [`Self::take_outputs`]
+    pub fn collect(&self, name: &str, backend: TypstBackend<'static>) -> Result<(), Error> {
+        let document = self.finish(backend)?;
+        self.outputs
+            .lock()
+            .unwrap()
+            .push((name.to_string(), document));
+        Ok(())
+    }
+
+    /// The documents collected so far with their names, sorted by name so the
+    /// order does not depend on which worker finished first
+    pub fn take_outputs(&self) -> Vec<(String, String)> {
+        let mut outputs = std::mem::take(&mut *self.outputs.lock().unwrap());
+        outputs.sort_by(|a, b| a.0.cmp(&b.0));
+        outputs
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TypstPool;
+    use plotters_backend::{BackendColor, DrawingBackend};
+
+    #[test]
+    fn test_pool() {
+        let pool = TypstPool::new()
+            .capacity(4096)
+            .setup(|backend| backend.reproducible());
+        let draw = |i: u8| {
+            let mut backend = pool.backend((100, 50));
+            let color = BackendColor {
+                alpha: 1.0,
+                rgb: (i, 0, 0),
+            };
+            backend.draw_pixel((0, i32::from(i)), color).unwrap();
+            backend
+        };
+
+        let document = pool.finish(draw(0)).unwrap();
+        assert!(document.contains("  #place(dx: 0pt, dy: 0pt, rect(width: 1pt, height: 1pt, fill: rgb(0, 0, 0), stroke: none))\n"));
+        let buffers = pool.buffers.lock().unwrap();
+        assert_eq!(buffers.len(), 1);
+        assert!(buffers[0].is_empty() && buffers[0].capacity() >= 4096);
+        drop(buffers);
+
+        std::thread::scope(|scope| {
+            for i in (1..=8).rev() {
+                let (pool, draw) = (&pool, &draw);
+                scope.spawn(move || pool.collect(&format!("chart-{}", i), draw(i)).unwrap());
+            }
+        });
+        let outputs = pool.take_outputs();
+        let names: Vec<_> = outputs.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            (1..=8).map(|i| format!("chart-{}", i)).collect::<Vec<_>>()
+        );
+        assert!(outputs[7]
+            .1
+            .contains("dy: 8pt, rect(width: 1pt, height: 1pt, fill: rgb(8, 0, 0), stroke: none)"));
+        assert!(pool.take_outputs().is_empty());
+    }
+}
@@ -0,0 +1,739 @@
+/*!
+Rasterizing the dense parts of a chart
+
+Behind the `image` feature, [`TypstBackend::raster_fallback`] turns a series with
+more primitives than a threshold, such as a scatter plot of a million points, into
+a single embedded PNG image, while the axes, labels and legend stay vector
+content. Typst compiles an image in no time, where a million shapes take minutes.
+
+The series are the parts of the chart begun by
+[`TypstSection::series`](crate::element::TypstSection::series), and the data
+layer in [`TypstBackend::split_layers`] mode. Their pixels, lines, rects, circles
+and polygons are drawn into the image both within and outside of the canvas
+bounds, but text and images stay vector content.
+
+The primitives of a series are only recorded until there are more than the
+threshold, when they are drawn into its image at once; a chart staying below it
+costs no image. A chart written to a file embeds each image as a PNG file next to
+it, named after the file and the series, such as `chart-series-1.png`; other
+targets embed the bytes of the PNG in the document.
+
+[`TypstBackend::raster_dpi`] sets the resolution of the images and
+[`TypstBackend::raster_only`] the series that may be rasterized at all. What was
+rasterized, and at which cost, is listed by [`TypstBackend::raster_report`] and in
+the [manifest](TypstBackend::manifest), to audit the trade-off between the size of
+the document and the fidelity of the chart:
+
+```
+use plotters_backend::DrawingBackend;
+use plotters_typst::TypstBackend;
+
+let mut backend = TypstBackend::new_buffered((320, 200))
+    .raster_fallback(10_000)
+    .raster_dpi(300.0)
+    .raster_only(&["series 2"]);
+// Draw the chart
+backend.present().unwrap();
+for series in backend.raster_report() {
+    println!("{}: {} primitives", series.name, series.primitives);
+}
+```
+*/
+
+use std::borrow::Cow;
+use std::io::Error;
+use std::ops::Range;
+
+use image::codecs::png::PngEncoder;
+use image::{ExtendedColorType, ImageEncoder};
+use plotters_backend::{BackendColor, BackendCoord, BackendStyle};
+
+use crate::{gray_level, Target, TypstBackend, MARKER_MARK};
+
+/// The resolution of the images of rasterized series, unless set with
+/// [`TypstBackend::raster_dpi`]
+const RASTER_DPI: f64 = 144.0;
+
+/// The most pixels the image of a series has, 256 MiB drawn in memory; the
+/// resolution of a larger image is lowered to fit
+const RASTER_MAX_PIXELS: f64 = (1 << 26) as f64;
+
+/// A series that may be rasterized, as listed by [`TypstBackend::raster_report`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct RasterReport {
+    /// The name of the series in its section comment, such as `series 1`, or
+    /// `data` for the data layer
+    pub name: String,
+    /// The number of primitives drawn in the series
+    pub primitives: usize,
+    /// Whether the series was replaced by an image
+    pub rasterized: bool,
+    /// The size of the image in pixels, or of the image it would be rasterized into
+    pub pixels: (u32, u32),
+    /// The resolution of the image in pixels per inch
+    pub dpi: f64,
+    /// The size of the encoded image in bytes, or 0 when it was not rasterized
+    pub bytes: usize,
+}
+
+/// A primitive drawn into the image of a series
+pub(crate) enum Shape<'s> {
+    Pixel(BackendCoord),
+    /// A line from one point to the other, of a stroke width
+    Line(BackendCoord, BackendCoord, f64),
+    /// A rect between opposite corners, filled or outlined with a stroke width
+    Rect(BackendCoord, BackendCoord, Option<f64>),
+    /// A circle of a radius, filled or outlined with a stroke width
+    Circle(BackendCoord, u32, Option<f64>),
+    Polygon(Cow<'s, [BackendCoord]>),
+}
+
+impl Shape<'_> {
+    /// The shape holding its points, to be drawn later
+    fn into_owned(self) -> Shape<'static> {
+        match self {
+            Shape::Pixel(p) => Shape::Pixel(p),
+            Shape::Line(from, to, width) => Shape::Line(from, to, width),
+            Shape::Rect(a, b, stroke) => Shape::Rect(a, b, stroke),
+            Shape::Circle(center, radius, stroke) => Shape::Circle(center, radius, stroke),
+            Shape::Polygon(points) => Shape::Polygon(Cow::Owned(points.into_owned())),
+        }
+    }
+}
+
+/// A series that may be rasterized
+pub(crate) struct DenseGroup {
+    name: String,
+    /// The byte ranges in `buf` of the commands of its primitives
+    ranges: Vec<Range<usize>>,
+    primitives: usize,
+    /// The number of clip regions and the reveal group open at its first primitive,
+    /// which its image is placed into; primitives drawn elsewhere stay vectors
+    context: Option<(usize, Option<String>)>,
+    /// Whether the series may be rasterized, unless it is left out by
+    /// [`TypstBackend::raster_only`]
+    allowed: bool,
+    /// The primitives drawn and their colors, until there are more than the
+    /// threshold
+    recorded: Vec<(Shape<'static>, BackendColor)>,
+    /// The image drawn into, once there are more primitives than the threshold
+    raster: Option<Raster>,
+    /// The size of its encoded image, once rasterized
+    bytes: Option<usize>,
+}
+
+/// An RGBA image with straight alpha, drawn by sampling the center of each pixel
+struct Raster {
+    width: usize,
+    height: usize,
+    scale: f64,
+    data: Vec<u8>,
+}
+
+impl Raster {
+    /// The image of a canvas of `size` backend pixels, `scale` image pixels per
+    /// backend pixel
+    fn new(size: (u32, u32), scale: f64) -> Self {
+        let (width, height) = Self::size(size, scale);
+        let (width, height) = (width as usize, height as usize);
+        Self {
+            width,
+            height,
+            scale,
+            data: vec![0; width * height * 4],
+        }
+    }
+
+    /// The size in image pixels of the image of a canvas of `size`
+    fn size((width, height): (u32, u32), scale: f64) -> (u32, u32) {
+        let scaled = |length: u32| (f64::from(length) * scale).ceil() as u32;
+        (scaled(width), scaled(height))
+    }
+
+    /// Composite `color` over the pixel at `(x, y)`
+    fn blend(&mut self, x: usize, y: usize, color: BackendColor) {
+        let i = (y * self.width + x) * 4;
+        let pixel = &mut self.data[i..i + 4];
+        let alpha = color.alpha.clamp(0.0, 1.0);
+        let below = f64::from(pixel[3]) / 255.0 * (1.0 - alpha);
+        let out = alpha + below;
+        if out <= 0.0 {
+            return;
+        }
+        let (r, g, b) = color.rgb;
+        for (channel, value) in pixel.iter_mut().zip([r, g, b]) {
+            let mixed = (f64::from(value) * alpha + f64::from(*channel) * below) / out;
+            *channel = mixed.round() as u8;
+        }
+        pixel[3] = (out * 255.0).round() as u8;
+    }
+
+    /// The range of pixel indices whose centers lie within `from..to` in backend
+    /// pixels, clamped to the image of `len` pixels
+    fn span(&self, from: f64, to: f64, len: usize) -> Range<usize> {
+        let start = (from * self.scale - 0.5).ceil().max(0.0) as usize;
+        let end = (to * self.scale - 0.5).ceil().clamp(0.0, len as f64) as usize;
+        start.min(end)..end
+    }
+
+    /// Fill the area enclosed by `contours` with the even-odd rule, or else the
+    /// non-zero rule
+    fn fill(&mut self, contours: &[Vec<(f64, f64)>], even_odd: bool, color: BackendColor) {
+        let points = contours.iter().flatten();
+        let top = points.clone().map(|p| p.1).fold(f64::INFINITY, f64::min);
+        let bottom = points.map(|p| p.1).fold(f64::NEG_INFINITY, f64::max);
+        let mut crossings = Vec::new();
+        for row in self.span(top, bottom, self.height) {
+            let y = (row as f64 + 0.5) / self.scale;
+            crossings.clear();
+            for contour in contours {
+                for (i, &(x0, y0)) in contour.iter().enumerate() {
+                    let (x1, y1) = contour[(i + 1) % contour.len()];
+                    if (y0 <= y) != (y1 <= y) {
+                        let x = x0 + (y - y0) / (y1 - y0) * (x1 - x0);
+                        crossings.push((x, if y1 > y0 { 1 } else { -1 }));
+                    }
+                }
+            }
+            crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let mut winding = 0;
+            for pair in crossings.windows(2) {
+                winding += pair[0].1;
+                let inside = if even_odd {
+                    winding % 2 != 0
+                } else {
+                    winding != 0
+                };
+                if inside {
+                    for column in self.span(pair[0].0, pair[1].0, self.width) {
+                        self.blend(column, row, color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Fill the pixels whose centers are within `inner..=outer` of `center`
+    fn ring(&mut self, center: (f64, f64), inner: f64, outer: f64, color: BackendColor) {
+        let (cx, cy) = center;
+        for row in self.span(cy - outer, cy + outer, self.height) {
+            let dy = (row as f64 + 0.5) / self.scale - cy;
+            for column in self.span(cx - outer, cx + outer, self.width) {
+                let dx = (column as f64 + 0.5) / self.scale - cx;
+                let distance = (dx * dx + dy * dy).sqrt();
+                if (inner..=outer).contains(&distance) {
+                    self.blend(column, row, color);
+                }
+            }
+        }
+    }
+
+    fn draw(&mut self, shape: &Shape, color: BackendColor) {
+        let point = |(x, y): BackendCoord| (f64::from(x), f64::from(y));
+        match *shape {
+            Shape::Pixel(p) => self.draw(&Shape::Rect(p, (p.0 + 1, p.1 + 1), None), color),
+            Shape::Line(from, to, width) => {
+                let ((x0, y0), (x1, y1)) = (point(from), point(to));
+                let length = ((x1 - x0).powi(2) + (y1 - y0).powi(2)).sqrt();
+                if length == 0.0 {
+                    return;
+                }
+                // Butt caps: the stroke ends at the end points
+                let (nx, ny) = (
+                    (y0 - y1) / length * width / 2.0,
+                    (x1 - x0) / length * width / 2.0,
+                );
+                let quad = vec![
+                    (x0 + nx, y0 + ny),
+                    (x1 + nx, y1 + ny),
+                    (x1 - nx, y1 - ny),
+                    (x0 - nx, y0 - ny),
+                ];
+                self.fill(&[quad], false, color);
+            }
+            Shape::Rect(a, b, stroke) => {
+                let ((x0, y0), (x1, y1)) = (point(a), point(b));
+                let (l, r, t, b) = (x0.min(x1), x0.max(x1), y0.min(y1), y0.max(y1));
+                let corners = |d: f64| {
+                    vec![
+                        (l - d, t - d),
+                        (r + d, t - d),
+                        (r + d, b + d),
+                        (l - d, b + d),
+                    ]
+                };
+                match stroke {
+                    None => self.fill(&[corners(0.0)], false, color),
+                    // The stroke is centered on the outline
+                    Some(width) => {
+                        self.fill(&[corners(width / 2.0), corners(-width / 2.0)], true, color)
+                    }
+                }
+            }
+            Shape::Circle(center, radius, stroke) => {
+                let (center, radius) = (point(center), f64::from(radius));
+                match stroke {
+                    None => self.ring(center, 0.0, radius, color),
+                    Some(width) => {
+                        self.ring(center, radius - width / 2.0, radius + width / 2.0, color)
+                    }
+                }
+            }
+            Shape::Polygon(ref points) => {
+                let contour = points.iter().map(|&p| point(p)).collect();
+                self.fill(&[contour], false, color);
+            }
+        }
+    }
+
+    fn encode_png(&self) -> Result<Vec<u8>, Error> {
+        let mut png = Vec::new();
+        PngEncoder::new(&mut png)
+            .write_image(
+                &self.data,
+                self.width as u32,
+                self.height as u32,
+                ExtendedColorType::Rgba8,
+            )
+            .map_err(Error::other)?;
+        Ok(png)
+    }
+}
+
+impl TypstBackend<'_> {
+    /// Draw a series of more than `threshold` primitives as an embedded image
+    /// instead of a command per primitive, see the [module](crate::raster)
+    ///
+    /// The image is placed where the first primitive of the series was drawn, and
+    /// has two image pixels per backend pixel. The mode is off in
+    /// [`Self::source_map_comments`] mode, where every drawing call keeps its lines.
+    pub fn raster_fallback(mut self, threshold: usize) -> Self {
+        self.options.raster_threshold = Some(threshold);
+        self
+    }
+
+    /// Rasterize series at `dpi` pixels per inch of the canvas at its natural size,
+    /// instead of 144, in [`Self::raster_fallback`] mode
+    ///
+    /// An image has at most 64 megapixels: a large canvas is rasterized at a lower
+    /// resolution, given by [`Self::raster_report`].
+    ///
+    /// # Panics
+    /// If `dpi` is not a finite number greater than zero.
+    pub fn raster_dpi(mut self, dpi: f64) -> Self {
+        assert!(
+            dpi.is_finite() && dpi > 0.0,
+            "raster DPI must be finite and positive, got {}",
+            dpi
+        );
+        self.options.raster_dpi = Some(dpi);
+        self
+    }
+
+    /// Image pixels per backend pixel of the images of the series, at the
+    /// [`Self::raster_dpi`] lowered to the size limit
+    fn raster_scale(&self) -> f64 {
+        let scale = self.options.raster_dpi.unwrap_or(RASTER_DPI) / 72.0;
+        let pixels = f64::from(self.size.0) * f64::from(self.size.1) * scale * scale;
+        if pixels > RASTER_MAX_PIXELS {
+            scale * (RASTER_MAX_PIXELS / pixels).sqrt()
+        } else {
+            scale
+        }
+    }
+
+    /// Only rasterize the series named `names` in their section comments, such as
+    /// `series 2`, or `data` for the data layer, in [`Self::raster_fallback`] mode
+    ///
+    /// The other series stay vector content however many primitives they have, e.g.
+    /// a line series whose crisp strokes matter more than the size of the document.
+    pub fn raster_only(mut self, names: &[&str]) -> Self {
+        self.options.raster_only = Some(names.iter().map(|name| name.to_string()).collect());
+        self
+    }
+
+    /// The series that may be rasterized in [`Self::raster_fallback`] mode, in
+    /// drawing order, with whether they were once the chart is presented
+    pub fn raster_report(&self) -> Vec<RasterReport> {
+        let scale = self.raster_scale();
+        self.dense_groups
+            .iter()
+            .map(|group| {
+                let pixels = match &group.raster {
+                    Some(raster) => (raster.width as u32, raster.height as u32),
+                    None => Raster::size(self.size, scale),
+                };
+                RasterReport {
+                    name: group.name.clone(),
+                    primitives: group.primitives,
+                    rasterized: group.bytes.is_some(),
+                    pixels,
+                    dpi: scale * 72.0,
+                    bytes: group.bytes.unwrap_or(0),
+                }
+            })
+            .collect()
+    }
+
+    /// Start the series `name` that may be rasterized, see [`Self::raster_fallback`]
+    #[cfg(feature = "plotters")]
+    pub(crate) fn begin_dense_group(&mut self, name: String) {
+        if self.options.raster_threshold.is_none() {
+            return;
+        }
+        let allowed = match &self.options.raster_only {
+            Some(names) => names.contains(&name),
+            None => true,
+        };
+        self.dense_groups.push(DenseGroup {
+            name,
+            ranges: Vec::new(),
+            primitives: 0,
+            context: None,
+            allowed,
+            recorded: Vec::new(),
+            raster: None,
+            bytes: None,
+        });
+        self.dense_group = Some(self.dense_groups.len() - 1);
+    }
+
+    /// End the series that may be rasterized, when another part of the chart begins
+    #[cfg(feature = "plotters")]
+    pub(crate) fn end_dense_group(&mut self) {
+        self.dense_group = None;
+    }
+
+    /// Draw `shape` in `color` into the image of the current series, noting that the
+    /// commands written since `start` are to be replaced if it is rasterized
+    pub(crate) fn rasterize(&mut self, start: usize, color: BackendColor, shape: Shape) {
+        let Some(index) = self.dense_group else {
+            return;
+        };
+        if self.options.source_map {
+            return;
+        }
+        let color = match self.options.grayscale {
+            true => {
+                let level = gray_level(color);
+                BackendColor {
+                    rgb: (level, level, level),
+                    alpha: color.alpha,
+                }
+            }
+            false => self.palette_color(color),
+        };
+        let context = (self.clips.len(), self.step.clone());
+        let end = self.buf.len();
+        let (size, scale) = (self.size, self.raster_scale());
+        let threshold = self.options.raster_threshold.unwrap_or(usize::MAX);
+        let group = &mut self.dense_groups[index];
+        if *group.context.get_or_insert_with(|| context.clone()) != context {
+            return;
+        }
+        group.primitives += 1;
+        if !group.allowed {
+            return;
+        }
+        match &mut group.raster {
+            Some(raster) => raster.draw(&shape, color),
+            // Crossing the threshold, the primitives recorded so far are drawn
+            None if group.primitives > threshold => {
+                let mut raster = Raster::new(size, scale);
+                for (shape, color) in group.recorded.drain(..) {
+                    raster.draw(&shape, color);
+                }
+                raster.draw(&shape, color);
+                group.recorded = Vec::new();
+                group.raster = Some(raster);
+            }
+            None => group.recorded.push((shape.into_owned(), color)),
+        }
+        // A rect merged into the bars before rewrites their command
+        match group.ranges.last_mut() {
+            Some(range) if range.end >= start => range.end = range.end.max(end),
+            _ => group.ranges.push(start..end),
+        }
+    }
+
+    /// The width of a stroke of `width` backend pixels, as scaled by
+    /// [`Self::stroke_scale`]
+    pub(crate) fn stroke_px<T: Into<f64>>(&self, width: T) -> f64 {
+        width.into() * self.options.stroke_scale.unwrap_or(1.0)
+    }
+
+    /// Like [`Self::rasterize`] for a closed shape, given the stroke width of its
+    /// outline, drawn as [`Self::shape_attrs`] writes it
+    pub(crate) fn rasterize_closed<'s, S: BackendStyle, F: Fn(Option<f64>) -> Shape<'s>>(
+        &mut self,
+        start: usize,
+        style: &S,
+        fill: bool,
+        outlined: bool,
+        shape: F,
+    ) {
+        if !fill {
+            let width = self.stroke_px(style.stroke_width());
+            self.rasterize(start, style.color(), shape(Some(width)));
+            return;
+        }
+        self.rasterize(start, style.color(), shape(None));
+        match self.options.outline {
+            Some((color, width)) if outlined && color.alpha > 0.0 => {
+                let width = self.stroke_px(width);
+                self.rasterize(start, color, shape(Some(width)));
+            }
+            _ => {}
+        }
+    }
+
+    /// Replace the commands of each series of more primitives than the threshold by
+    /// its image
+    pub(crate) fn resolve_dense_groups(&mut self) -> Result<(), Error> {
+        let Some(threshold) = self.options.raster_threshold else {
+            return Ok(());
+        };
+        let (width, height) = (self.len(self.size.0), self.len(self.size.1));
+        let path = match self.target {
+            Target::File(path) => Some(path),
+            _ => None,
+        };
+        let mut replaced = Vec::new();
+        for group in &mut self.dense_groups {
+            // The series below the threshold stay vector content
+            group.recorded = Vec::new();
+            let Some(raster) = &group.raster else {
+                continue;
+            };
+            if group.primitives <= threshold || group.ranges.is_empty() {
+                continue;
+            }
+            let png = raster.encode_png()?;
+            group.bytes = Some(png.len());
+            let image = match path {
+                Some(path) => {
+                    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                    let name = format!("{}-{}.png", stem, group.name.replace(' ', "-"));
+                    std::fs::write(path.with_file_name(&name), &png)?;
+                    format!(
+                        "  #place(image({:?}, width: {}, height: {}))\n",
+                        name, width, height
+                    )
+                }
+                None => {
+                    let mut image = String::with_capacity(png.len() * 4 + 128);
+                    image.push_str("  #place(image.decode(bytes((");
+                    for (i, byte) in png.iter().enumerate() {
+                        if i > 0 {
+                            image.push_str(", ");
+                        }
+                        image.push_str(itoa::Buffer::new().format(*byte));
+                    }
+                    image.push_str(&format!(
+                        ")), format: \"png\", width: {}, height: {}))\n",
+                        width, height
+                    ));
+                    image
+                }
+            };
+            replaced.push((group.ranges[0].start..group.ranges[0].start, image));
+            replaced.extend(
+                group
+                    .ranges
+                    .iter()
+                    .map(|range| (range.clone(), String::new())),
+            );
+        }
+        if self.options.manifest {
+            let report = self.raster_report();
+            self.manifest.rasterized(&report);
+        }
+        if replaced.is_empty() {
+            return Ok(());
+        }
+        replaced.sort_by_key(|(range, _)| (range.start, range.end));
+        // Markers only used by the replaced commands are not bound
+        for (range, _) in &replaced {
+            for (i, part) in self.buf[range.clone()].split(MARKER_MARK).enumerate() {
+                if let (1, Ok(id)) = (i % 2, part.parse::<usize>()) {
+                    self.markers.values[id].1 -= 1;
+                }
+            }
+        }
+        let mut buf = String::with_capacity(self.buf.len());
+        let mut offset = 0;
+        for (range, replacement) in replaced {
+            buf.push_str(&self.buf[offset..range.start]);
+            buf.push_str(&replacement);
+            offset = range.end;
+        }
+        buf.push_str(&self.buf[offset..]);
+        self.buf = buf;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "plotters"))]
+mod test {
+    use super::RasterReport;
+    use crate::element::TypstSection;
+    use crate::test::checked_save_file;
+    use crate::TypstBackend;
+    use plotters::prelude::*;
+
+    #[test]
+    fn test_raster_fallback() {
+        let draw = |backend: TypstBackend| {
+            let output = backend.output().unwrap();
+            let root = backend.into_drawing_area();
+            root.draw(&TypstSection::new("frame")).unwrap();
+            root.draw(&Rectangle::new([(10, 10), (190, 110)], BLACK))
+                .unwrap();
+            root.draw(&TypstSection::series()).unwrap();
+            for i in 0..500 {
+                let center = ((i * 37) % 170 + 15, (i * 53) % 90 + 15);
+                root.draw(&Circle::new(center, 2, BLUE.mix(0.6).filled()))
+                    .unwrap();
+            }
+            root.draw(&PathElement::new(
+                vec![(15, 100), (100, 20), (185, 100)],
+                RED,
+            ))
+            .unwrap();
+            root.draw(&TypstSection::series()).unwrap();
+            for x in [50, 100, 150] {
+                root.draw(&Circle::new((x, 60), 4, GREEN.filled())).unwrap();
+            }
+            root.draw(&Text::new("label", (20, 115), ("sans-serif", 10)))
+                .unwrap();
+            root.present().unwrap();
+            output.take()
+        };
+
+        let hybrid = draw(TypstBackend::new_buffered((200, 130)).raster_fallback(100));
+        checked_save_file("test_raster_fallback", &hybrid);
+        // The dense series is an image in place of its shapes, the rest stays vector
+        assert_eq!(
+            hybrid
+                .matches("  #place(image.decode(bytes((137, 80, 78, 71, ")
+                .count(),
+            1
+        );
+        assert!(hybrid.contains(r#"format: "png", width: 200pt, height: 130pt))"#));
+        assert!(!hybrid.contains("rgb(0, 0, 255"));
+        assert!(!hybrid.contains("line(length: "));
+        assert_eq!(hybrid.matches("circle(radius: 4pt").count(), 3);
+        assert!(hybrid.contains("rect(width: 180pt, height: 100pt"));
+        assert!(hybrid.contains("label"));
+        assert!(
+            hybrid.find("// section: series 1").unwrap() < hybrid.find("image.decode").unwrap()
+        );
+
+        let vector = draw(TypstBackend::new_buffered((200, 130)).raster_fallback(1000));
+        assert!(!vector.contains("image.decode"));
+        #[cfg(feature = "test-util")]
+        {
+            use crate::test_util::{ink_bounds, render};
+            assert_eq!(ink_bounds(&render(&hybrid)), ink_bounds(&render(&vector)));
+        }
+    }
+
+    #[test]
+    fn test_raster_report() {
+        let mut backend = TypstBackend::new_buffered((200, 100))
+            .raster_fallback(20)
+            .raster_dpi(72.0)
+            .raster_only(&["series 1"])
+            .manifest();
+        for series in 0..3 {
+            backend.write_section(None);
+            for i in 0..10 * series {
+                backend
+                    .draw_circle((10 + i * 5, 50), 2, &RED.filled(), true)
+                    .unwrap();
+            }
+            for i in 0..30 {
+                backend
+                    .draw_pixel((10 + i * 5, 20 + series * 20), BLACK.to_backend_color())
+                    .unwrap();
+            }
+        }
+        backend.present().unwrap();
+
+        let report = backend.raster_report();
+        assert_eq!(report.len(), 3);
+        assert!(report[0].rasterized && report[0].bytes > 0);
+        assert_eq!((report[0].primitives, report[0].pixels), (30, (200, 100)));
+        // Left out of rasterizing, however dense
+        assert_eq!(
+            report[2],
+            RasterReport {
+                name: "series 3".to_string(),
+                primitives: 50,
+                rasterized: false,
+                pixels: (200, 100),
+                dpi: 72.0,
+                bytes: 0,
+            }
+        );
+        let manifest = backend.manifest_json().unwrap();
+        let first = format!(
+            "  \"rasterized\": [\n    {{\"name\": \"series 1\", \"primitives\": 30, \"rasterized\": true, \"pixels\": [200, 100], \"dpi\": 72, \"bytes\": {}}},\n",
+            report[0].bytes
+        );
+        assert!(manifest.contains(&first));
+    }
+
+    #[test]
+    fn test_raster_sidecar() {
+        let dir = std::path::Path::new("target/test/typst/raster");
+        std::fs::create_dir_all(dir).unwrap();
+        let path = dir.join("chart.typ");
+        let mut backend = TypstBackend::new(&path, (100, 50)).raster_fallback(20);
+        for series in 0..2 {
+            backend.write_section(None);
+            for i in 0..15 * (series + 1) {
+                backend
+                    .draw_pixel((5 + i * 3, 10 + series * 20), BLUE.to_backend_color())
+                    .unwrap();
+            }
+        }
+        // Only the series beyond the threshold is drawn into an image
+        let groups = &backend.dense_groups;
+        assert!(groups[0].raster.is_none() && groups[0].recorded.len() == 15);
+        assert!(groups[1].raster.is_some() && groups[1].recorded.is_empty());
+        backend.present().unwrap();
+        drop(backend);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content
+            .contains("  #place(image(\"chart-series-2.png\", width: 100pt, height: 50pt))\n"));
+        assert!(!content.contains("image.decode"));
+        let png = image::open(dir.join("chart-series-2.png")).unwrap();
+        assert_eq!((png.width(), png.height()), (200, 100));
+        // Images are resolved relative to the working directory
+        let image = format!("image(\"{}/", dir.display());
+        checked_save_file("test_raster_sidecar", &content.replace("image(\"", &image));
+    }
+
+    #[test]
+    fn test_raster_size_limit() {
+        let mut backend = TypstBackend::new_buffered((2000, 1000))
+            .raster_fallback(20)
+            .raster_dpi(7200.0)
+            .raster_only(&[]);
+        backend.write_section(None);
+        backend.present().unwrap();
+
+        let report = backend.raster_report();
+        let (width, height) = report[0].pixels;
+        assert_eq!((width, height), (11586, 5793));
+        assert!(report[0].dpi < 7200.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "raster DPI must be finite and positive")]
+    fn test_raster_dpi_rejects_nan() {
+        let _ = TypstBackend::new_buffered((200, 100)).raster_dpi(f64::NAN);
+    }
+}
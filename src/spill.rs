@@ -0,0 +1,189 @@
+/*!
+Spilling the drawing commands to disk
+
+With [`TypstBackend::spill_to_disk`], the drawing commands held in memory are moved
+to a temporary file whenever they grow beyond a cap, so a pathological chart of
+hundreds of millions of primitives cannot exhaust the memory of the process
+generating it. The commands are read back one spilled chunk at a time when the
+chart is presented, and streamed to a file or writer target.
+
+The passes rewriting the commands once the chart is complete are applied to each
+chunk as it is read back. The modes needing all of the commands in memory at once
+do not spill, see [`TypstBackend::spill_to_disk`].
+*/
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Error, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{compact_lines, replace_marks, Namespace, TypstBackend};
+
+/// The drawing commands spilled to a temporary file, deleted when dropped
+pub(crate) struct Spill {
+    path: PathBuf,
+    file: File,
+    /// The length in bytes of each chunk spilled, in order
+    chunks: Vec<usize>,
+    /// The zero offset dropped from the commands in compact mode, and what the
+    /// marks of the markers and text styles stand for, once resolved
+    pub(crate) zero: Option<String>,
+    pub(crate) marks: Vec<(char, Vec<String>)>,
+}
+
+impl Spill {
+    /// Create an empty temporary file
+    fn new() -> Result<Self, Error> {
+        static FILES: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "plotters-typst-{}-{}.spill",
+            std::process::id(),
+            FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Self {
+            path,
+            file,
+            chunks: Vec::new(),
+            zero: None,
+            marks: Vec::new(),
+        })
+    }
+
+    /// Pass each chunk of the spilled commands to `write` in order, with the passes
+    /// completing the chart applied
+    pub(crate) fn read<F>(&self, mut write: F) -> Result<(), Error>
+    where
+        F: FnMut(&str) -> Result<(), Error>,
+    {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut chunk = Vec::new();
+        for &len in &self.chunks {
+            chunk.resize(len, 0);
+            reader.read_exact(&mut chunk)?;
+            let commands = std::str::from_utf8(&chunk).map_err(Error::other)?;
+            let mut commands = match &self.zero {
+                Some(zero) => compact_lines(commands, zero),
+                None => commands.to_string(),
+            };
+            for (mark, replacements) in &self.marks {
+                commands = replace_marks(&commands, *mark, replacements);
+            }
+            write(&commands)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl TypstBackend<'_> {
+    /// Move the drawing commands to a temporary file whenever more than `cap` bytes
+    /// of them are held in memory, so a pathological chart cannot exhaust the memory
+    /// of the process generating it
+    ///
+    /// The commands are read back one spilled chunk at a time when the chart is
+    /// presented, and streamed to a file or writer target. They are not spilled in [`Self::source_map_comments`],
+    /// [`Self::split_layers`], [`Self::split_series`] and hashed
+    /// [`Self::namespace`] modes, nor with a raster fallback, which need all of them
+    /// in memory when the chart is presented. A document returned as a string, by
+    /// [`Self::finish`] or into a string target, is still built in memory.
+    pub fn spill_to_disk(mut self, cap: usize) -> Self {
+        self.options.spill_cap = Some(cap);
+        self
+    }
+
+    /// The number of bytes of drawing commands spilled to disk so far
+    pub fn spilled_bytes(&self) -> usize {
+        self.spill
+            .as_ref()
+            .map_or(0, |spill| spill.chunks.iter().sum())
+    }
+
+    /// Whether the commands can be spilled, see [`Self::spill_to_disk`]
+    fn can_spill(&self) -> bool {
+        #[cfg(feature = "image")]
+        if self.options.raster_threshold.is_some() {
+            return false;
+        }
+        !self.options.source_map
+            && !self.options.layers
+            && !self.options.split_series
+            && !matches!(self.options.namespace, Some(Namespace::Hashed))
+    }
+
+    /// Spill the drawing commands in memory if they grew beyond the cap, between two
+    /// drawing calls
+    pub(crate) fn spill_commands(&mut self) -> Result<(), Error> {
+        match self.options.spill_cap {
+            Some(cap) if self.buf.len() > cap && self.can_spill() => {}
+            _ => return Ok(()),
+        }
+        if self.spill.is_none() {
+            self.spill = Some(Spill::new()?);
+        }
+        let spill = self.spill.as_mut().unwrap();
+        spill.file.write_all(self.buf.as_bytes())?;
+        spill.chunks.push(self.buf.len());
+        self.buf.clear();
+        // The bars written last are in the file now, and cannot be extended
+        self.bars = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::TypstBackend;
+    use plotters::prelude::*;
+
+    #[test]
+    fn test_spill_to_disk() {
+        fn draw(backend: TypstBackend) -> TypstBackend {
+            let mut backend = backend.compact();
+            for i in 0..400 {
+                let center = ((i * 37) % 300, (i * 53) % 200);
+                backend
+                    .draw_circle(center, 3, &BLUE.filled(), true)
+                    .unwrap();
+                if i % 50 == 0 {
+                    let style = TextStyle::from(("serif", 10 + i / 50).into_font());
+                    backend.draw_text("tick", &style, center).unwrap();
+                }
+            }
+            backend
+        }
+
+        let mut expected = Vec::new();
+        draw(TypstBackend::with_writer(&mut expected, (300, 200)))
+            .present()
+            .unwrap();
+        let mut spilled = Vec::new();
+        let mut backend =
+            draw(TypstBackend::with_writer(&mut spilled, (300, 200)).spill_to_disk(4096));
+        assert!(backend.buf.len() <= 4096 + 256);
+        assert!(backend.spilled_bytes() > 2 * 4096);
+        let path = backend.spill.as_ref().unwrap().path.clone();
+        assert!(path.exists());
+        backend.present().unwrap();
+        drop(backend);
+        assert!(!path.exists());
+        // Marker bindings, text rules and compaction span the spilled chunks
+        assert_eq!(
+            String::from_utf8(spilled).unwrap(),
+            String::from_utf8(expected).unwrap()
+        );
+
+        let finished = draw(TypstBackend::new_buffered((300, 200)).spill_to_disk(4096));
+        let unspilled = draw(TypstBackend::new_buffered((300, 200)));
+        assert_eq!(finished.finish().unwrap(), unspilled.finish().unwrap());
+    }
+}
@@ -0,0 +1,142 @@
+/*!
+Typst tables of plotted data
+
+A [`DataTable`] renders the numbers behind a chart as a Typst `table`, for reports
+that show the data next to the figure. It is independent of the backend: write its
+markup after the chart or into a file of its own.
+*/
+
+use std::fmt;
+
+use crate::escape_markup;
+
+type Formatter<'a> = Box<dyn Fn(usize, f64) -> String + 'a>;
+
+/// A table of numeric data with a header row
+pub struct DataTable<'a> {
+    headers: Vec<String>,
+    rows: Vec<Vec<f64>>,
+    formatter: Formatter<'a>,
+}
+
+impl<'a> DataTable<'a> {
+    /// Create an empty table with one column per header
+    pub fn new<H: Into<String>, I: IntoIterator<Item = H>>(headers: I) -> Self {
+        Self {
+            headers: headers.into_iter().map(Into::into).collect(),
+            rows: Vec::new(),
+            formatter: Box::new(|_, value| value.to_string()),
+        }
+    }
+
+    /// Append a row; missing cells are left empty and extra cells are dropped
+    pub fn row<R: IntoIterator<Item = f64>>(mut self, row: R) -> Self {
+        self.rows.push(row.into_iter().collect());
+        self
+    }
+
+    /// Append several rows, see [`Self::row`]
+    pub fn rows<R: IntoIterator<Item = f64>, I: IntoIterator<Item = R>>(mut self, rows: I) -> Self {
+        self.rows
+            .extend(rows.into_iter().map(|row| row.into_iter().collect()));
+        self
+    }
+
+    /// Print every number with `digits` digits after the decimal point
+    pub fn precision(self, digits: usize) -> Self {
+        self.formatter(move |_, value| format!("{:.*}", digits, value))
+    }
+
+    /// Format the numbers with `formatter`, called with the column index and the value
+    pub fn formatter<F: Fn(usize, f64) -> String + 'a>(mut self, formatter: F) -> Self {
+        self.formatter = Box::new(formatter);
+        self
+    }
+}
+
+impl fmt::Display for DataTable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let columns = self.headers.len();
+        write!(f, "#table(columns: {}, align: right", columns)?;
+        if columns > 0 {
+            let header = self
+                .headers
+                .iter()
+                .map(|h| format!("[*{}*]", escape_markup(h, true)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            write!(f, ", table.header({})", header)?;
+        }
+        for row in &self.rows {
+            for column in 0..columns {
+                match row.get(column) {
+                    Some(value) => write!(
+                        f,
+                        ", [{}]",
+                        escape_markup(&(self.formatter)(column, *value), true)
+                    )?,
+                    None => f.write_str(", []")?,
+                }
+            }
+        }
+        writeln!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DataTable;
+    use crate::test::checked_save_file;
+
+    #[test]
+    fn test_data_table() {
+        let table = DataTable::new(["Month", "Rain #mm"])
+            .row([1.0, 30.25])
+            .rows([[2.0, 42.0], [3.0, 4.125]])
+            .row([4.0]);
+        let content = table.to_string();
+
+        checked_save_file("test_data_table", &content);
+        assert_eq!(
+            content,
+            concat!(
+                "#table(columns: 2, align: right, table.header([*Month*], [*Rain \\#mm*]), ",
+                "[1], [30.25], [2], [42], [3], [4.125], [4], [])\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_data_table_formatting() {
+        let fixed = DataTable::new(["x", "y"]).row([1.0, 2.345]).precision(1);
+        assert!(fixed.to_string().ends_with("[1.0], [2.3])\n"));
+
+        let custom = DataTable::new(["year", "share"])
+            .row([2024.0, 0.125])
+            .formatter(|column, value| match column {
+                0 => format!("{}", value as i64),
+                _ => format!("{:.1}%", value * 100.0),
+            });
+        assert!(custom.to_string().ends_with("[2024], [12.5%])\n"));
+    }
+
+    #[test]
+    fn test_data_table_escaping() {
+        let table = DataTable::new(["unit_price", "*Total"])
+            .row([1.0, -2.5])
+            .formatter(|column, value| match column {
+                0 => format!("<{}> @ref [x]", value),
+                _ => format!("{}_net", value),
+            });
+        let content = table.to_string();
+
+        checked_save_file("test_data_table_escaping", &content);
+        assert_eq!(
+            content,
+            concat!(
+                "#table(columns: 2, align: right, table.header([*unit\\_price*], [*\\*Total*]), ",
+                "[\\<1\\> \\@ref \\[x\\]], [-2.5\\_net])\n"
+            )
+        );
+    }
+}
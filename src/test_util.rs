@@ -0,0 +1,181 @@
+//! Helpers for testing generated Typst output.
+//!
+//! Sources are compiled with the `typst` crates against the fonts bundled in
+//! `typst-assets`, so results do not depend on the fonts installed on the
+//! machine running the tests.
+
+use std::path::PathBuf;
+
+use tiny_skia::Pixmap;
+use typst::layout::{Frame, FrameItem};
+
+use crate::compile::compile;
+
+/// Asserts that `source` is valid Typst.
+///
+/// Meant for downstream test suites: render a chart with
+/// [`TypstBackend::with_string`](crate::TypstBackend::with_string) and pass the
+/// resulting fragment here.
+///
+/// # Panics
+/// With the compiler diagnostics if the source fails to compile.
+pub fn assert_compiles(source: &str) {
+    if let Err(e) = compile(source) {
+        panic!("Typst compilation failed:\n{e}");
+    }
+}
+
+/// Renders the first page of `source` to an image.
+///
+/// # Panics
+/// If the source fails to compile.
+pub fn render(source: &str) -> Pixmap {
+    let document = compile(source).unwrap_or_else(|e| panic!("Typst compilation failed:\n{e}"));
+    crate::compile::render(&document)
+}
+
+/// The text of the first page of `source`, run after run in layout order.
+///
+/// Useful to check what a label reads once escaped and laid out.
+///
+/// # Panics
+/// If the source fails to compile.
+pub fn rendered_text(source: &str) -> String {
+    fn collect(frame: &Frame, text: &mut String) {
+        for (_, item) in frame.items() {
+            match item {
+                FrameItem::Group(group) => collect(&group.frame, text),
+                FrameItem::Text(run) => text.push_str(&run.text),
+                _ => {}
+            }
+        }
+    }
+
+    let document = compile(source).unwrap_or_else(|e| panic!("Typst compilation failed:\n{e}"));
+    let mut text = String::new();
+    collect(&document.pages[0].frame, &mut text);
+    text
+}
+
+/// The bounding box `(left, top, right, bottom)` of the pixels darker than mid
+/// gray, or `None` for a blank image.
+///
+/// Useful to locate a rendered element, e.g. to check where a label landed.
+pub fn ink_bounds(pixmap: &Pixmap) -> Option<(u32, u32, u32, u32)> {
+    let width = pixmap.width();
+    pixmap
+        .pixels()
+        .iter()
+        .enumerate()
+        .filter(|(_, pixel)| {
+            let pixel = pixel.demultiply();
+            (pixel.red() as u32 + pixel.green() as u32 + pixel.blue() as u32) < 3 * 128
+                && pixel.alpha() > 128
+        })
+        .map(|(i, _)| (i as u32 % width, i as u32 / width))
+        .fold(None, |bounds, (x, y)| {
+            let (l, t, r, b) = bounds.unwrap_or((x, y, x, y));
+            Some((l.min(x), t.min(y), r.max(x), b.max(y)))
+        })
+}
+
+/// How far a rendering may drift from its reference image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tolerance {
+    /// Largest per-channel difference for two pixels to still count as equal.
+    pub channel: u8,
+    /// Fraction of pixels (0.0 - 1.0) allowed to differ.
+    pub pixels: f64,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            channel: 16,
+            pixels: 0.001,
+        }
+    }
+}
+
+fn snapshot_dir() -> PathBuf {
+    let root = std::env::var_os("CARGO_MANIFEST_DIR").map_or_else(PathBuf::new, PathBuf::from);
+    root.join("tests").join("snapshots")
+}
+
+/// Compares the rendering of `source` with `tests/snapshots/<name>.png` using the default tolerance.
+///
+/// See [`assert_snapshot_with`].
+pub fn assert_snapshot(name: &str, source: &str) {
+    assert_snapshot_with(name, source, Tolerance::default());
+}
+
+/// Compares the rendering of `source` with `tests/snapshots/<name>.png`.
+///
+/// References are created or rewritten only when the `UPDATE_SNAPSHOTS`
+/// environment variable is set, e.g. `UPDATE_SNAPSHOTS=1 cargo test`. When the
+/// reference is missing or differs, the rendering is saved as `<name>.actual.png`
+/// next to it for inspection.
+///
+/// # Panics
+/// If the source fails to compile, the reference is missing, or the rendering
+/// differs from the reference.
+pub fn assert_snapshot_with(name: &str, source: &str, tolerance: Tolerance) {
+    let dir = snapshot_dir();
+    let reference_path = dir.join(format!("{name}.png"));
+    let actual_path = dir.join(format!("{name}.actual.png"));
+    let actual = render(source);
+
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        std::fs::create_dir_all(&dir).unwrap();
+        actual.save_png(&reference_path).unwrap();
+        let _ = std::fs::remove_file(&actual_path);
+        return;
+    }
+    let Ok(reference) = Pixmap::load_png(&reference_path) else {
+        std::fs::create_dir_all(&dir).unwrap();
+        actual.save_png(&actual_path).unwrap();
+        panic!(
+            "snapshot `{name}` has no reference at {} (actual rendering saved to {}); \
+             run with UPDATE_SNAPSHOTS=1 to create it",
+            reference_path.display(),
+            actual_path.display()
+        );
+    };
+
+    let mismatch = if (reference.width(), reference.height()) != (actual.width(), actual.height()) {
+        Some(format!(
+            "size {}x{} differs from reference {}x{}",
+            actual.width(),
+            actual.height(),
+            reference.width(),
+            reference.height()
+        ))
+    } else {
+        let differing = reference
+            .data()
+            .chunks_exact(4)
+            .zip(actual.data().chunks_exact(4))
+            .filter(|(a, b)| {
+                a.iter()
+                    .zip(b.iter())
+                    .any(|(a, b)| a.abs_diff(*b) > tolerance.channel)
+            })
+            .count();
+        let total = (actual.width() * actual.height()) as usize;
+        (differing as f64 > total as f64 * tolerance.pixels)
+            .then(|| format!("{differing} of {total} pixels differ"))
+    };
+
+    match mismatch {
+        Some(reason) => {
+            actual.save_png(&actual_path).unwrap();
+            panic!(
+                "snapshot `{name}` does not match: {reason} (actual rendering saved to {})",
+                actual_path.display()
+            );
+        }
+        None => {
+            let _ = std::fs::remove_file(&actual_path);
+        }
+    }
+}
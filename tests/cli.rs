@@ -0,0 +1,50 @@
+#![cfg(feature = "cli")]
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn test_csv_to_typst() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("cli");
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("temperatures.csv");
+    let output = dir.join("temperatures.typ");
+    fs::write(&input, "day,low,high\n1,3.5,12\n2,4,14.5\n3,2,11\n").unwrap();
+    let _ = fs::remove_file(&output);
+
+    let run = Command::new(env!("CARGO_BIN_EXE_plotters-typst"))
+        .arg(&input)
+        .args(["--kind", "bar", "--size", "320x200", "--title", "Week 1"])
+        .output()
+        .unwrap();
+    assert!(
+        run.status.success(),
+        "{}",
+        String::from_utf8_lossy(&run.stderr)
+    );
+    assert_eq!(
+        String::from_utf8(run.stdout).unwrap(),
+        format!("Chart has been saved to {}\n", output.display())
+    );
+
+    let content = fs::read_to_string(&output).unwrap();
+    assert!(content.starts_with("#box(width: 320pt, height: 200pt, clip: true"));
+    assert!(content.contains("Week 1"));
+    assert!(content.contains("low"));
+    assert!(content.contains("high"));
+    // Three bars for each of the two series
+    assert!(content.matches("rect(").count() >= 6);
+    #[cfg(feature = "test-util")]
+    plotters_typst::test_util::assert_compiles(&content);
+
+    let run = Command::new(env!("CARGO_BIN_EXE_plotters-typst"))
+        .arg(&input)
+        .args(["--y", "mean"])
+        .output()
+        .unwrap();
+    assert!(!run.status.success());
+    assert!(String::from_utf8(run.stderr)
+        .unwrap()
+        .starts_with("plotters-typst: no column named `mean`"));
+}